@@ -0,0 +1,74 @@
+// Placement of firmware, kernel and initrd in RAM, and the reset vector ROM
+// that hands over to them the way QEMU's virt board does.
+
+/// Kernels following firmware start at the next multiple of this, which is
+/// where OpenSBI's generic fw_jump expects an rv32 kernel.
+pub const KERNEL_ALIGN: u32 = 4 << 20;
+/// The initrd goes this far above the kernel, or half the size of RAM above
+/// it if that is less, so that the kernel does not clobber it while unpacking.
+const INITRD_OFFSET: u32 = 128 << 20;
+const INITRD_ALIGN: u32 = 0x1000;
+
+/// `fw_dynamic_info` as defined by OpenSBI, version 2.
+const FW_DYNAMIC_INFO_MAGIC: u32 = 0x4942_534f;
+const FW_DYNAMIC_INFO_VERSION: u32 = 2;
+/// The kernel runs in S-mode.
+const FW_DYNAMIC_INFO_NEXT_MODE_S: u32 = 1;
+/// Let the firmware pick the boot hart.
+const FW_DYNAMIC_INFO_ANY_HART: u32 = u32::MAX;
+
+/// A range of RAM taken by one of the boot images or the device tree.
+pub struct Region{
+    pub name: &'static str,
+    pub start: u32,
+    pub end: u32
+}
+
+/// Add `region` to `regions`, failing if it overlaps any of them.
+pub fn claim(regions:&mut Vec<Region>,region:Region) -> Result<(),String>{
+    if let Some(other) = regions.iter().find(|r| r.start < region.end && region.start < r.end){
+        return Err(format!("the {} at {:#x}..{:#x} overlaps the {} at {:#x}..{:#x}",
+            region.name,region.start,region.end,other.name,other.start,other.end));
+    }
+    regions.push(region);
+    Ok(())
+}
+
+/// Round `addr` up to a multiple of the power of two `align`.
+pub fn align_up(addr:u32,align:u32) -> Option<u32>{
+    Some(addr.checked_add(align - 1)? & !(align - 1))
+}
+
+/// Where the initrd goes for a kernel at `kernel` in `ram_size` bytes of RAM.
+pub fn initrd_addr(kernel:u32,ram_size:u32) -> Option<u32>{
+    align_up(kernel.checked_add(INITRD_OFFSET.min(ram_size / 2))?,INITRD_ALIGN)
+}
+
+/// Contents of the reset ROM. Every hart enters it with nothing set up and
+/// leaves for `start` with
+///
+///  * a0 = its hart id
+///  * a1 = `fdt`, the device tree
+///  * a2 = the `fw_dynamic_info` naming `next`, the kernel, for OpenSBI's fw_dynamic
+pub fn reset_vector(start:u32,fdt:u32,next:u32) -> Vec<u8>{
+    let words = [
+        0x0000_0297, // auipc t0, 0
+        0x0282_8613, // addi  a2, t0, 40
+        0xf140_2573, // csrr  a0, mhartid
+        0x0202_a583, // lw    a1, 32(t0)
+        0x0182_a283, // lw    t0, 24(t0)
+        0x0002_8067, // jr    t0
+        start,
+        0,
+        fdt,
+        0,
+        // fw_dynamic_info, at offset 40
+        FW_DYNAMIC_INFO_MAGIC,
+        FW_DYNAMIC_INFO_VERSION,
+        next,
+        FW_DYNAMIC_INFO_NEXT_MODE_S,
+        0,
+        FW_DYNAMIC_INFO_ANY_HART
+    ];
+    words.iter().flat_map(|w| w.to_le_bytes()).collect()
+}
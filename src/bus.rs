@@ -1,29 +1,254 @@
-use crate::dram::Dram;
+use std::fs::read;
+use std::io;
+use crate::clint::Clint;
+use crate::device::Device;
+use crate::memory::Memory;
+use crate::elf::Elf;
 use crate::exceptions::Exception;
+use crate::plic::Plic;
 use crate::param::*;
+
+/// Size in bytes of the naturally aligned block an LR.W reserves.
+const RESERVATION_GRANULE:u32 = 4;
+
+/// What a region of the address map is routed to. The harts talk to the
+/// CLINT and the PLIC directly, so they stay typed fields of `Bus`.
+enum Target{
+    Memory(Memory),
+    Clint,
+    Plic,
+    Device{device:Box<dyn Device>,irq:Option<u32>}
+}
+
+/// What a region is, as seen by `Bus::mappings`.
+pub enum Mapping<'a>{
+    Ram,
+    Rom,
+    Clint,
+    Plic,
+    Device(&'a dyn Device,Option<u32>)
+}
+
+struct Region{
+    name: String,
+    base: u32,
+    size: u32,
+    target: Target
+}
+
 pub struct Bus{
-    pub dram: Dram
+    pub clint: Clint,
+    pub plic: Plic,
+    /// Sorted by base address and never overlapping.
+    regions: Vec<Region>,
+    /// Granule reserved by each hart's last LR.W, if any.
+    reservations: Vec<Option<u32>>
 }
 
 impl Bus{
-    pub fn new()->Self{
-        Self{dram:Dram::new()}
+    /// An empty address map whose CLINT and PLIC serve `harts` harts.
+    pub fn new(harts:usize)->Self{
+        Self{clint:Clint::new(harts),plic:Plic::new(harts),regions:Vec::new(),reservations:vec![None;harts]}
+    }
+    /// Insert a region, keeping the map sorted and rejecting overlaps.
+    fn map(&mut self,name:&str,base:u32,size:u32,target:Target) -> Result<(),String>{
+        if size == 0 || base.checked_add(size - 1).is_none(){
+            return Err(format!("{} at {:#x} with size {:#x} does not fit in the address space",name,base,size));
+        }
+        let i = self.regions.partition_point(|r| r.base < base);
+        let neighbours = self.regions[i.saturating_sub(1)..].iter().take(2);
+        for r in neighbours{
+            let overlaps = if r.base <= base {base - r.base < r.size} else {r.base - base < size};
+            if overlaps{
+                return Err(format!(
+                    "{} at {:#x}..{:#x} overlaps {} at {:#x}..{:#x}",
+                    name,base,base as u64 + size as u64,r.name,r.base,r.base as u64 + r.size as u64
+                ));
+            }
+        }
+        self.regions.insert(i,Region{name:name.to_string(),base,size,target});
+        Ok(())
+    }
+    /// Map a RAM or ROM at `base`.
+    pub fn add_memory(&mut self,name:&str,base:u32,memory:Memory) -> Result<(),String>{
+        self.map(name,base,memory.size(),Target::Memory(memory))
+    }
+    pub fn map_clint(&mut self,name:&str,base:u32,size:u32) -> Result<(),String>{
+        self.map(name,base,size,Target::Clint)
+    }
+    pub fn map_plic(&mut self,name:&str,base:u32,size:u32) -> Result<(),String>{
+        self.map(name,base,size,Target::Plic)
+    }
+    /// Map `device` at `base..base + size`, with its interrupt line wired to
+    /// PLIC source `irq` if given.
+    pub fn attach(&mut self,name:&str,base:u32,size:u32,irq:Option<u32>,device:Box<dyn Device>) -> Result<(),String>{
+        if let Some(irq) = irq{
+            if irq == 0 || irq >= PLIC_SOURCES{
+                return Err(format!("{} uses interrupt {}, but the PLIC has sources 1..{}",name,irq,PLIC_SOURCES));
+            }
+        }
+        self.map(name,base,size,Target::Device{device,irq})
+    }
+    /// The region containing `addr`.
+    fn find(&self,addr:u32) -> Option<usize>{
+        let i = self.regions.partition_point(|r| r.base <= addr).checked_sub(1)?;
+        if addr - self.regions[i].base < self.regions[i].size {Some(i)} else {None}
+    }
+    /// Name, base, size and kind of every region, in address order.
+    pub fn mappings(&self) -> impl Iterator<Item = (&str,u32,u32,Mapping<'_>)>{
+        self.regions.iter().map(|r| {
+            let mapping = match &r.target{
+                Target::Memory(memory) if memory.is_read_only() => Mapping::Rom,
+                Target::Memory(_) => Mapping::Ram,
+                Target::Clint => Mapping::Clint,
+                Target::Plic => Mapping::Plic,
+                Target::Device{device,irq} => Mapping::Device(device.as_ref(),*irq)
+            };
+            (r.name.as_str(),r.base,r.size,mapping)
+        })
+    }
+    /// Copy `bytes` to the RAM or ROM at `addr` and zero-fill the rest of a
+    /// `len` byte region.
+    pub fn write_bytes(&mut self,addr:u32,bytes:&[u8],len:u32) -> io::Result<()>{
+        let region = self.find(addr).map(|i| &mut self.regions[i]);
+        match region{
+            Some(Region{base,target:Target::Memory(memory),..}) if memory.size() - (addr - *base) >= len => {
+                memory.write_bytes(addr - *base,bytes,len);
+                Ok(())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} bytes at {:#x} do not fit in memory",len,addr)
+            ))
+        }
     }
-    pub fn load_binary(&mut self,filename:&str) -> Result<(),Exception>{
-        self.dram.load_instruction(filename)
+    /// Copy a flat binary image to `addr`.
+    pub fn load_binary(&mut self,filename:&str,addr:u32) -> io::Result<()>{
+        let code = read(filename)?;
+        self.write_bytes(addr,&code,code.len() as u32)
     }
-    pub fn load(&self,addr:u32,size:u32) -> Result<u32,Exception>{
-        match addr{
-            DRAM_BASE..=DRAM_END => self.dram.load(addr,size),
-            _ => Err(Exception::LoadAccessFault(addr))
+    /// Place every loadable segment of `elf` at its physical address.
+    pub fn load_elf(&mut self,elf:&Elf) -> io::Result<()>{
+        for seg in &elf.segments{
+            self.write_bytes(seg.paddr,&seg.data,seg.mem_size)?;
         }
+        Ok(())
+    }
+    pub fn load(&mut self,addr:u32,size:u32) -> Result<u32,Exception>{
+        let fault = Exception::LoadAccessFault(addr);
+        let i = self.find(addr).ok_or(fault)?;
+        let region = &mut self.regions[i];
+        let offset = addr - region.base;
+        match &mut region.target{
+            Target::Memory(memory) => memory.load(offset,size),
+            Target::Clint => self.clint.load(offset,size),
+            Target::Plic => self.plic.load(offset,size),
+            Target::Device{device,..} => device.load(offset,size)
+        }.map_err(|_| fault)
     }
     pub fn store(&mut self,addr:u32,size:u32,value:u32) -> Result<(),Exception>{
-        match addr{
-            DRAM_BASE..=DRAM_END => self.dram.store(addr,size,value),
-            _ => Err(Exception::LoadAccessFault(addr))
+        let fault = Exception::StoreAMOAccessFault(addr);
+        let i = self.find(addr).ok_or(fault)?;
+        // a store to a reserved granule breaks that hart's LR/SC pair; a
+        // misaligned store may touch the next granule as well
+        let first = addr & !(RESERVATION_GRANULE - 1);
+        let last = addr.wrapping_add(size / 8 - 1) & !(RESERVATION_GRANULE - 1);
+        for reservation in &mut self.reservations{
+            if reservation.is_some_and(|granule| granule == first || granule == last){
+                *reservation = None;
+            }
+        }
+        let region = &mut self.regions[i];
+        let offset = addr - region.base;
+        match &mut region.target{
+            Target::Memory(memory) => memory.store(offset,size,value),
+            Target::Clint => self.clint.store(offset,size,value),
+            Target::Plic => self.plic.store(offset,size,value),
+            Target::Device{device,..} => device.store(offset,size,value)
+        }.map_err(|_| fault)
+    }
+    /// Reserve the granule holding `addr` for `hart`.
+    pub fn reserve(&mut self,hart:usize,addr:u32){
+        self.reservations[hart] = Some(addr & !(RESERVATION_GRANULE - 1));
+    }
+    /// Drop `hart`'s reservation, returning whether it covered `addr`.
+    pub fn take_reservation(&mut self,hart:usize,addr:u32) -> bool{
+        self.reservations[hart].take() == Some(addr & !(RESERVATION_GRANULE - 1))
+    }
+    pub fn clear_reservation(&mut self,hart:usize){
+        self.reservations[hart] = None;
+    }
+    /// Advance the devices by one instruction and sample their interrupt lines.
+    pub fn tick(&mut self){
+        self.clint.tick();
+        for region in &mut self.regions{
+            if let Target::Device{device,irq} = &mut region.target{
+                device.tick();
+                if let Some(irq) = *irq{
+                    self.plic.set_level(irq,device.is_interrupting());
+                }
+            }
+        }
+    }
+    pub fn reset(&mut self){
+        self.clint.reset();
+        self.plic.reset();
+        self.reservations.fill(None);
+        for region in &mut self.regions{
+            match &mut region.target{
+                Target::Memory(memory) => memory.reset(),
+                Target::Device{device,..} => device.reset(),
+                _ => ()
+            }
         }
     }
-
 }
 
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// Two harts sharing a page of RAM at DRAM_BASE.
+    fn bus() -> Bus{
+        let mut bus = Bus::new(2);
+        bus.add_memory("ram",DRAM_BASE,Memory::new(0x1000,false)).unwrap();
+        bus
+    }
+
+    #[test]
+    fn a_reservation_holds_until_it_is_taken(){
+        let mut bus = bus();
+        bus.reserve(0,DRAM_BASE + 0x10);
+        // any address in the granule matches, and taking it ends it
+        assert!(bus.take_reservation(0,DRAM_BASE + 0x12));
+        assert!(!bus.take_reservation(0,DRAM_BASE + 0x10));
+        bus.reserve(0,DRAM_BASE + 0x10);
+        assert!(!bus.take_reservation(0,DRAM_BASE + 0x14));
+        assert!(!bus.take_reservation(1,DRAM_BASE + 0x10));
+    }
+
+    #[test]
+    fn stores_to_a_reserved_granule_break_the_reservation(){
+        let mut bus = bus();
+        bus.reserve(0,DRAM_BASE + 0x10);
+        bus.reserve(1,DRAM_BASE + 0x20);
+        bus.store(DRAM_BASE + 0x13,8,0).unwrap();
+        assert!(!bus.take_reservation(0,DRAM_BASE + 0x10));
+        // neighbouring granules are left alone
+        bus.reserve(0,DRAM_BASE + 0x10);
+        bus.store(DRAM_BASE + 0x14,32,0).unwrap();
+        bus.store(DRAM_BASE + 0x0c,32,0).unwrap();
+        assert!(bus.take_reservation(0,DRAM_BASE + 0x10));
+        assert!(bus.take_reservation(1,DRAM_BASE + 0x20));
+    }
+
+    #[test]
+    fn misaligned_stores_break_reservations_on_either_granule(){
+        let mut bus = bus();
+        for (addr,size) in [(DRAM_BASE + 0x0f,16),(DRAM_BASE + 0x0e,32),(DRAM_BASE + 0x13,16),(DRAM_BASE + 0x11,32)]{
+            bus.reserve(0,DRAM_BASE + 0x10);
+            bus.store(addr,size,0).unwrap();
+            assert!(!bus.take_reservation(0,DRAM_BASE + 0x10),"{:#x}",addr);
+        }
+    }
+}
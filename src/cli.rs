@@ -0,0 +1,222 @@
+use crate::clint::Timebase;
+use crate::param::*;
+use crate::trace::{ALL_MODES,parse_modes,parse_outputs};
+
+pub const USAGE: &str = "\
+usage: remu [options] [<image>]
+
+<image> is either an ELF32 executable, whose segments are placed at their
+physical addresses, or a flat binary copied to --load-addr. It is loaded
+after the images of the machine description, if any.
+
+Every hart starts with its hart id in a0 and the address of a device tree
+describing the machine in a1. The tree sits at the top of the first RAM.
+
+With --bios or --kernel, the harts start in a reset vector ROM at 0x1000
+that jumps to the firmware, loaded at the start of RAM, or to the kernel
+if there is no firmware. OpenSBI's fw_jump and fw_dynamic both find the
+kernel, so that
+
+    remu --bios fw_jump.bin --kernel Image --initrd rootfs.cpio
+
+boots Linux.
+
+options:
+    --machine <file>     build the machine from a TOML description instead of
+                         the default one-hart board
+    --load-addr <addr>   where a flat binary is copied to (default 0x80000000)
+    --dram-size <size>   DRAM size of the default board in bytes, K/M/G suffixes
+                         allowed (default 512M)
+    --entry <addr>       initial pc (default: ELF entry point or load address)
+    --bios <file>        firmware, ELF or flat binary, run from the reset ROM
+    --kernel <file>      kernel booted by the firmware, or entered from the
+                         reset ROM without --bios
+    --kernel-addr <addr> where a flat kernel is copied to (default: the next
+                         4 MiB boundary after the firmware, or the start of
+                         RAM without --bios)
+    --initrd <file>      initial ramdisk, placed above the kernel and passed
+                         in /chosen of the device tree
+    --append <args>      kernel command line, put in /chosen/bootargs of the
+                         generated device tree
+    --dtb <file>         pass this device tree blob to the harts instead of
+                         generating one from the machine
+    --dump-dtb <file>    write the device tree blob to <file> and exit
+    --timer <source>     what advances the CLINT's mtime: 'insns', one tick per
+                         instruction (default), or 'wall', 10 MHz of host time
+    --no-stdin           do not feed stdin to the UART, so that the guest never
+                         waits on or consumes the host's input
+    --trace <outputs>    trace what the harts do, as a comma-separated list of:
+                           insns    disassembled instructions
+                           regs     register writes
+                           mem      loads and stores
+                           traps    exceptions and interrupts
+                           commits  Spike's commit log
+                         or 'off' (default)
+    --trace-file <file>  write the trace to <file> instead of stderr
+    --trace-pc <start>:<end>
+                         only trace instructions at start <= pc < end
+    --trace-mode <modes> only trace instructions run in these privilege
+                         modes, given as letters, e.g. 'su' (default 'msu')
+    --signature <file>   when the run ends, write the memory from the image's
+                         begin_signature symbol up to end_signature to <file>,
+                         one 32-bit word per line in hex, as riscv-arch-test
+                         expects
+    --stats              print each hart's TLB hits and misses to stderr when
+                         the run ends
+    --max-insns <n>      stop successfully after every hart executed <n> instructions
+    -h, --help           print this message
+
+exit status:
+    0        --max-insns instructions were executed, or the guest exited
+             with code 0 through the HTIF tohost word
+    n        the guest exited with code n (at most 255) through tohost
+    1        the machine description or an image could not be loaded, or
+             the signature could not be written
+    2        bad command line
+    64 + n   the hart stopped on an exception with cause n that had no
+             handler: its trap vector was 0 or could not be fetched";
+
+pub const EXIT_LOAD_ERROR: u8 = 1;
+pub const EXIT_USAGE: u8 = 2;
+pub const EXIT_EXCEPTION_BASE: u8 = 64;
+
+pub struct Options{
+    pub image: Option<String>,
+    pub machine: Option<String>,
+    pub load_addr: u32,
+    pub dram_size: Option<u32>,
+    pub entry: Option<u32>,
+    pub bios: Option<String>,
+    pub kernel: Option<String>,
+    pub kernel_addr: Option<u32>,
+    pub initrd: Option<String>,
+    pub bootargs: Option<String>,
+    pub dtb: Option<String>,
+    pub dump_dtb: Option<String>,
+    pub signature: Option<String>,
+    pub timebase: Timebase,
+    pub no_stdin: bool,
+    pub trace: u32,
+    pub trace_file: Option<String>,
+    pub trace_pc: Option<(u32,u32)>,
+    pub trace_modes: u32,
+    pub stats: bool,
+    pub max_instructions: Option<u64>,
+    pub help: bool
+}
+
+impl Options{
+    pub fn parse<I:Iterator<Item = String>>(mut args:I) -> Result<Self,String>{
+        let mut opts = Self{
+            image: None,
+            machine: None,
+            load_addr: DRAM_BASE,
+            dram_size: None,
+            entry: None,
+            bios: None,
+            kernel: None,
+            kernel_addr: None,
+            initrd: None,
+            bootargs: None,
+            dtb: None,
+            dump_dtb: None,
+            signature: None,
+            timebase: Timebase::Instructions,
+            no_stdin: false,
+            trace: 0,
+            trace_file: None,
+            trace_pc: None,
+            trace_modes: ALL_MODES,
+            stats: false,
+            max_instructions: None,
+            help: false
+        };
+        while let Some(arg) = args.next(){
+            let mut value = |name:&str| args.next().ok_or(format!("{} expects a value",name));
+            match arg.as_str(){
+                "-h" | "--help" => opts.help = true,
+                "--trace" => opts.trace = parse_outputs(&value(&arg)?)?,
+                "--trace-file" => opts.trace_file = Some(value(&arg)?),
+                "--trace-pc" => opts.trace_pc = Some(parse_range(&value(&arg)?)?),
+                "--trace-mode" => opts.trace_modes = parse_modes(&value(&arg)?)?,
+                "--stats" => opts.stats = true,
+                "--no-stdin" => opts.no_stdin = true,
+                "--load-addr" => opts.load_addr = parse_u32(&value(&arg)?)?,
+                "--machine" => opts.machine = Some(value(&arg)?),
+                "--dram-size" => opts.dram_size = Some(parse_size(&value(&arg)?)?),
+                "--entry" => opts.entry = Some(parse_u32(&value(&arg)?)?),
+                "--bios" => opts.bios = Some(value(&arg)?),
+                "--kernel" => opts.kernel = Some(value(&arg)?),
+                "--kernel-addr" => opts.kernel_addr = Some(parse_u32(&value(&arg)?)?),
+                "--initrd" => opts.initrd = Some(value(&arg)?),
+                "--append" => opts.bootargs = Some(value(&arg)?),
+                "--dtb" => opts.dtb = Some(value(&arg)?),
+                "--dump-dtb" => opts.dump_dtb = Some(value(&arg)?),
+                "--signature" => opts.signature = Some(value(&arg)?),
+                "--timer" => {
+                    opts.timebase = match value(&arg)?.as_str(){
+                        "insns" => Timebase::Instructions,
+                        "wall" => Timebase::WallClock,
+                        other => return Err(format!("unknown timer source '{}'",other))
+                    };
+                }
+                "--max-insns" => {
+                    let n = value(&arg)?;
+                    opts.max_instructions = Some(n.parse().map_err(|_| format!("invalid instruction count '{}'",n))?);
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option '{}'",arg)),
+                _ if opts.image.is_none() => opts.image = Some(arg),
+                _ => return Err(format!("unexpected argument '{}'",arg))
+            }
+        }
+        if opts.help{
+            return Ok(opts);
+        }
+        if opts.machine.is_some(){
+            if opts.dram_size.is_some(){
+                return Err("--dram-size only applies to the default board, not to --machine".to_string());
+            }
+            return Ok(opts);
+        }
+        if opts.image.is_none() && opts.bios.is_none() && opts.kernel.is_none() && opts.dump_dtb.is_none(){
+            return Err("no image given".to_string());
+        }
+        let size = *opts.dram_size.get_or_insert(DRAM_SIZE);
+        if size == 0 || DRAM_BASE.checked_add(size).is_none(){
+            return Err(format!("DRAM size {:#x} does not fit above {:#x}",size,DRAM_BASE));
+        }
+        Ok(opts)
+    }
+}
+
+/// Parse a decimal or `0x`-prefixed hexadecimal number.
+pub fn parse_u32(s:&str) -> Result<u32,String>{
+    let parsed = match s.strip_prefix("0x").or(s.strip_prefix("0X")){
+        Some(hex) => u32::from_str_radix(&hex.replace('_',""),16),
+        None => s.replace('_',"").parse()
+    };
+    parsed.map_err(|_| format!("invalid number '{}'",s))
+}
+
+/// Parse a `<start>:<end>` address range.
+fn parse_range(s:&str) -> Result<(u32,u32),String>{
+    let (start,end) = s.split_once(':').ok_or(format!("invalid range '{}', expected <start>:<end>",s))?;
+    let (start,end) = (parse_u32(start)?,parse_u32(end)?);
+    if start >= end{
+        return Err(format!("empty range '{}'",s));
+    }
+    Ok((start,end))
+}
+
+/// Parse a byte count with an optional K, M or G suffix.
+pub fn parse_size(s:&str) -> Result<u32,String>{
+    let (digits,shift) = match s.char_indices().last(){
+        Some((i,'k' | 'K')) => (&s[..i],10),
+        Some((i,'m' | 'M')) => (&s[..i],20),
+        Some((i,'g' | 'G')) => (&s[..i],30),
+        _ => (s,0)
+    };
+    parse_u32(digits)?
+        .checked_mul(1 << shift)
+        .ok_or(format!("size '{}' is too large",s))
+}
@@ -0,0 +1,248 @@
+use std::time::{Duration,Instant};
+use crate::device::Device;
+use crate::exceptions::Exception;
+use crate::param::*;
+
+// register offsets (SiFive layout)
+const MSIP: u32 = 0x0;
+const MTIMECMP: u32 = 0x4000;
+const MTIME: u32 = 0xbff8;
+const MTIMEH: u32 = 0xbffc;
+
+/// Harts whose mtimecmp fits below mtime; their msip registers end well
+/// below mtimecmp.
+pub const MAX_HARTS: usize = ((MTIME - MTIMECMP) / 8) as usize;
+
+/// How often the wall-clock timebase re-reads the host clock for `mtip`.
+const WALL_CLOCK_SAMPLE_PERIOD: u32 = 256;
+
+/// What drives mtime forward.
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum Timebase{
+    /// One tick per retired instruction, so runs are reproducible.
+    Instructions,
+    /// TIMEBASE_FREQ ticks per second of host time.
+    WallClock
+}
+
+/// Core-local interruptor: machine software interrupts (msip) and the
+/// machine timer (mtime/mtimecmp) for every hart.
+pub struct Clint{
+    msip: Vec<bool>,
+    mtimecmp: Vec<u64>,
+    timebase: Timebase,
+    /// mtime at `epoch` for the wall clock, the current mtime otherwise
+    mtime: u64,
+    epoch: Instant,
+    /// mtime as of the last tick, used to drive mtip
+    now: u64,
+    ticks: u32
+}
+
+/// Read the 32-bit half of `value` selected by `offset`.
+fn half(value:u64,offset:u32) -> u32{
+    (value >> (8*(offset % 8))) as u32
+}
+
+/// Replace the 32-bit half of `value` selected by `offset`.
+fn set_half(value:u64,offset:u32,data:u32) -> u64{
+    let shift = 8*(offset % 8);
+    (value & !(0xffff_ffff << shift)) | ((data as u64) << shift)
+}
+
+impl Clint{
+    pub fn new(harts:usize) -> Self{
+        Self{
+            msip: vec![false;harts],
+            mtimecmp: vec![u64::MAX;harts],
+            timebase: Timebase::Instructions,
+            mtime: 0,
+            epoch: Instant::now(),
+            now: 0,
+            ticks: 0
+        }
+    }
+    pub fn set_timebase(&mut self,timebase:Timebase){
+        self.set_mtime(self.mtime());
+        self.timebase = timebase;
+    }
+    pub fn mtime(&self) -> u64{
+        match self.timebase{
+            Timebase::Instructions => self.mtime,
+            Timebase::WallClock => {
+                let elapsed = self.epoch.elapsed().as_nanos() * TIMEBASE_FREQ as u128 / 1_000_000_000;
+                self.mtime.wrapping_add(elapsed as u64)
+            }
+        }
+    }
+    pub fn timebase(&self) -> Timebase{
+        self.timebase
+    }
+    /// The earliest mtimecmp still ahead of mtime, if any hart has one.
+    pub fn next_deadline(&self) -> Option<u64>{
+        let now = self.mtime();
+        self.mtimecmp.iter().copied().filter(|&t| t > now && t != u64::MAX).min()
+    }
+    /// Host time until mtime reaches `deadline` on the wall clock.
+    pub fn time_until(&self,deadline:u64) -> Duration{
+        let ticks = deadline.saturating_sub(self.mtime());
+        Duration::from_nanos((ticks as u128 * 1_000_000_000 / TIMEBASE_FREQ as u128) as u64)
+    }
+    /// Advance the instruction timebase by `ticks` at once, as if that many
+    /// instructions had run.
+    pub fn skip(&mut self,ticks:u64){
+        self.set_mtime(self.mtime.wrapping_add(ticks));
+    }
+    /// Re-read the host clock for mtip, as after sleeping.
+    pub fn sample(&mut self){
+        self.ticks = 0;
+        self.now = self.mtime();
+    }
+    fn set_mtime(&mut self,value:u64){
+        self.mtime = value;
+        self.epoch = Instant::now();
+        self.now = value;
+    }
+    /// Level of hart `hart`'s machine software interrupt line.
+    pub fn msip(&self,hart:usize) -> bool{
+        self.msip[hart]
+    }
+    /// Level of hart `hart`'s machine timer interrupt line.
+    pub fn mtip(&self,hart:usize) -> bool{
+        self.now >= self.mtimecmp[hart]
+    }
+}
+
+impl Device for Clint{
+    fn load(&mut self,offset:u32,size:u32) -> Result<u32,Exception>{
+        let harts = self.msip.len() as u32;
+        if size != 32 || !offset.is_multiple_of(4){
+            return Err(Exception::LoadAccessFault(offset));
+        }
+        match offset{
+            _ if offset < MSIP + 4*harts => Ok(self.msip[(offset/4) as usize] as u32),
+            _ if (MTIMECMP..MTIMECMP + 8*harts).contains(&offset) => {
+                Ok(half(self.mtimecmp[((offset - MTIMECMP)/8) as usize],offset))
+            }
+            MTIME | MTIMEH => Ok(half(self.mtime(),offset)),
+            _ => Err(Exception::LoadAccessFault(offset))
+        }
+    }
+    fn store(&mut self,offset:u32,size:u32,value:u32) -> Result<(),Exception>{
+        let harts = self.msip.len() as u32;
+        if size != 32 || !offset.is_multiple_of(4){
+            return Err(Exception::StoreAMOAccessFault(offset));
+        }
+        match offset{
+            _ if offset < MSIP + 4*harts => self.msip[(offset/4) as usize] = value & 1 == 1,
+            _ if (MTIMECMP..MTIMECMP + 8*harts).contains(&offset) => {
+                let hart = ((offset - MTIMECMP)/8) as usize;
+                self.mtimecmp[hart] = set_half(self.mtimecmp[hart],offset,value);
+            }
+            MTIME | MTIMEH => self.set_mtime(set_half(self.mtime(),offset,value)),
+            _ => return Err(Exception::StoreAMOAccessFault(offset))
+        }
+        Ok(())
+    }
+    fn reset(&mut self){
+        *self = Self{timebase:self.timebase,..Self::new(self.msip.len())};
+    }
+    fn tick(&mut self){
+        match self.timebase{
+            Timebase::Instructions => {
+                self.mtime = self.mtime.wrapping_add(1);
+                self.now = self.mtime;
+            }
+            Timebase::WallClock => {
+                self.ticks += 1;
+                if self.ticks == WALL_CLOCK_SAMPLE_PERIOD{
+                    self.ticks = 0;
+                    self.now = self.mtime();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn mtime_and_mtimecmp_are_read_and_written_in_halves(){
+        let mut clint = Clint::new(2);
+        clint.store(MTIME,32,0xdead_beef).unwrap();
+        clint.store(MTIMEH,32,0x1234).unwrap();
+        assert_eq!(clint.mtime(),0x1234_dead_beef);
+        assert_eq!(clint.load(MTIME,32).unwrap(),0xdead_beef);
+        assert_eq!(clint.load(MTIMEH,32).unwrap(),0x1234);
+        assert_eq!(clint.load(MTIMECMP + 8,32).unwrap(),0xffff_ffff);
+        clint.store(MTIMECMP + 8,32,0x100).unwrap();
+        clint.store(MTIMECMP + 12,32,0).unwrap();
+        assert_eq!(clint.load(MTIMECMP + 8,32).unwrap(),0x100);
+        assert_eq!(clint.load(MTIMECMP + 12,32).unwrap(),0);
+        assert_eq!(clint.load(MTIMECMP,32).unwrap(),0xffff_ffff,"hart 0 is untouched");
+    }
+
+    #[test]
+    fn mtime_counts_instructions(){
+        let mut clint = Clint::new(1);
+        for _ in 0..3{
+            clint.tick();
+        }
+        assert_eq!(clint.load(MTIME,32).unwrap(),3);
+        clint.skip(10);
+        assert_eq!(clint.mtime(),13);
+    }
+
+    #[test]
+    fn mtip_follows_mtimecmp(){
+        let mut clint = Clint::new(2);
+        assert!(!clint.mtip(0));
+        clint.store(MTIMECMP,32,2).unwrap();
+        clint.store(MTIMECMP + 4,32,0).unwrap();
+        clint.tick();
+        assert!(!clint.mtip(0));
+        clint.tick();
+        assert!(clint.mtip(0));
+        assert!(!clint.mtip(1));
+        assert_eq!(clint.next_deadline(),None);
+        clint.store(MTIMECMP,32,5).unwrap();
+        assert!(!clint.mtip(0),"raising mtimecmp clears mtip");
+        assert_eq!(clint.next_deadline(),Some(5));
+        clint.store(MTIME,32,5).unwrap();
+        assert!(clint.mtip(0),"writing mtime updates mtip");
+    }
+
+    #[test]
+    fn msip_drives_the_software_interrupt_line(){
+        let mut clint = Clint::new(2);
+        clint.store(MSIP + 4,32,0xffff_ffff).unwrap();
+        assert!(!clint.msip(0));
+        assert!(clint.msip(1));
+        assert_eq!(clint.load(MSIP + 4,32).unwrap(),1,"only bit 0 is implemented");
+        clint.store(MSIP + 4,32,2).unwrap();
+        assert!(!clint.msip(1));
+    }
+
+    #[test]
+    fn bad_accesses_fault(){
+        let mut clint = Clint::new(1);
+        assert!(matches!(clint.load(MTIME,16),Err(Exception::LoadAccessFault(_))));
+        assert!(matches!(clint.load(MTIME + 2,32),Err(Exception::LoadAccessFault(_))));
+        assert!(matches!(clint.load(MSIP + 4,32),Err(Exception::LoadAccessFault(_))),"no hart 1");
+        assert!(matches!(clint.store(MTIMECMP + 8,32,0),Err(Exception::StoreAMOAccessFault(_))));
+    }
+
+    #[test]
+    fn reset_keeps_the_timebase(){
+        let mut clint = Clint::new(1);
+        clint.set_timebase(Timebase::WallClock);
+        clint.store(MSIP,32,1).unwrap();
+        clint.store(MTIMECMP,32,0).unwrap();
+        clint.reset();
+        assert_eq!(clint.timebase(),Timebase::WallClock);
+        assert!(!clint.msip(0));
+        assert_eq!(clint.load(MTIMECMP,32).unwrap(),0xffff_ffff);
+    }
+}
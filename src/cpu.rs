@@ -1,263 +1,571 @@
+use std::cell::RefCell;
+use std::rc::Rc;
 use crate::bus::Bus;
 use crate::exceptions::*;
 use crate::param::*;
 use crate::csr::*;
 use crate::interrupt::*;
+use crate::rvc;
+use crate::tlb::{Tlb,TlbEntry};
+use crate::pmp::{Pmp,PMP_R,PMP_W,PMP_X};
+use crate::fpu::{Format,F32,F64,Rounding};
+use crate::trace::{Access,Record,Trap,Written,destination};
 
 pub const MACHINE:u32 = 3;
 pub const SUPERVISOR:u32 = 1;
 pub const USER:u32 = 0;
 
+/// Upper half of an f register holding a properly NaN-boxed single.
+const NAN_BOX:u64 = 0xffff_ffff_0000_0000;
+
+/// The format selected by the fmt field of a floating-point instruction.
+fn float_format(fmt:u32) -> Option<Format>{
+    match fmt{
+        0b00 => Some(F32),
+        0b01 => Some(F64),
+        _ => None
+    }
+}
+
+#[derive(Clone,Copy)]
 enum AccessType{
     Instruction,
     Load,
     Store
 }
 
+impl AccessType{
+    fn page_fault(self,addr:u32) -> Exception{
+        match self{
+            AccessType::Instruction => Exception::InstructionPageFault(addr),
+            AccessType::Load => Exception::LoadPageFault(addr),
+            AccessType::Store => Exception::StoreAMOPageFault(addr)
+        }
+    }
+    /// The PMP permission the access needs.
+    fn pmp_permission(self) -> u8{
+        match self{
+            AccessType::Instruction => PMP_X,
+            AccessType::Load => PMP_R,
+            AccessType::Store => PMP_W
+        }
+    }
+    fn access_fault(self,addr:u32) -> Exception{
+        match self{
+            AccessType::Instruction => Exception::InstructionAccessFault(addr),
+            AccessType::Load => Exception::LoadAccessFault(addr),
+            AccessType::Store => Exception::StoreAMOAccessFault(addr)
+        }
+    }
+}
+
 pub struct Cpu{
     pc: u32,
+    /// Length in bytes of the instruction being executed (2 or 4).
+    inst_len: u32,
     regs: [u32;32],
-    f_regs: [f32;32],
-    bus: Bus,
+    /// Raw bit patterns of the floating-point registers; single-precision
+    /// values are NaN-boxed in the low half.
+    f_regs: [u64;32],
+    /// Shared with the other harts of the machine.
+    bus: Rc<RefCell<Bus>>,
     csr: Csr,
     mode: u32,
     enable_paging: bool,
-    page_table: u32
+    /// Physical address of the root page table; Sv32 addresses 34 bits.
+    page_table: u64,
+    /// Address space of satp, tagging the TLB entries.
+    asid: u32,
+    tlb: Tlb,
+    pmp: Pmp,
+    /// Stalled in WFI until an enabled interrupt is pending.
+    waiting: bool,
+    /// What the current step did, kept while tracing.
+    record: Option<Record>,
+    tracing: bool,
+    hart_id: usize
 }
 
 impl Cpu{
-    pub fn new() -> Self{
-        let mut regs = [0;32];
-        let mut f_regs = [0.0;32];
-        regs[2] = DRAM_END;
+    /// Hart `hart_id` implementing the extensions in `misa`.
+    pub fn new(hart_id:usize,misa:u32,bus:Rc<RefCell<Bus>>) -> Self{
+        let mut csr = Csr::new(misa);
+        csr.csrs[MHARTID] = hart_id as u32;
         Self{
             pc:DRAM_BASE,
-            regs,
-            f_regs,
-            bus:Bus::new(),
-            csr:Csr::new(),
+            inst_len:4,
+            regs:[0;32],
+            f_regs:[0;32],
+            bus,
+            csr,
             mode:MACHINE,
             enable_paging: false,
-            page_table: 0
+            page_table: 0,
+            asid: 0,
+            tlb: Tlb::new(),
+            pmp: Pmp::new(),
+            waiting: false,
+            record: None,
+            tracing: false,
+            hart_id
         }
     }
+    /// Return to the power-on state: M-mode at DRAM_BASE with every
+    /// register and CSR cleared, except misa and mhartid.
     pub fn reset(&mut self){
-        self.regs.iter_mut().for_each(|x| *x = 0);
-        self.pc = DRAM_BASE;
-        self.regs[2] = DRAM_END;
+        let misa = self.csr.csrs[MISA];
+        *self = Self{tracing:self.tracing,..Self::new(self.hart_id,misa,Rc::clone(&self.bus))};
     }
-    pub fn run(&mut self) -> Result<(),Exception>{
-        loop{
-            let instr = match self.fetch(){
-                Ok(instr) => instr,
-                Err(e) => {
-                    self.handle_exception(e);
-                    if e.is_fatal(){
-                        println!("{}",e);
-                        break Err(e);
-                    }
-                    continue;
-                }
-            };
-            match self.execute(instr){
-                Ok(new_pc) => {
-                    self.pc = new_pc;
-                },
-                Err(e) => {
-                    self.handle_exception(e);
-                    if e.is_fatal(){
-                        println!("{}",e);
-                        break Err(e);
-                    }
-                    continue;
-                }
+    pub fn pc(&self) -> u32{
+        self.pc
+    }
+    pub fn set_pc(&mut self,pc:u32){
+        self.pc = pc;
+    }
+    pub fn set_reg(&mut self,r:usize,value:u32){
+        self.regs[r] = value;
+    }
+    #[cfg(test)]
+    pub fn reg(&self,r:usize) -> u32{
+        self.regs[r]
+    }
+    /// Whether the hart is stalled in WFI with none of the interrupts it
+    /// enables in mie pending on its lines as they are now.
+    pub fn is_waiting(&mut self) -> bool{
+        if !self.waiting{
+            return false;
+        }
+        self.update_interrupt_lines();
+        self.csr.csrs[MIE] & self.csr.csrs[MIP] == 0
+    }
+    /// Count the `steps` a waiting hart was skipped for as cycles.
+    pub fn skip(&mut self,steps:u64){
+        self.csr.skip_cycles(steps);
+    }
+    /// Keep a record of what each step does.
+    pub fn set_tracing(&mut self,tracing:bool){
+        self.tracing = tracing;
+    }
+    /// What the last step did, while tracing.
+    pub fn take_record(&mut self) -> Option<Record>{
+        self.record.take()
+    }
+    /// Execute one instruction or take a trap, then take any pending
+    /// interrupt. An exception is fatal, and returned once its trap is set
+    /// up, if its trap vector is 0 or the handler there cannot be fetched.
+    /// A hart in WFI does nothing until an interrupt it enables in mie is
+    /// pending, even one that is globally disabled, and then takes it if
+    /// it can.
+    pub fn step(&mut self) -> Result<(),Exception>{
+        if self.tracing{
+            self.record = Some(Record::new(self.pc,self.mode));
+        }
+        self.update_interrupt_lines();
+        if self.waiting{
+            // time passes in WFI, but no instruction retires
+            self.csr.advance_counters(false);
+            if self.csr.csrs[MIE] & self.csr.csrs[MIP] == 0{
+                return Ok(());
+            }
+            self.waiting = false;
+            if let Some(i) = self.check_pending_interrupt(){
+                self.trap(i.code(),0);
             }
-            match self.check_pending_interrupt(){
-                Some(i) => self.handle_interrupt(i),
-                None => ()
+            return Ok(());
+        }
+        let result = self.fetch().and_then(|inst| {
+            if let Some(record) = &mut self.record{
+                record.inst = Some(inst);
+            }
+            self.execute(inst)
+        });
+        match result{
+            Ok(new_pc) => {
+                self.pc = new_pc;
+                self.trace_retired();
+            }
+            Err(e) => {
+                let pc = self.pc;
+                self.trap(e.code(),e.tval());
+                self.csr.advance_counters(false);
+                // without a handler, or with one that cannot be fetched, the
+                // trap would repeat forever
+                let fatal = self.pc == 0 || (e.is_fetch_fault() && self.pc == pc);
+                return if fatal {Err(e)} else {Ok(())};
             }
-            self.dump_registers();
+        }
+        if let Some(i) = self.check_pending_interrupt(){
+            self.trap(i.code(),0);
+        }
+        self.csr.advance_counters(true);
+        Ok(())
+    }
+    /// Read a CSR for `inst`, which is illegal unless the CSR exists and the
+    /// current privilege mode may access it, and also write it if `write`.
+    fn read_csr(&self,inst:u32,csr_addr:usize,write:bool) -> Result<u32,Exception>{
+        if !self.csr.is_accessible(csr_addr,self.mode,write){
+            return Err(Exception::IllegalInstruction(inst));
+        }
+        let mtime = || self.bus.borrow().clint.mtime();
+        match csr_addr{
+            TIME => Ok(mtime() as u32),
+            TIMEH => Ok((mtime() >> 32) as u32),
+            _ => self.csr.load(csr_addr).map_err(|_| Exception::IllegalInstruction(inst))
         }
     }
-    pub fn load_binary(&mut self,filename:&str) -> Result<(),Exception>{
-        self.bus.load_binary(filename)
+    /// The value csrrs and csrrc set or clear bits of, given the value `read`:
+    /// only the software-written SEIP takes part for mip.
+    fn modified_csr(&self,csr_addr:usize,read:u32) -> u32{
+        if csr_addr == MIP {self.csr.written_mip()} else {read}
     }
-    fn dump_registers(&self){
-        println!("pc: {:x}",self.pc);
-        self.regs
-        .iter()
-        .zip(0..)
-        .for_each(|(x,i)| if (i+1) % 4 == 0 {println!("x{:<2}: {:<9x}",i,x)} else {print!("x{:<2}: {:<9x}",i,x)});
-
-        self.f_regs
-        .iter()
-        .zip(0..)
-        .for_each(|(x,i)| if (i+1) % 4 == 0 {println!("f{:<2}: {:<9}",i,x)} else {print!("f{:<2}: {:<9}",i,x)});
-        println!("");
+    fn write_csr(&mut self,inst:u32,csr_addr:usize,value:u32) -> Result<(),Exception>{
+        self.csr.store(csr_addr,value).map_err(|_| Exception::IllegalInstruction(inst))?;
+        self.csr_written(csr_addr);
+        Ok(())
+    }
+    /// Refresh the state derived from the CSR just written.
+    fn csr_written(&mut self,csr_addr:usize){
+        if (PMPCFG0..=PMPCFG3).contains(&csr_addr) || (PMPADDR0..=PMPADDR15).contains(&csr_addr){
+            self.pmp.update(&self.csr);
+        }
+        self.updating_page(csr_addr);
     }
     fn updating_page(&mut self,csr_addr:usize) {
         if csr_addr != SATP{return;}
         let satp = self.csr.load(SATP).unwrap();
-        self.page_table = PAGE_SIZE*(satp & MASK_PPN);
+        self.page_table = (satp & MASK_PPN) as u64 * PAGE_SIZE as u64;
         self.enable_paging = ((satp & MASK_MODE) >> 31) == 1;
+        self.asid = (satp & MASK_ASID) >> 22;
+        self.tlb.flush_all();
 
     }
-    fn translate(&self,addr:u32,accesstype:AccessType) -> Result<u32,Exception>{
-        if !self.enable_paging{
-            Ok(addr)
+    /// Read a PTE, which PMP checks as an S-mode access. Physical addresses
+    /// beyond the 32-bit bus do not exist.
+    fn load_pte(&mut self,pa:u64) -> Option<u32>{
+        if !self.pmp.check(pa,4,PMP_R,SUPERVISOR){
+            return None;
+        }
+        let pa = u32::try_from(pa).ok()?;
+        self.bus.borrow_mut().load(pa,32).ok()
+    }
+    /// Privilege mode that loads and stores are performed in: MPP when
+    /// MPRV is set.
+    fn effective_mode(&self,access:AccessType) -> u32{
+        let mstatus = self.csr.csrs[MSTATUS];
+        match access{
+            AccessType::Load | AccessType::Store if mstatus & MASK_MPRV != 0 => (mstatus & MASK_MPP) >> 11,
+            _ => self.mode
+        }
+    }
+    /// Translate `addr` and check that PMP allows the `size` bit access
+    /// at the physical address.
+    fn physical(&mut self,addr:u32,size:u32,access:AccessType) -> Result<u32,Exception>{
+        let pa = self.translate(addr,access)?;
+        if self.pmp.check(pa as u64,(size / 8) as u64,access.pmp_permission(),self.effective_mode(access)){
+            Ok(pa)
         } else{
-            let mut base = self.load(self.page_table*PAGE_SIZE,32)?;
-            let mut have_processed = 0;
-            loop{
-                let offset = (addr & (0xffc00000_u32 >> have_processed)) >> (22 - have_processed);
-                let pte = self.load(base + offset*PTE_SIZE,32)?;
-                let (p_v,p_r,p_w,p_x) = (pte & 1,(pte&0b10)>>1,(pte&0b100)>>2,(pte&0b1000)>>3);
-                if p_v == 0 || (p_r == 0 && p_w == 1) {
-                    match accesstype {
-                        AccessType::Instruction => return Err(Exception::InstructionPageFault(addr)),
-                        AccessType::Load => return Err(Exception::LoadPageFault(addr)),
-                        AccessType::Store => return Err(Exception::StoreAMOPageFault(addr)),
-                    }
-                } else{
-                    //leaf page
-                    if p_r == 1 || p_x == 1{
-                        //privilege check
-                        // if
-                        // check if it's a superpage and if so ,check if aligned correctly
-                        if have_processed == 0 && (addr & (0xffc00000_u32 >> 10) != 0){
-                            match accesstype {
-                                AccessType::Instruction => return Err(Exception::InstructionPageFault(addr)),
-                                AccessType::Load => return Err(Exception::LoadPageFault(addr)),
-                                AccessType::Store => return Err(Exception::StoreAMOPageFault(addr)),
-                            }
-                        }
-                        let MASK_PA = (0xffc00000_u32 as i32 >> have_processed) as u32;
-                        let offset = addr | !MASK_PA;
-                        let pa = (pte & MASK_PA) | offset;
-                        return Ok(pa);
-                    } else{
-                        have_processed += 10;
-                        base = (pte & 0xfffff000) >> 12;
-                    }
-                }
+            Err(access.access_fault(addr))
+        }
+    }
+    /// Walk the page tables for `addr`, returning the leaf PTE, the leaf's
+    /// physical address and whether it maps a superpage.
+    fn walk(&mut self,addr:u32,access:AccessType) -> Result<TlbEntry,Exception>{
+        let vpn = [(addr >> 12) & 0x3ff,addr >> 22];
+        let mut table = self.page_table;
+        let mut level = 1;
+        loop{
+            let pte_addr = table + (vpn[level] * PTE_SIZE) as u64;
+            let pte = self.load_pte(pte_addr).ok_or(access.access_fault(addr))?;
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0){
+                return Err(access.page_fault(addr));
             }
-
+            if pte & (PTE_R | PTE_X) != 0{
+                return Ok(TlbEntry{vpn:addr >> 12,asid:self.asid,pte,pte_addr,superpage:level == 1});
+            }
+            // a pointer to the next level
+            if level == 0{
+                return Err(access.page_fault(addr));
+            }
+            level -= 1;
+            table = (pte >> 10) as u64 * PAGE_SIZE as u64;
         }
     }
-    fn fetch(&self) -> Result<u32,Exception>{
-        let addr = self.translate(self.pc,AccessType::Instruction)?;
-        self.bus.load(addr,32)
+    /// Translate `addr` with Sv32 if satp enables it for the effective
+    /// privilege mode, which is MPP for loads and stores when MPRV is set.
+    /// The leaf PTE's A bit, and D bit for stores, are set in memory rather
+    /// than raising a page fault for software to set them.
+    fn translate(&mut self,addr:u32,access:AccessType) -> Result<u32,Exception>{
+        let mstatus = self.csr.csrs[MSTATUS];
+        let mode = self.effective_mode(access);
+        if !self.enable_paging || mode == MACHINE{
+            return Ok(addr);
+        }
+        let dirty = if matches!(access,AccessType::Store) {PTE_A | PTE_D} else {PTE_A};
+        // an entry whose A or D bit still has to be set is walked again, so
+        // that the update starts from the PTE in memory
+        let entry = match self.tlb.lookup(addr >> 12,self.asid).filter(|e| e.pte & dirty == dirty){
+            Some(entry) => {
+                self.tlb.hits += 1;
+                entry
+            }
+            None => {
+                self.tlb.misses += 1;
+                self.csr.count_event(EVENT_TLB_MISS);
+                let entry = self.walk(addr,access)?;
+                self.tlb.insert(entry);
+                entry
+            }
+        };
+        let pte = entry.pte;
+        let permitted = match access{
+            AccessType::Instruction => pte & PTE_X != 0,
+            AccessType::Load => pte & PTE_R != 0 || (mstatus & MASK_MXR != 0 && pte & PTE_X != 0),
+            AccessType::Store => pte & PTE_W != 0
+        };
+        // S-mode may touch user pages only with SUM set, and never run them
+        let privileged = if pte & PTE_U != 0{
+            mode == USER || (mstatus & MASK_SUM != 0 && !matches!(access,AccessType::Instruction))
+        } else{
+            mode != USER
+        };
+        let ppn = pte >> 10;
+        // a superpage must be aligned to its size
+        let misaligned = entry.superpage && ppn & 0x3ff != 0;
+        if !permitted || !privileged || misaligned{
+            return Err(access.page_fault(addr));
+        }
+        if pte & dirty != dirty{
+            if !self.pmp.check(entry.pte_addr,4,PMP_W,SUPERVISOR){
+                return Err(access.access_fault(addr));
+            }
+            let pte_addr = u32::try_from(entry.pte_addr).map_err(|_| access.access_fault(addr))?;
+            self.bus.borrow_mut().store(pte_addr,32,pte | dirty).map_err(|_| access.access_fault(addr))?;
+            self.tlb.insert(TlbEntry{pte:pte | dirty,..entry});
+        }
+        let pa = if entry.superpage{
+            ((ppn >> 10) as u64) << 22 | (addr & 0x3f_ffff) as u64
+        } else{
+            (ppn as u64) << 12 | (addr & 0xfff) as u64
+        };
+        u32::try_from(pa).map_err(|_| access.access_fault(addr))
+    }
+    /// TLB hits and misses of this hart so far.
+    pub fn tlb_stats(&self) -> (u64,u64){
+        (self.tlb.hits,self.tlb.misses)
+    }
+    /// Fetch the instruction at pc, 16 bits first so that a compressed
+    /// instruction at the end of a page does not touch the next one.
+    fn fetch(&mut self) -> Result<u32,Exception>{
+        let addr = self.physical(self.pc,16,AccessType::Instruction)?;
+        let low = self.bus.borrow_mut().load(addr,16).map_err(|_| Exception::InstructionAccessFault(self.pc))?;
+        if rvc::is_compressed(low){
+            self.inst_len = 2;
+            return Ok(low);
+        }
+        let next = self.pc.wrapping_add(2);
+        let addr = self.physical(next,16,AccessType::Instruction)?;
+        let high = self.bus.borrow_mut().load(addr,16).map_err(|_| Exception::InstructionAccessFault(next))?;
+        self.inst_len = 4;
+        Ok(low | (high << 16))
+    }
+    fn read_memory(&mut self,addr:u32,size:u32) -> Result<u32,Exception>{
+        let pa = self.physical(addr,size,AccessType::Load)?;
+        self.bus.borrow_mut().load(pa,size).map_err(|_| Exception::LoadAccessFault(addr))
+    }
+    fn write_memory(&mut self,addr:u32,size:u32,value:u32) -> Result<(),Exception>{
+        let pa = self.physical(addr,size,AccessType::Store)?;
+        self.bus.borrow_mut().store(pa,size,value).map_err(|_| Exception::StoreAMOAccessFault(addr))
     }
-    fn load(&self,addr:u32,size:u32) -> Result<u32,Exception>{
-        let addr = self.translate(addr,AccessType::Load)?;
-        self.bus.load(addr,size)
+    fn load(&mut self,addr:u32,size:u32) -> Result<u32,Exception>{
+        let value = self.read_memory(addr,size)?;
+        self.trace_access(addr,size,value as u64,false);
+        Ok(value)
     }
     fn store(&mut self,addr:u32,size:u32,value:u32) -> Result<(),Exception>{
-        let addr = self.translate(self.pc,AccessType::Store)?;
-        self.bus.store(addr,size,value)
+        self.write_memory(addr,size,value)?;
+        self.trace_access(addr,size,value as u64,true);
+        Ok(())
     }
-    fn update_pc(&mut self) -> Result<u32,Exception>{
-        Ok(self.pc+4)
-    }
-    fn handle_exception(&mut self,e:Exception) {
-        let mode = self.mode;
-        //if exception happened in User or Supervisor level and allowed to be delegate
-        let (STATUS,TVEC,CAUSE,EPC,TVAL,MASK_PIE,pie_i,MASK_IE,ie_i,MASK_PP,pp_i) = 
-        if mode <= SUPERVISOR && self.csr.is_medelegate(e.code()){
-            self.mode = SUPERVISOR;
-            (SSTATUS,STVEC,SCAUSE,SEPC,STVAL,MASK_SPIE,5,MASK_SIE,1,MASK_SPP,8)
+    /// Read-modify-write the word at `addr` for an AMO, which needs write
+    /// permission even to read. Returns the old value.
+    fn amo(&mut self,addr:u32,op:impl Fn(u32) -> u32) -> Result<u32,Exception>{
+        let pa = self.physical(addr,32,AccessType::Store)?;
+        let mut bus = self.bus.borrow_mut();
+        let old = bus.load(pa,32).map_err(|_| Exception::StoreAMOAccessFault(addr))?;
+        let new = op(old);
+        bus.store(pa,32,new).map_err(|_| Exception::StoreAMOAccessFault(addr))?;
+        drop(bus);
+        self.trace_access(addr,32,old as u64,false);
+        self.trace_access(addr,32,new as u64,true);
+        Ok(old)
+    }
+    /// Note a load or store of `size` bits in the trace record.
+    fn trace_access(&mut self,addr:u32,size:u32,value:u64,store:bool){
+        if let Some(record) = &mut self.record{
+            record.accesses.push(Access{addr,size,value,store});
+        }
+    }
+    /// Note in the trace record that the instruction fetched retired, and
+    /// the register it wrote.
+    fn trace_retired(&mut self){
+        if let Some(record) = &mut self.record{
+            let inst = record.inst.unwrap_or(0);
+            let expanded = if rvc::is_compressed(inst) {rvc::expand(inst).unwrap_or(0)} else {inst};
+            record.retired = true;
+            record.written = destination(expanded).map(|(float,r)| {
+                if float {Written::F(r,self.f_regs[r as usize])} else {Written::X(r,self.regs[r as usize])}
+            });
+        }
+    }
+    /// Read an f register as `fmt`; a single that is not NaN-boxed reads as
+    /// the canonical NaN.
+    fn read_f(&self,r:usize,fmt:Format) -> u64{
+        let val = self.f_regs[r];
+        if fmt == F64{
+            val
+        } else if val & NAN_BOX == NAN_BOX{
+            val & !NAN_BOX
         } else{
-            self.mode = MACHINE;
-            (MSTATUS,MTVEC,MCAUSE,MEPC,MTVAL,MASK_MPIE,7,MASK_MIE,3,MASK_MPP,11)
-        };
-        self.csr.store(CAUSE,e.code());
-        self.csr.store(EPC,self.pc);
-        if let Ok(tvec) = self.csr.load(TVEC){
-            self.pc = tvec;
-        }
-        self.csr.store(TVAL,e.value());
-        if let Ok(mut status) = self.csr.load(STATUS){
-            let ie = (status & MASK_IE) >> ie_i;
-            status = (status & !MASK_IE) | (ie_i << pie_i);
-            status &= !MASK_IE;
-            status = (status & !MASK_PP) | (mode << pp_i);
-            self.csr.store(STATUS,status);
+            F32.canonical_nan()
+        }
+    }
+    fn write_f(&mut self,r:usize,fmt:Format,val:u64){
+        self.f_regs[r] = if fmt == F64 {val} else {val | NAN_BOX};
+    }
+    /// Rounding mode selected by an instruction's rm field, with 7 meaning frm.
+    fn rounding(&self,inst:u32) -> Result<Rounding,Exception>{
+        let rm = match (inst >> 12) & 0x7{
+            0x7 => self.csr.load(FRM)?,
+            rm => rm
         };
-
+        Rounding::from_bits(rm).ok_or(Exception::IllegalInstruction(inst))
     }
-    fn handle_interrupt(&mut self,i:Interrupt){
-        let pc = self.pc;
-        let mode = self.mode;
-        let (status,tvec,cause_csr,epc,tval,MASK_PIE,pie_i,MASK_PP,pp_i,MASK_IE,ie_i) = 
-        if mode <= SUPERVISOR && self.csr.is_midelegate(i.code()){
-            self.mode = SUPERVISOR;
-            (SSTATUS,STVEC,SCAUSE,SEPC,STVAL,MASK_SPIE,5,MASK_SPP,8,MASK_SIE,1)
+    fn update_pc(&mut self) -> Result<u32,Exception>{
+        Ok(self.pc.wrapping_add(self.inst_len))
+    }
+    /// The pc a taken branch or jump goes to, which must be 4-byte aligned
+    /// unless the C extension is enabled.
+    fn jump(&self,target:u32) -> Result<u32,Exception>{
+        if target & 2 != 0 && !self.has_extension('c'){
+            return Err(Exception::InstructionAddrMisaligned(target));
+        }
+        Ok(target)
+    }
+    /// Take a trap with `cause`, whose top bit marks an interrupt. It goes
+    /// to S-mode if it comes from S- or U-mode and medeleg or mideleg
+    /// delegates it, to M-mode otherwise, stacking the previous mode in
+    /// xPP and the interrupt enable in xPIE. A vectored xtvec sends
+    /// interrupts to its base plus 4 times their cause.
+    fn trap(&mut self,cause:u32,tval:u32){
+        let (from,epc_value) = (self.mode,self.pc);
+        self.csr.count_event(EVENT_TRAP);
+        self.bus.borrow_mut().clear_reservation(self.hart_id);
+        let interrupt = cause & MASK_INTERRUPT_BIT != 0;
+        let code = cause & !MASK_INTERRUPT_BIT;
+        let delegated = self.mode <= SUPERVISOR
+            && if interrupt {self.csr.is_midelegate(code)} else {self.csr.is_medelegate(code)};
+        let (tvec,epc,xcause,xtval,ie,pie,pp,pp_shift) = if delegated{
+            (STVEC,SEPC,SCAUSE,STVAL,MASK_SIE,MASK_SPIE,MASK_SPP,8)
         } else{
-            self.mode = MACHINE;
-            (MSTATUS,MTVEC,MCAUSE,MEPC,MTVAL,MASK_MPIE,7,MASK_MPP,11,MASK_MIE,3)
+            (MTVEC,MEPC,MCAUSE,MTVAL,MASK_MIE,MASK_MPIE,MASK_MPP,11)
         };
-        self.csr.store(epc,self.pc);
-        //according the mode
-        let vec = self.csr.load(tvec).unwrap();
-        let t_mode = vec & 0b11;
-        let t_addr = vec & !0b11;
-        self.pc = match mode{
-            0 => t_addr,
-            1 => t_addr + i.code() << 2,
-            _ => unreachable!()
+        let status = self.csr.csrs[MSTATUS];
+        let pie_value = if status & ie != 0 {pie} else {0};
+        self.csr.csrs[MSTATUS] = (status & !(ie | pie | pp)) | pie_value | (self.mode << pp_shift);
+        self.csr.csrs[epc] = self.pc;
+        self.csr.csrs[xcause] = cause;
+        self.csr.csrs[xtval] = tval;
+        let base = self.csr.csrs[tvec] & !0b11;
+        let vectored = self.csr.csrs[tvec] & 0b11 == 1;
+        self.pc = if interrupt && vectored {base.wrapping_add(4 * code)} else {base};
+        self.mode = if delegated {SUPERVISOR} else {MACHINE};
+        if let Some(record) = &mut self.record{
+            record.traps.push(Trap{cause,tval,epc:epc_value,from,to:self.mode,handler:self.pc});
+        }
+    }
+    /// mret (`machine`) or sret: return to the mode in xPP with xIE restored
+    /// from xPIE, setting xPIE and leaving xPP at the least-privileged mode.
+    /// Returning below M-mode also clears MPRV. Returns the new pc.
+    fn trap_return(&mut self,machine:bool) -> u32{
+        let (epc,ie,pie,pp,pp_shift) = if machine{
+            (MEPC,MASK_MIE,MASK_MPIE,MASK_MPP,11)
+        } else{
+            (SEPC,MASK_SIE,MASK_SPIE,MASK_SPP,8)
         };
-        self.csr.store(cause_csr,i.code());
-        self.csr.store(tval,0);
-        let mut new_status = self.csr.load(status).unwrap();
-        new_status = (new_status & !MASK_PIE) | (MASK_PIE | (((new_status & MASK_IE)>>ie_i) << pie_i));
-        new_status = (new_status & !MASK_PP) | (mode << pp_i);
-        new_status &= !MASK_IE;
-        self.csr.store(status,new_status);
-
-    }
-    fn check_pending_interrupt(&mut self) -> Option<Interrupt>{
-        let (mstatus,sstatus) = (self.csr.load(MSTATUS).unwrap(),self.csr.load(SSTATUS).unwrap());
-        if self.mode == MACHINE && (mstatus & MASK_MIE) == 0{
-            return None;
+        let status = self.csr.csrs[MSTATUS];
+        let mode = (status & pp) >> pp_shift;
+        let least = if self.has_extension('u') {USER} else {MACHINE};
+        let ie_value = if status & pie != 0 {ie} else {0};
+        let mut status = (status & !(ie | pp)) | ie_value | pie | (least << pp_shift);
+        if mode != MACHINE{
+            status &= !MASK_MPRV;
         }
-        if self.mode == SUPERVISOR && (sstatus & MASK_SIE) == 0{
+        self.csr.csrs[MSTATUS] = status;
+        self.mode = mode;
+        self.bus.borrow_mut().clear_reservation(self.hart_id);
+        self.csr.csrs[epc]
+    }
+    /// Mirror the level of the devices' interrupt lines into mip.
+    fn update_interrupt_lines(&mut self){
+        let (hart,bus) = (self.hart_id,self.bus.borrow());
+        self.csr.set_interrupt_line(MASK_MSIP,bus.clint.msip(hart));
+        self.csr.set_interrupt_line(MASK_MTIP,bus.clint.mtip(hart));
+        self.csr.set_interrupt_line(MASK_MEIP,bus.plic.is_context_interrupting(2*hart));
+        self.csr.set_interrupt_line(MASK_SEIP,bus.plic.is_context_interrupting(2*hart + 1));
+    }
+    /// The interrupt to take now, if any. An interrupt goes to S-mode if
+    /// mideleg delegates it and to M-mode otherwise, and is enabled if the
+    /// hart runs below that mode, or in it with xIE set; M-mode never takes
+    /// delegated ones. Those for M-mode come first, then in PRIORITY order.
+    /// The pending bits are levels, which only the CLINT, the PLIC or a
+    /// write to mip clears.
+    fn check_pending_interrupt(&self) -> Option<Interrupt>{
+        let pending = self.csr.csrs[MIE] & self.csr.csrs[MIP];
+        if pending == 0{
             return None;
         }
-        
-        // if self.bus.uart.is_interrupting(){
-
-        // }
-        let (mie,mip) = (self.csr.load(MIE).unwrap(),self.csr.load(MIP).unwrap());
-        let pending = mie & mip;
+        let (mstatus,mideleg) = (self.csr.csrs[MSTATUS],self.csr.csrs[MIDELEG]);
+        let machine_enabled = self.mode < MACHINE || mstatus & MASK_MIE != 0;
+        let supervisor_enabled = self.mode < SUPERVISOR || (self.mode == SUPERVISOR && mstatus & MASK_SIE != 0);
+        let machine = if machine_enabled {pending & !mideleg} else {0};
+        let supervisor = if supervisor_enabled {pending & mideleg} else {0};
+        let enabled = if machine != 0 {machine} else {supervisor};
+        PRIORITY.into_iter().find(|i| enabled & i.mask() != 0)
+    }
 
-        if (pending & MASK_MEIP) != 0 {
-            self.csr.store(MIP, self.csr.load(MIP).unwrap() & !MASK_MEIP);
-            return Some(Interrupt::MachineExternalInterrupt);
-        }
-        if (pending & MASK_MSIP) != 0 {
-            self.csr.store(MIP, self.csr.load(MIP).unwrap() & !MASK_MSIP);
-            return Some(Interrupt::MachineSoftwareInterrupt);
-        }
-        if (pending & MASK_MTIP) != 0 {
-            self.csr.store(MIP, self.csr.load(MIP).unwrap() & !MASK_MTIP);
-            return Some(Interrupt::MachineTimerInterrupt);
-        }
-        if (pending & MASK_SEIP) != 0 {
-            self.csr.store(MIP, self.csr.load(MIP).unwrap() & !MASK_SEIP);
-            return Some(Interrupt::SupervisorExternalInterrupt);
+    /// Whether misa has the extension named by `letter`.
+    fn has_extension(&self,letter:char) -> bool{
+        self.csr.csrs[MISA] & (1 << (letter as u32 - 'a' as u32)) != 0
+    }
+    fn execute(&mut self,inst:u32) -> Result<u32,Exception>{
+        if !rvc::is_compressed(inst){
+            let next = self.execute_32(inst)?;
+            self.count_retired(inst);
+            return Ok(next);
         }
-        if (pending & MASK_SSIP) != 0 {
-            self.csr.store(MIP, self.csr.load(MIP).unwrap() & !MASK_SSIP);
-            return Some(Interrupt::SupervisorSoftwareInterrupt);
+        if !self.has_extension('c'){
+            return Err(Exception::IllegalInstruction(inst));
         }
-        if (pending & MASK_STIP) != 0 {
-            self.csr.store(MIP, self.csr.load(MIP).unwrap() & !MASK_STIP);
-            return Some(Interrupt::SupervisorTimerInterrupt);
+        // mtval gets the 16-bit encoding rather than its expansion
+        let expanded = rvc::expand(inst).ok_or(Exception::IllegalInstruction(inst))?;
+        let next = self.execute_32(expanded).map_err(|e| match e{
+            Exception::IllegalInstruction(_) => Exception::IllegalInstruction(inst),
+            e => e
+        })?;
+        self.count_retired(expanded);
+        Ok(next)
+    }
+    /// Count the performance events of the 32-bit instruction just retired.
+    fn count_retired(&mut self,inst:u32){
+        match inst & 0x7f{
+            0x03 | 0x07 => self.csr.count_event(EVENT_LOAD),
+            0x23 | 0x27 => self.csr.count_event(EVENT_STORE),
+            0x63 => self.csr.count_event(EVENT_BRANCH),
+            _ => ()
         }
-        None
     }
 
-    fn execute(&mut self,inst:u32) -> Result<u32,Exception>{
+    fn execute_32(&mut self,inst:u32) -> Result<u32,Exception>{
         let opcode = inst & 0x7f;
         let rd = ((inst & 0xf80) >> 7) as usize;
         let funct3 = (inst & 0x00007000) >> 12;
@@ -266,6 +574,29 @@ impl Cpu{
         let funct7 = (inst & 0xfe000000) >> 25;
 
         self.regs[0] = 0;
+        // instructions of extensions left out of misa are illegal
+        let extension = match opcode{
+            0x2f => Some('a'),
+            0x33 if funct7 == 0x01 => Some('m'),
+            0x07 | 0x27 if funct3 == 0x3 => Some('d'),
+            0x43 | 0x47 | 0x4b | 0x4f | 0x53 if funct7 & 0b11 == 0b01 => Some('d'),
+            // fcvt.s.d
+            0x53 if funct7 == 0x20 && rs2 == 1 => Some('d'),
+            0x07 | 0x27 | 0x43 | 0x47 | 0x4b | 0x4f | 0x53 => Some('f'),
+            _ => None
+        };
+        if extension.is_some_and(|e| !self.has_extension(e)){
+            return Err(Exception::IllegalInstruction(inst));
+        }
+        if matches!(opcode,0x07 | 0x27 | 0x43 | 0x47 | 0x4b | 0x4f | 0x53){
+            if !self.csr.fp_enabled(){
+                return Err(Exception::IllegalInstruction(inst));
+            }
+            // everything but a store may write f registers or fflags
+            if opcode != 0x27{
+                self.csr.set_fp_dirty();
+            }
+        }
         match opcode {
             0x03 => {
                 // imm[11:0] = inst[31:20]
@@ -306,6 +637,28 @@ impl Cpu{
                     
                 }
             }
+            0x07 => {
+                // imm[11:0] = inst[31:20]
+                let imm = (inst as i32 >> 20) as u32;
+                let addr = self.regs[rs1].wrapping_add(imm);
+                match funct3 {
+                    0x2 => {
+                        // flw
+                        let val = self.load(addr, 32)?;
+                        self.write_f(rd, F32, val as u64);
+                        return self.update_pc();
+                    }
+                    0x3 => {
+                        // fld
+                        let low = self.read_memory(addr, 32)? as u64;
+                        let high = self.read_memory(addr.wrapping_add(4), 32)? as u64;
+                        self.trace_access(addr, 64, low | (high << 32), false);
+                        self.write_f(rd, F64, low | (high << 32));
+                        return self.update_pc();
+                    }
+                    _ => Err(Exception::IllegalInstruction(inst)),
+                }
+            }
             0x0f => {
                 // A fence instruction does nothing because this emulator executes an
                 // instruction sequentially on a single thread.
@@ -319,8 +672,8 @@ impl Cpu{
             0x13 => {
                 // imm[11:0] = inst[31:20]
                 let imm = ((inst & 0xfff00000) as i32 >> 20) as u32;
-                // "The shift amount is encoded in the lower 6 bits of the I-immediate field for RV64I."
-                let shamt = (imm & 0x3f) as u32;
+                // the shift amount is the low 5 bits of the immediate; RV32 reserves imm[5]
+                let shamt = imm & 0x1f;
                 match funct3 {
                     0x0 => {
                         // addi
@@ -329,12 +682,15 @@ impl Cpu{
                     }
                     0x1 => {
                         // slli
+                        if funct7 != 0x00{
+                            return Err(Exception::IllegalInstruction(inst));
+                        }
                         self.regs[rd] = self.regs[rs1] << shamt;
                         return self.update_pc();
                     }
                     0x2 => {
                         // slti
-                        self.regs[rd] = if (self.regs[rs1] as i64) < (imm as i64) { 1 } else { 0 };
+                        self.regs[rd] = if (self.regs[rs1] as i32) < (imm as i32) { 1 } else { 0 };
                         return self.update_pc();
                     }
                     0x3 => {
@@ -348,15 +704,15 @@ impl Cpu{
                         return self.update_pc();
                     }
                     0x5 => {
-                        match funct7 >> 1 {
+                        match funct7 {
                             // srli
                             0x00 => {
                                 self.regs[rd] = self.regs[rs1].wrapping_shr(shamt);
                                 return self.update_pc();
                             },
                             // srai
-                            0x10 => {
-                                self.regs[rd] = (self.regs[rs1] as i64).wrapping_shr(shamt) as u32;
+                            0x20 => {
+                                self.regs[rd] = (self.regs[rs1] as i32).wrapping_shr(shamt) as u32;
                                 return self.update_pc();
                             }
                             _ => Err(Exception::IllegalInstruction(inst)),
@@ -379,42 +735,6 @@ impl Cpu{
                 self.regs[rd] = self.pc.wrapping_add(imm);
                 return self.update_pc();
             }
-            0x1b => {
-                let imm = ((inst as i32 as i64) >> 20) as u32;
-                // "SLLIW, SRLIW, and SRAIW encodings with imm[5] ̸= 0 are reserved."
-                let shamt = (imm & 0x1f) as u32;
-                match funct3 {
-                    0x0 => {
-                        // addiw
-                        self.regs[rd] = self.regs[rs1].wrapping_add(imm) as i32 as i64 as u32;
-                        return self.update_pc();
-                    }
-                    0x1 => {
-                        // slliw
-                        self.regs[rd] = self.regs[rs1].wrapping_shl(shamt) as i32 as i64 as u32;
-                        return self.update_pc();
-                    }
-                    0x5 => {
-                        match funct7 {
-                            0x00 => {
-                                // srliw
-                                self.regs[rd] = (self.regs[rs1] as u32).wrapping_shr(shamt) as i32
-                                    as i64 as u32;
-                                return self.update_pc();
-                            }
-                            0x20 => {
-                                // sraiw
-                                self.regs[rd] =
-                                    (self.regs[rs1] as i32).wrapping_shr(shamt) as i32 as u32;
-                                return self.update_pc();
-                            }
-                            _ => Err(Exception::IllegalInstruction(inst)),
-                        }
-                    }
-                    _ => Err(Exception::IllegalInstruction(inst)),
-                    
-                }
-            }
             0x23 => {
                 // imm[11:5|4:0] = inst[31:25|11:7]
                 let imm = (((inst & 0xfe000000) as i32 as i64 >> 20) as u32) | ((inst >> 7) & 0x1f);
@@ -423,84 +743,108 @@ impl Cpu{
                     0x0 => {self.store(addr, 8, self.regs[rs2])?;  self.update_pc()}, // sb
                     0x1 => {self.store(addr, 16, self.regs[rs2])?; self.update_pc()}, // sh
                     0x2 => {self.store(addr, 32, self.regs[rs2])?; self.update_pc()}, // sw
-                    _ => unreachable!(),
+                    _ => Err(Exception::IllegalInstruction(inst)),
+                }
+            }
+            0x27 => {
+                // imm[11:5|4:0] = inst[31:25|11:7]
+                let imm = (((inst & 0xfe000000) as i32 >> 20) as u32) | ((inst >> 7) & 0x1f);
+                let addr = self.regs[rs1].wrapping_add(imm);
+                match funct3 {
+                    0x2 => {
+                        // fsw
+                        self.store(addr, 32, self.f_regs[rs2] as u32)?;
+                        return self.update_pc();
+                    }
+                    0x3 => {
+                        // fsd
+                        self.write_memory(addr, 32, self.f_regs[rs2] as u32)?;
+                        self.write_memory(addr.wrapping_add(4), 32, (self.f_regs[rs2] >> 32) as u32)?;
+                        self.trace_access(addr, 64, self.f_regs[rs2], true);
+                        return self.update_pc();
+                    }
+                    _ => Err(Exception::IllegalInstruction(inst)),
                 }
             }
             0x2f => {
                 let funtc5 = (funct7 & 0b1111100) >> 2;
                 let _aq = (funct7 & 0b0000010) >> 1; // acquire access
                 let _rl = funct7 & 0b0000001; // release access
+                let addr = self.regs[rs1];
+                let v = self.regs[rs2];
+                if funct3 != 0x2{
+                    return Err(Exception::IllegalInstruction(inst));
+                }
+                if !addr.is_multiple_of(4){
+                    return Err(match funtc5{
+                        0x02 => Exception::LoadAccessMisaligned(addr),
+                        _ => Exception::StoreAMOAddrMisaligned(addr)
+                    });
+                }
                 match funtc5{
+                    0x02 => {
+                        //LR.W
+                        let paddr = self.physical(addr,32,AccessType::Load)?;
+                        let value = self.bus.borrow_mut().load(paddr,32).map_err(|_| Exception::LoadAccessFault(addr))?;
+                        self.bus.borrow_mut().reserve(self.hart_id,paddr);
+                        self.trace_access(addr,32,value as u64,false);
+                        self.regs[rd] = value;
+                        return self.update_pc();
+                    }
+                    0x03 => {
+                        //SC.W
+                        let paddr = self.physical(addr,32,AccessType::Store)?;
+                        let reserved = self.bus.borrow_mut().take_reservation(self.hart_id,paddr);
+                        if reserved{
+                            self.bus.borrow_mut().store(paddr,32,self.regs[rs2]).map_err(|_| Exception::StoreAMOAccessFault(addr))?;
+                            self.trace_access(addr,32,self.regs[rs2] as u64,true);
+                        }
+                        self.regs[rd] = !reserved as u32;
+                        return self.update_pc();
+                    }
                     0x00 => {
                         //AMOADD.W 
-                        let t = self.load(self.regs[rs1],32)?;
-                        self.store(self.regs[rs1],32,t.wrapping_add(self.regs[rs2]));
-                        self.regs[rd] = t;
+                        self.regs[rd] = self.amo(addr,|t| t.wrapping_add(v))?;
                         return self.update_pc();
                     }
                     0x01 => {
                         //AMOSWAP.w
-                        let t = self.load(self.regs[rs1],32)?;
-                        self.store(self.regs[rs1],32,self.regs[rs2]);
-                        self.regs[rs2] = t;
-                        self.regs[rd] = t;
+                        self.regs[rd] = self.amo(addr,|_| v)?;
                         return self.update_pc();
                     }
                     0x04 => {
                         //AMOXOR.W
-                        let t = self.load(self.regs[rs1],32)?;
-                        self.store(self.regs[rs1],32,t ^ self.regs[rs2]);
-                        self.regs[rd] = t;
+                        self.regs[rd] = self.amo(addr,|t| t ^ v)?;
                         return self.update_pc();
                     }
                     0x08 => {
                         //AMOOR.W
-                        let t = self.load(self.regs[rs1],32)?;
-                        self.store(self.regs[rs1],32,t | self.regs[rs2]);
-                        self.regs[rd] = t;
+                        self.regs[rd] = self.amo(addr,|t| t | v)?;
                         return self.update_pc();
                     }
                     0x0c => {
                         //AMOAND.W
-                        let t = self.load(self.regs[rs1],32)?;
-                        self.store(self.regs[rs1],32,t & self.regs[rs2]);
-                        self.regs[rd] = t;
+                        self.regs[rd] = self.amo(addr,|t| t & v)?;
                         return self.update_pc();
                     }
                     0x10 => {
                         //AMOMIN.w
-                        let t = self.load(self.regs[rs1],32)?;
-                        if (self.regs[rs2] as i32) < (t as i32) {
-                            self.store(self.regs[rs1],32,self.regs[rs2]);
-                        }
-                        self.regs[rd] = t;
+                        self.regs[rd] = self.amo(addr,|t| (t as i32).min(v as i32) as u32)?;
                         return self.update_pc();
                     }
                     0x14 => {
                         //AMOMAX.w
-                        let t = self.load(self.regs[rs1],32)?;
-                        if (self.regs[rs2] as i32) > (t as i32) {
-                            self.store(self.regs[rs1],32,self.regs[rs2]);
-                        }
-                        self.regs[rd] = t;
+                        self.regs[rd] = self.amo(addr,|t| (t as i32).max(v as i32) as u32)?;
                         return self.update_pc();
                     }
                     0x18 => {
                         //AMOMINU.w
-                        let t = self.load(self.regs[rs1],32)?;
-                        if self.regs[rs2] < t {
-                            self.store(self.regs[rs1],32,self.regs[rs2]);
-                        }
-                        self.regs[rd] = t;
+                        self.regs[rd] = self.amo(addr,|t| t.min(v))?;
                         return self.update_pc();
                     }
                     0x1c => {
                         //AMOMAXU.w
-                        let t = self.load(self.regs[rs1],32)?;
-                        if self.regs[rs2] > t {
-                            self.store(self.regs[rs1],32,self.regs[rs2]);
-                        }
-                        self.regs[rd] = t;
+                        self.regs[rd] = self.amo(addr,|t| t.max(v))?;
                         return self.update_pc();
                     }
                     _ => Err(Exception::IllegalInstruction(inst))
@@ -508,9 +852,9 @@ impl Cpu{
             }
             0x33 => {
                 // "SLL, SRL, and SRA perform logical left, logical right, and arithmetic right
-                // shifts on the value in register rs1 by the shift amount held in register rs2.
-                // In RV64I, only the low 6 bits of rs2 are considered for the shift amount."
-                let shamt = ((self.regs[rs2] & 0x3f) as u32) as u32;
+                // shifts on the value in register rs1 by the shift amount held in the lower
+                // 5 bits of register rs2."
+                let shamt = self.regs[rs2] & 0x1f;
                 match (funct3, funct7) {
                     (0x0, 0x00) => {
                         // add
@@ -541,38 +885,38 @@ impl Cpu{
                         return self.update_pc();
                     }
                     (0x4, 0x01) => {
-                        //div
+                        //div, giving -1 for division by zero and the dividend on overflow
                         if self.regs[rs2] == 0{
-                            self.regs[rd] = 0;
+                            self.regs[rd] = u32::MAX;
                         } else{
-                            self.regs[rd] = (self.regs[rs1] as i32 / self.regs[rs2] as i32) as u32;
+                            self.regs[rd] = (self.regs[rs1] as i32).wrapping_div(self.regs[rs2] as i32) as u32;
                         }
                         return self.update_pc();
                     }
                     (0x5, 0x01) => {
                         //divu
                         if self.regs[rs2] == 0{
-                            self.regs[rd] = 0;
+                            self.regs[rd] = u32::MAX;
                         } else{
                             self.regs[rd] = self.regs[rs1] / self.regs[rs2];
                         }
                         return self.update_pc();
                     }
                     (0x6, 0x01) => {
-                        //rem
+                        //rem, giving the dividend for division by zero and 0 on overflow
                         if self.regs[rs2] == 0{
-                            self.regs[rd] = 0;
+                            self.regs[rd] = self.regs[rs1];
                         } else{
-                            self.regs[rd] = (self.regs[rs1] as i32 % self.regs[rs2] as i32) as u32;
+                            self.regs[rd] = (self.regs[rs1] as i32).wrapping_rem(self.regs[rs2] as i32) as u32;
                         }
                         return self.update_pc();
                     }
                     (0x7, 0x01) => {
                         //remu
                         if self.regs[rs2] == 0{
-                            self.regs[rd] = 0;
+                            self.regs[rd] = self.regs[rs1];
                         } else{
-                            self.regs[rd] = self.regs[rs1] / self.regs[rs2];
+                            self.regs[rd] = self.regs[rs1] % self.regs[rs2];
                         }
                         return self.update_pc();
                     }
@@ -608,7 +952,7 @@ impl Cpu{
                     }
                     (0x5, 0x20) => {
                         // sra
-                        self.regs[rd] = (self.regs[rs1] as i64).wrapping_shr(shamt) as u32;
+                        self.regs[rd] = (self.regs[rs1] as i32).wrapping_shr(shamt) as u32;
                         return self.update_pc();
                     }
                     (0x6, 0x00) => {
@@ -629,152 +973,118 @@ impl Cpu{
                 self.regs[rd] = (inst & 0xfffff000) as i32 as u32;
                 return self.update_pc();
             }
-            0x43 => {
-                // fmadd.s
-                let rs3 = ((funct7 & 0x7c) >> 2) as usize;
-                self.f_regs[rd] = self.f_regs[rs1] * self.f_regs[rs2] + self.f_regs[rs3];
-                return self.update_pc();
-
-            }
-            0x47 => {
-                //fmsub.s
+            0x43 | 0x47 | 0x4b | 0x4f => {
+                // fmadd, fmsub, fnmsub, fnmadd
+                let fmt = float_format(funct7 & 0b11).ok_or(Exception::IllegalInstruction(inst))?;
                 let rs3 = ((funct7 & 0x7c) >> 2) as usize;
-                self.f_regs[rd] = self.f_regs[rs1] * self.f_regs[rs2] - self.f_regs[rs3];
-                return self.update_pc();
-            }
-            0x4b => {
-                //fnmsub.s
-                let rs3 = ((funct7 & 0x7c) >> 2) as usize;
-                self.f_regs[rd] = -self.f_regs[rs1] * self.f_regs[rs2] + self.f_regs[rs3];
-                return self.update_pc();
-            }
-            0x4f => {
-                //fnmadd.s
-                let rs3 = ((funct7 & 0x7c) >> 2) as usize;
-                self.f_regs[rd] = -self.f_regs[rs1] * self.f_regs[rs2] - self.f_regs[rs3];
+                let rm = self.rounding(inst)?;
+                // fnmsub/fnmadd negate the product, fmsub/fnmadd the addend
+                let a = self.read_f(rs1, fmt) ^ if opcode == 0x4b || opcode == 0x4f {fmt.sign_mask()} else {0};
+                let c = self.read_f(rs3, fmt) ^ if opcode == 0x47 || opcode == 0x4f {fmt.sign_mask()} else {0};
+                let mut flags = 0;
+                let result = fmt.mul_add(a, self.read_f(rs2, fmt), c, rm, &mut flags);
+                self.write_f(rd, fmt, result);
+                self.csr.accrue_fflags(flags);
                 return self.update_pc();
             }
             0x53 => {
-                match (funct7,funct3) {
+                let fmt = float_format(funct7 & 0b11).ok_or(Exception::IllegalInstruction(inst))?;
+                let (a,b) = (self.read_f(rs1, fmt),self.read_f(rs2, fmt));
+                let sign = fmt.sign_mask();
+                let mut flags = 0;
+                match (funct7 >> 2,funct3) {
                     (0x00,_) => {
-                        //fadd.s
-                        self.f_regs[rd] = self.f_regs[rs1] + self.f_regs[rs2];
-                        return self.update_pc();
-                    }
-                    (0x04,_) => {
-                        //fsub.s
-                        self.f_regs[rd] = self.f_regs[rs1] - self.f_regs[rs2];
-                        return self.update_pc();
-                    }
-                    (0x08,_) => {
-                        //fmul.s
-                        self.f_regs[rd] = self.f_regs[rs1] * self.f_regs[rs2];
-                        return self.update_pc();
-                    }
-                    (0x0c,_) => {
-                        //fdiv.s
-                        self.f_regs[rd] = self.f_regs[rs1] / self.f_regs[rs2];
-                        return self.update_pc();
-                    }
-                    (0x2c,_) => {
-                        //fsqrt.s
-                        self.f_regs[rd] = f64::sqrt(self.f_regs[rs1] as f64) as f32;
-                        return self.update_pc();
-                    }
-                    (0x10,0x00) => {
-                        //fsgnj.s
-                        self.f_regs[rd] = if self.f_regs[rs2] < 0.0 { -1.0} else {1.0} * self.f_regs[rs1].abs();
-                        return self.update_pc();
-                    }
-                    (0x10,0x01) => {
-                        //fsgnjn.s
-                        self.f_regs[rd] = if self.f_regs[rs2] < 0.0 { 1.0} else {-1.0} * self.f_regs[rs1].abs();
-                        return self.update_pc();
-                    }
-                    (0x10,0x02) => {
-                        //fsgnjx.s
-                        let sign = |x| if x >= 0.0{1.0} else{-1.0};
-                        self.f_regs[rd] = sign(self.f_regs[rs1])*sign(self.f_regs[rs2]) * self.f_regs[rs1].abs();
-                        return self.update_pc();
-                    }
-                    (0x14,0x00) => {
-                        //fmin.s
-                        self.f_regs[rd] = if self.f_regs[rs1] < self.f_regs[rs2]{self.f_regs[rs1]}else{self.f_regs[rs2]};
-                        return self.update_pc();
-                    }
-                    (0x14,0x01) => {
-                        //fmax.s
-                        self.f_regs[rd] = if self.f_regs[rs1] < self.f_regs[rs2]{self.f_regs[rs2]}else{self.f_regs[rs1]};
-                        return self.update_pc();
-                    }
-                    (0x60,_) => {
-                        match rs2{
-                            0x0 => {
-                                //fcvt.w.s
-                                self.regs[rd] = self.f_regs[rs1] as i32 as u32;
-                                return self.update_pc();
-                            }
-                            0x1 => {
-                                //fcvt.wu.s
-                                self.regs[rd] = self.f_regs[rs1] as u32;
-                                return self.update_pc();
-                            }
-                            _ => return Err(Exception::IllegalInstruction(inst))
-                        }
-                    }
-                    (0x70,0x0) => {
-                        //fmv.x.w
-                        self.regs[rd] = f32::to_bits(self.f_regs[rs1]) ;
-                        return self.update_pc();
-                    }
-                    (0x50,_) => {
-                        match funct3{
-                            0x2 => {
-                                //feq.s
-                                self.regs[rd] = (self.f_regs[rs1] == self.f_regs[rs1]) as u32;
-                                return self.update_pc();
-                            }
-                            0x1 => {
-                                //flt
-                                self.regs[rd] = (self.f_regs[rs1] < self.f_regs[rs1]) as u32;
-                                return self.update_pc();
-                            }
-                            0x0 => {
-                                //fle
-                                self.regs[rd] = (self.f_regs[rs1] <= self.f_regs[rs1]) as u32;
-                                return self.update_pc();
-                            }
-                            _ => return Err(Exception::IllegalInstruction(inst))
-                        }
-                        
-
-                    }
-                    (0x70,_) => {
-                        //fclass dont want to implemment
-                        return Err(Exception::IllegalInstruction(inst));
-                    }
-                    (0x68, _) => {
-                        match rs2{
-                            0x0 => {
-                                //fcvt.s.w
-                                self.f_regs[rd] = self.regs[rs1] as i32 as f32;
-                                return self.update_pc();
-                            }
-                            0x1 => {
-                                //fcvt.s.wu
-                                self.f_regs[rd] = self.regs[rs1] as f32;
-                                return self.update_pc();
-                            }
-                            _ => return Err(Exception::IllegalInstruction(inst))
-                        }
-                    }
-                    (0x78,0x0) => {
+                        //fadd
+                        let result = fmt.add(a,b,self.rounding(inst)?,&mut flags);
+                        self.write_f(rd, fmt, result);
+                    }
+                    (0x01,_) => {
+                        //fsub
+                        let result = fmt.sub(a,b,self.rounding(inst)?,&mut flags);
+                        self.write_f(rd, fmt, result);
+                    }
+                    (0x02,_) => {
+                        //fmul
+                        let result = fmt.mul(a,b,self.rounding(inst)?,&mut flags);
+                        self.write_f(rd, fmt, result);
+                    }
+                    (0x03,_) => {
+                        //fdiv
+                        let result = fmt.div(a,b,self.rounding(inst)?,&mut flags);
+                        self.write_f(rd, fmt, result);
+                    }
+                    (0x0b,_) if rs2 == 0 => {
+                        //fsqrt
+                        let result = fmt.sqrt(a,self.rounding(inst)?,&mut flags);
+                        self.write_f(rd, fmt, result);
+                    }
+                    (0x04,0x00) => {
+                        //fsgnj
+                        self.write_f(rd, fmt, (a & !sign) | (b & sign));
+                    }
+                    (0x04,0x01) => {
+                        //fsgnjn
+                        self.write_f(rd, fmt, (a & !sign) | (!b & sign));
+                    }
+                    (0x04,0x02) => {
+                        //fsgnjx
+                        self.write_f(rd, fmt, a ^ (b & sign));
+                    }
+                    (0x05,0x00) => {
+                        //fmin
+                        self.write_f(rd, fmt, fmt.min_max(a,b,false,&mut flags));
+                    }
+                    (0x05,0x01) => {
+                        //fmax
+                        self.write_f(rd, fmt, fmt.min_max(a,b,true,&mut flags));
+                    }
+                    (0x08,_) if rs2 == 1 && fmt == F32 => {
+                        //fcvt.s.d
+                        let result = F64.convert(F32,self.read_f(rs1, F64),self.rounding(inst)?,&mut flags);
+                        self.write_f(rd, F32, result);
+                    }
+                    (0x08,_) if rs2 == 0 && fmt == F64 => {
+                        //fcvt.d.s
+                        let result = F32.convert(F64,self.read_f(rs1, F32),self.rounding(inst)?,&mut flags);
+                        self.write_f(rd, F64, result);
+                    }
+                    (0x18,_) if rs2 <= 1 => {
+                        //fcvt.w, fcvt.wu
+                        self.regs[rd] = fmt.to_int(a,rs2 == 0,self.rounding(inst)?,&mut flags);
+                    }
+                    (0x1a,_) if rs2 <= 1 => {
+                        //fcvt.s.w, fcvt.s.wu, fcvt.d.w, fcvt.d.wu
+                        let result = fmt.convert_from_int(self.regs[rs1],rs2 == 0,self.rounding(inst)?,&mut flags);
+                        self.write_f(rd, fmt, result);
+                    }
+                    (0x14,0x2) => {
+                        //feq
+                        self.regs[rd] = fmt.equal(a,b,&mut flags) as u32;
+                    }
+                    (0x14,0x1) => {
+                        //flt
+                        self.regs[rd] = fmt.less(a,b,false,&mut flags) as u32;
+                    }
+                    (0x14,0x0) => {
+                        //fle
+                        self.regs[rd] = fmt.less(a,b,true,&mut flags) as u32;
+                    }
+                    (0x1c,0x0) if rs2 == 0 && fmt == F32 => {
+                        //fmv.x.w copies the low half without unboxing
+                        self.regs[rd] = self.f_regs[rs1] as u32;
+                    }
+                    (0x1c,0x1) if rs2 == 0 => {
+                        //fclass
+                        self.regs[rd] = fmt.classify(a);
+                    }
+                    (0x1e,0x0) if rs2 == 0 && fmt == F32 => {
                         //fmv.w.x
-                        self.f_regs[rd] = f32::from_bits(self.regs[rs1]);
-                        return self.update_pc();
+                        self.write_f(rd, F32, self.regs[rs1] as u64);
                     }
                     _ => return Err(Exception::IllegalInstruction(inst))
                 }
+                self.csr.accrue_fflags(flags);
+                return self.update_pc();
             }
             0x63 => {
                 // imm[12|10:5|4:1|11] = inst[31|30:25|11:8|7]
@@ -787,42 +1097,42 @@ impl Cpu{
                     0x0 => {
                         // beq
                         if self.regs[rs1] == self.regs[rs2] {
-                            return Ok(self.pc.wrapping_add(imm));
+                            return self.jump(self.pc.wrapping_add(imm));
                         }
                         return self.update_pc();
                     }
                     0x1 => {
                         // bne
                         if self.regs[rs1] != self.regs[rs2] {
-                            return Ok(self.pc.wrapping_add(imm));
+                            return self.jump(self.pc.wrapping_add(imm));
                         }
                         return self.update_pc();
                     }
                     0x4 => {
                         // blt
                         if (self.regs[rs1] as i32) < (self.regs[rs2] as i32) {
-                            return Ok(self.pc.wrapping_add(imm));
+                            return self.jump(self.pc.wrapping_add(imm));
                         }
                         return self.update_pc();
                     }
                     0x5 => {
                         // bge
                         if (self.regs[rs1] as i32) >= (self.regs[rs2] as i32) {
-                            return Ok(self.pc.wrapping_add(imm));
+                            return self.jump(self.pc.wrapping_add(imm));
                         }
                         return self.update_pc();
                     }
                     0x6 => {
                         // bltu
                         if self.regs[rs1] < self.regs[rs2] {
-                            return Ok(self.pc.wrapping_add(imm));
+                            return self.jump(self.pc.wrapping_add(imm));
                         }
                         return self.update_pc();
                     }
                     0x7 => {
                         // bgeu
                         if self.regs[rs1] >= self.regs[rs2] {
-                            return Ok(self.pc.wrapping_add(imm));
+                            return self.jump(self.pc.wrapping_add(imm));
                         }
                         return self.update_pc();
                     }
@@ -832,25 +1142,27 @@ impl Cpu{
             }
             0x67 => {
                 // jalr
-                let t = self.pc + 4;
+                let t = self.pc.wrapping_add(self.inst_len);
 
                 let imm = (((inst & 0xfff00000) as i32) >> 20) as u32;
-                let new_pc = (self.regs[rs1].wrapping_add(imm)) & !1;
+                let new_pc = self.jump((self.regs[rs1].wrapping_add(imm)) & !1)?;
 
                 self.regs[rd] = t;
                 return Ok(new_pc);
             }
             0x6f => {
                 // jal
-                self.regs[rd] = self.pc + 4;
+                let t = self.pc.wrapping_add(self.inst_len);
 
                 // imm[20|10:1|11|19:12] = inst[31|30:21|20|19:12]
                 let imm = (((inst & 0x80000000) as i32 as i64 >> 11) as u32) // imm[20]
                     | (inst & 0xff000) // imm[19:12]
                     | ((inst >> 9) & 0x800) // imm[11]
                     | ((inst >> 20) & 0x7fe); // imm[10:1]
+                let new_pc = self.jump(self.pc.wrapping_add(imm))?;
 
-                return Ok(self.pc.wrapping_add(imm));
+                self.regs[rd] = t;
+                return Ok(new_pc);
             }
             0x73 => {
                 let csr_addr = ((inst & 0xfff00000) >> 20) as usize;
@@ -871,34 +1183,40 @@ impl Cpu{
                                 return Err(Exception::Breakpoint(self.pc));
                             }
                             (0x2,0x8) => {
-                                //sret
-                                //mode <- MPP, SIE <- MPIE, MPP <- 0, MIE <- 0,pc <- SEPC
-                                let mut sstatus = self.csr.load(SSTATUS)?;
-                                let spp = (sstatus | MASK_MPP) >> 11;
-                                let spie = (sstatus | MASK_SPIE) >> 7;
-                                self.mode = spp;
-                                sstatus = (sstatus & !MASK_SIE) | (spie << 1);
-                                sstatus &= !MASK_SPP;
-                                sstatus &= !MASK_SPIE;
-                                let new_pc = self.csr.load(SEPC)? & !0b11;
-                                Ok(new_pc)
+                                //sret, which U-mode may not use, nor S-mode with TSR set
+                                let tsr = self.csr.csrs[MSTATUS] & MASK_TSR != 0;
+                                if self.mode == USER || (self.mode == SUPERVISOR && tsr){
+                                    return Err(Exception::IllegalInstruction(inst));
+                                }
+                                Ok(self.trap_return(false))
                             }
                             (0x2,0x18) => {
                                 //mret
-                                //mode <- MPP, MIE <- MPIE, MPP <- 0, MIE <- 0,pc <- MEPC
-                                let mut mstatus = self.csr.load(MSTATUS)?;
-                                let mpp = (mstatus | MASK_SPP) >> 8;
-                                let mpie = (mstatus | MASK_SPIE) >> 5;
-                                self.mode = mpp;
-                                mstatus = (mstatus & !MASK_SIE) | (mpie << 3);
-                                mstatus &= !MASK_MPP;
-                                mstatus &= !MASK_MPIE;
-                                let new_pc = self.csr.load(MEPC)? & !0b11;
-                                Ok(new_pc)
+                                if self.mode != MACHINE{
+                                    return Err(Exception::IllegalInstruction(inst));
+                                }
+                                Ok(self.trap_return(true))
+                            }
+                            (0x5,0x8) => {
+                                //wfi, which U-mode may not use, nor S-mode with TW set
+                                let tw = self.csr.csrs[MSTATUS] & MASK_TW != 0;
+                                if self.mode == USER || (self.mode == SUPERVISOR && tw){
+                                    return Err(Exception::IllegalInstruction(inst));
+                                }
+                                // an interrupt already pending ends the wait at once
+                                self.waiting = self.csr.csrs[MIE] & self.csr.csrs[MIP] == 0;
+                                self.update_pc()
                             }
                             (_, 0x9) => {
-                                // sfence.vma
-                                // Do nothing.
+                                // sfence.vma: x0 in rs1 means every address,
+                                // x0 in rs2 every address space
+                                let tvm = self.csr.csrs[MSTATUS] & MASK_TVM != 0;
+                                if self.mode == USER || (self.mode == SUPERVISOR && tvm){
+                                    return Err(Exception::IllegalInstruction(inst));
+                                }
+                                let vaddr = if rs1 == 0 {None} else {Some(self.regs[rs1])};
+                                let asid = if rs2 == 0 {None} else {Some(self.regs[rs2] & (MASK_ASID >> 22))};
+                                self.tlb.flush(vaddr,asid);
                                 return self.update_pc();
                             }
                             _ => Err(Exception::IllegalInstruction(inst))
@@ -906,44 +1224,52 @@ impl Cpu{
                     }
                     0x1 => {
                         //csrrw
-                        self.regs[rd] = self.csr.load(csr_addr)?;
-                        self.csr.store(csr_addr,self.regs[rs1]);
-                        self.updating_page(csr_addr);
+                        let t = self.read_csr(inst,csr_addr,true)?;
+                        self.write_csr(inst,csr_addr,self.regs[rs1])?;
+                        self.regs[rd] = t;
                         return self.update_pc();
                     }
                     0x2 => {
-                        //csrrs
-                        self.regs[rd] = self.csr.load(csr_addr)?;
-                        self.csr.store(csr_addr,self.regs[rs1] | self.regs[rd]);
-                        self.updating_page(csr_addr);
+                        //csrrs, which does not write the CSR when rs1 is x0
+                        let t = self.read_csr(inst,csr_addr,rs1 != 0)?;
+                        if rs1 != 0{
+                            self.write_csr(inst,csr_addr,self.regs[rs1] | self.modified_csr(csr_addr,t))?;
+                        }
+                        self.regs[rd] = t;
                         return self.update_pc();
                     }
                     0x3 => {
                         //csrrc
-                        self.regs[rd] = self.csr.load(csr_addr)?;
-                        self.csr.store(csr_addr,!self.regs[rs1] & self.regs[rd]);
-                        self.updating_page(csr_addr);
+                        let t = self.read_csr(inst,csr_addr,rs1 != 0)?;
+                        if rs1 != 0{
+                            self.write_csr(inst,csr_addr,!self.regs[rs1] & self.modified_csr(csr_addr,t))?;
+                        }
+                        self.regs[rd] = t;
                         return self.update_pc();
                     }
                     0x5 => {
                         //csrrwi
-                        self.regs[rd] = self.csr.load(csr_addr)?;
-                        self.csr.store(csr_addr,rs1 as u32);
-                        self.updating_page(csr_addr);
+                        let t = self.read_csr(inst,csr_addr,true)?;
+                        self.write_csr(inst,csr_addr,rs1 as u32)?;
+                        self.regs[rd] = t;
                         return self.update_pc();
                     }
                     0x6 => {
-                        //csrrsi
-                        self.regs[rd] = self.csr.load(csr_addr)?;
-                        self.csr.store(csr_addr,(rs1 as u32) | self.regs[rd]);
-                        self.updating_page(csr_addr);
+                        //csrrsi, which does not write the CSR when uimm is 0
+                        let t = self.read_csr(inst,csr_addr,rs1 != 0)?;
+                        if rs1 != 0{
+                            self.write_csr(inst,csr_addr,(rs1 as u32) | self.modified_csr(csr_addr,t))?;
+                        }
+                        self.regs[rd] = t;
                         return self.update_pc();
                     }
                     0x7 => {
                         //csrrci
-                        self.regs[rd] = self.csr.load(csr_addr)?;
-                        self.csr.store(csr_addr,!(rs1 as u32) & self.regs[rd]);
-                        self.updating_page(csr_addr);
+                        let t = self.read_csr(inst,csr_addr,rs1 != 0)?;
+                        if rs1 != 0{
+                            self.write_csr(inst,csr_addr,!(rs1 as u32) & self.modified_csr(csr_addr,t))?;
+                        }
+                        self.regs[rd] = t;
                         return self.update_pc();
                     }
                     _ => Err(Exception::IllegalInstruction(inst))
@@ -955,4 +1281,475 @@ impl Cpu{
     }
 
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::memory::Memory;
+    use crate::pmp::PMP_A;
+
+    const NOP: u32 = 0x0000_0013;
+    const ECALL: u32 = 0x0000_0073;
+    const SRET: u32 = 0x1020_0073;
+    const MRET: u32 = 0x3020_0073;
+    const WFI: u32 = 0x1050_0073;
+    const MTVEC_BASE: u32 = DRAM_BASE + 0x400;
+    const STVEC_BASE: u32 = DRAM_BASE + 0x800;
+    const INTERRUPT: u32 = MASK_INTERRUPT_BIT;
+    const MTIMECMP: u32 = CLINT_BASE + 0x4000;
+    /// The PLIC sources the tests raise MEIP and SEIP with.
+    const MACHINE_SOURCE: u32 = 1;
+    const SUPERVISOR_SOURCE: u32 = 2;
+
+    /// A hart running in `mode` at DRAM_BASE in RAM full of nops, with trap
+    /// vectors in M- and S-mode and PMP letting every mode access everything.
+    fn hart(mode:u32) -> Cpu{
+        let mut bus = Bus::new(1);
+        bus.add_memory("ram",DRAM_BASE,Memory::new(0x1000,false)).unwrap();
+        bus.map_clint("clint",CLINT_BASE,CLINT_SIZE).unwrap();
+        bus.map_plic("plic",PLIC_BASE,PLIC_SIZE).unwrap();
+        for addr in (DRAM_BASE..DRAM_BASE + 0x1000).step_by(4){
+            bus.store(addr,32,NOP).unwrap();
+        }
+        let misa = "imafdcsu".bytes().fold(1 << 30,|misa,c| misa | 1 << (c - b'a'));
+        let mut cpu = Cpu::new(0,misa,Rc::new(RefCell::new(bus)));
+        cpu.mode = mode;
+        cpu.csr.csrs[MTVEC] = MTVEC_BASE;
+        cpu.csr.csrs[STVEC] = STVEC_BASE;
+        cpu.csr.store(PMPADDR0,u32::MAX).unwrap();
+        cpu.csr.store(PMPCFG0,(PMP_A | PMP_R | PMP_W | PMP_X) as u32).unwrap();
+        cpu.csr_written(PMPCFG0);
+        cpu
+    }
+    fn put(cpu:&Cpu,addr:u32,inst:u32){
+        cpu.bus.borrow_mut().store(addr,32,inst).unwrap();
+    }
+    fn set_msip(cpu:&Cpu,level:bool){
+        put(cpu,CLINT_BASE,level as u32);
+    }
+    fn set_mtip(cpu:&Cpu,level:bool){
+        // mtime stays 0, so mtip follows whether mtimecmp is 0
+        put(cpu,MTIMECMP + 4,if level {0} else {u32::MAX});
+        put(cpu,MTIMECMP,0);
+    }
+    /// Drive the PLIC context that raises MEIP (`machine`) or SEIP.
+    fn set_external(cpu:&Cpu,machine:bool,level:bool){
+        let (context,source) = if machine {(0,MACHINE_SOURCE)} else {(1,SUPERVISOR_SOURCE)};
+        put(cpu,PLIC_BASE + 4 * source,1);
+        put(cpu,PLIC_BASE + 0x2000 + 0x80 * context,1 << source);
+        cpu.bus.borrow_mut().plic.set_level(source,level);
+    }
+    fn mstatus(cpu:&Cpu) -> u32{
+        cpu.csr.csrs[MSTATUS]
+    }
+
+    #[test]
+    fn interrupts_for_the_same_mode_follow_priority(){
+        let mut cpu = hart(MACHINE);
+        cpu.csr.csrs[MIE] = u32::MAX;
+        set_external(&cpu,true,true);
+        set_external(&cpu,false,true);
+        set_msip(&cpu,true);
+        set_mtip(&cpu,true);
+        cpu.csr.csrs[MIP] = MASK_SSIP | MASK_STIP;
+        let mut taken = Vec::new();
+        for _ in 0..6{
+            cpu.csr.csrs[MSTATUS] |= MASK_MIE;
+            cpu.step().unwrap();
+            let cause = cpu.csr.csrs[MCAUSE];
+            taken.push(cause & !INTERRUPT);
+            // lower the line just taken
+            match cause & !INTERRUPT{
+                11 => set_external(&cpu,true,false),
+                3 => set_msip(&cpu,false),
+                7 => set_mtip(&cpu,false),
+                9 => set_external(&cpu,false,false),
+                c => cpu.csr.csrs[MIP] &= !(1 << c)
+            }
+        }
+        assert_eq!(taken,[11,3,7,9,1,5]);
+    }
+
+    #[test]
+    fn supervisor_external_ranks_above_software_and_timer(){
+        let mut cpu = hart(MACHINE);
+        cpu.csr.csrs[MIE] = MASK_SEIP | MASK_SSIP | MASK_STIP;
+        cpu.csr.csrs[MSTATUS] |= MASK_MIE;
+        set_external(&cpu,false,true);
+        cpu.csr.csrs[MIP] = MASK_SSIP | MASK_STIP;
+        cpu.step().unwrap();
+        assert_eq!(cpu.csr.csrs[MCAUSE],INTERRUPT | 9);
+    }
+
+    #[test]
+    fn pending_bits_are_levels_owned_by_their_source(){
+        let mut cpu = hart(MACHINE);
+        cpu.csr.csrs[MIE] = MASK_MTIP;
+        cpu.csr.csrs[MSTATUS] |= MASK_MIE;
+        set_mtip(&cpu,true);
+        cpu.step().unwrap();
+        assert_eq!(cpu.csr.csrs[MCAUSE],INTERRUPT | 7);
+        // taking the interrupt leaves it pending
+        assert_ne!(cpu.csr.csrs[MIP] & MASK_MTIP,0);
+        set_mtip(&cpu,false);
+        cpu.step().unwrap();
+        assert_eq!(cpu.csr.csrs[MIP] & MASK_MTIP,0);
+        // software cannot clear a line driven by the CLINT
+        set_msip(&cpu,true);
+        cpu.csr.store(MIP,0).unwrap();
+        cpu.step().unwrap();
+        assert_ne!(cpu.csr.csrs[MIP] & MASK_MSIP,0);
+    }
+
+    #[test]
+    fn machine_mode_masks_its_interrupts_with_mie(){
+        let mut cpu = hart(MACHINE);
+        cpu.csr.csrs[MIE] = MASK_MSIP;
+        set_msip(&cpu,true);
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc,DRAM_BASE + 4);
+        assert_eq!(cpu.csr.csrs[MCAUSE],0);
+    }
+
+    #[test]
+    fn machine_interrupts_preempt_lower_modes_regardless_of_mie(){
+        let mut cpu = hart(SUPERVISOR);
+        cpu.csr.csrs[MIE] = MASK_MSIP;
+        set_msip(&cpu,true);
+        cpu.step().unwrap();
+        assert_eq!((cpu.mode,cpu.pc),(MACHINE,MTVEC_BASE));
+        assert_eq!(cpu.csr.csrs[MCAUSE],INTERRUPT | 3);
+        assert_eq!(cpu.csr.csrs[MEPC],DRAM_BASE + 4);
+        assert_eq!((mstatus(&cpu) & MASK_MPP) >> 11,SUPERVISOR);
+    }
+
+    #[test]
+    fn delegated_interrupts_go_to_supervisor_mode(){
+        let mut cpu = hart(USER);
+        cpu.csr.csrs[MIDELEG] = MASK_SSIP;
+        cpu.csr.csrs[MIE] = MASK_SSIP;
+        cpu.csr.csrs[MIP] = MASK_SSIP;
+        // U-mode takes them whatever SIE says
+        cpu.step().unwrap();
+        assert_eq!((cpu.mode,cpu.pc),(SUPERVISOR,STVEC_BASE));
+        assert_eq!(cpu.csr.csrs[SCAUSE],INTERRUPT | 1);
+        assert_eq!(cpu.csr.csrs[SEPC],DRAM_BASE + 4);
+        assert_eq!(mstatus(&cpu) & MASK_SPP,0);
+        assert_eq!(cpu.csr.csrs[MCAUSE],0);
+    }
+
+    #[test]
+    fn supervisor_mode_masks_delegated_interrupts_with_sie(){
+        let mut cpu = hart(SUPERVISOR);
+        cpu.csr.csrs[MIDELEG] = MASK_STIP;
+        cpu.csr.csrs[MIE] = MASK_STIP;
+        cpu.csr.csrs[MIP] = MASK_STIP;
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc,DRAM_BASE + 4);
+        cpu.csr.csrs[MSTATUS] |= MASK_SIE;
+        cpu.step().unwrap();
+        assert_eq!((cpu.mode,cpu.pc),(SUPERVISOR,STVEC_BASE));
+        let status = mstatus(&cpu);
+        assert_eq!(status & (MASK_SIE | MASK_SPIE | MASK_SPP),MASK_SPIE | MASK_SPP);
+    }
+
+    #[test]
+    fn machine_mode_never_takes_delegated_interrupts(){
+        let mut cpu = hart(MACHINE);
+        cpu.csr.csrs[MIDELEG] = MASK_SSIP;
+        cpu.csr.csrs[MIE] = MASK_SSIP;
+        cpu.csr.csrs[MIP] = MASK_SSIP;
+        cpu.csr.csrs[MSTATUS] |= MASK_MIE | MASK_SIE;
+        cpu.step().unwrap();
+        assert_eq!((cpu.mode,cpu.pc),(MACHINE,DRAM_BASE + 4));
+    }
+
+    #[test]
+    fn interrupts_for_machine_mode_come_before_delegated_ones(){
+        let mut cpu = hart(USER);
+        // SEI outranks SSI, but only SSI goes to M-mode
+        cpu.csr.csrs[MIDELEG] = MASK_SEIP;
+        cpu.csr.csrs[MIE] = MASK_SEIP | MASK_SSIP;
+        cpu.csr.csrs[MIP] = MASK_SSIP;
+        set_external(&cpu,false,true);
+        cpu.step().unwrap();
+        assert_eq!(cpu.mode,MACHINE);
+        assert_eq!(cpu.csr.csrs[MCAUSE],INTERRUPT | 1);
+    }
+
+    #[test]
+    fn vectored_xtvec_offsets_interrupts_only(){
+        let mut cpu = hart(MACHINE);
+        cpu.csr.store(MTVEC,MTVEC_BASE | 1).unwrap();
+        cpu.csr.csrs[MIE] = MASK_MTIP;
+        cpu.csr.csrs[MSTATUS] |= MASK_MIE;
+        set_mtip(&cpu,true);
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc,MTVEC_BASE + 4 * 7);
+        put(&cpu,cpu.pc,ECALL);
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc,MTVEC_BASE);
+        assert_eq!(cpu.csr.csrs[MCAUSE],11);
+    }
+
+    #[test]
+    fn exceptions_delegate_only_from_lower_modes(){
+        let mut cpu = hart(USER);
+        cpu.csr.store(MEDELEG,1 << 8 | 1 << 2).unwrap();
+        put(&cpu,DRAM_BASE,ECALL);
+        cpu.step().unwrap();
+        assert_eq!((cpu.mode,cpu.pc),(SUPERVISOR,STVEC_BASE));
+        assert_eq!(cpu.csr.csrs[SCAUSE],8);
+        assert_eq!(cpu.csr.csrs[SEPC],DRAM_BASE);
+        assert_eq!(cpu.csr.csrs[STVAL],0);
+        // an illegal instruction in M-mode stays there despite medeleg
+        cpu.mode = MACHINE;
+        put(&cpu,STVEC_BASE,0);
+        cpu.step().unwrap();
+        assert_eq!((cpu.mode,cpu.pc),(MACHINE,MTVEC_BASE));
+        assert_eq!(cpu.csr.csrs[MCAUSE],2);
+        assert_eq!((mstatus(&cpu) & MASK_MPP) >> 11,MACHINE);
+    }
+
+    #[test]
+    fn trap_entry_stacks_and_mret_restores(){
+        let mut cpu = hart(SUPERVISOR);
+        cpu.csr.csrs[MSTATUS] |= MASK_MIE;
+        put(&cpu,DRAM_BASE,ECALL);
+        put(&cpu,MTVEC_BASE,MRET);
+        cpu.step().unwrap();
+        let status = mstatus(&cpu);
+        assert_eq!(status & (MASK_MIE | MASK_MPIE),MASK_MPIE);
+        assert_eq!((status & MASK_MPP) >> 11,SUPERVISOR);
+        assert_eq!(cpu.csr.csrs[MCAUSE],9);
+        cpu.csr.csrs[MSTATUS] |= MASK_MPRV;
+        cpu.csr.csrs[MEPC] += 4;
+        cpu.step().unwrap();
+        assert_eq!((cpu.mode,cpu.pc),(SUPERVISOR,DRAM_BASE + 4));
+        let status = mstatus(&cpu);
+        assert_eq!(status & (MASK_MIE | MASK_MPIE),MASK_MIE | MASK_MPIE);
+        assert_eq!(status & (MASK_MPP | MASK_MPRV),0);
+    }
+
+    #[test]
+    fn sret_restores_and_honours_tsr(){
+        let mut cpu = hart(SUPERVISOR);
+        cpu.csr.csrs[MSTATUS] |= MASK_SPIE;
+        cpu.csr.csrs[SEPC] = DRAM_BASE + 0x100;
+        put(&cpu,DRAM_BASE,SRET);
+        cpu.step().unwrap();
+        assert_eq!((cpu.mode,cpu.pc),(USER,DRAM_BASE + 0x100));
+        assert_eq!(mstatus(&cpu) & (MASK_SIE | MASK_SPIE | MASK_SPP),MASK_SIE | MASK_SPIE);
+
+        let mut cpu = hart(SUPERVISOR);
+        cpu.csr.csrs[MSTATUS] |= MASK_TSR;
+        put(&cpu,DRAM_BASE,SRET);
+        cpu.step().unwrap();
+        assert_eq!((cpu.mode,cpu.pc),(MACHINE,MTVEC_BASE));
+        assert_eq!((cpu.csr.csrs[MCAUSE],cpu.csr.csrs[MTVAL]),(2,SRET));
+    }
+
+    #[test]
+    fn wfi_wakes_on_an_interrupt_masked_by_mie(){
+        let mut cpu = hart(MACHINE);
+        cpu.csr.csrs[MIE] = MASK_MTIP;
+        put(&cpu,DRAM_BASE,WFI);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.is_waiting());
+        assert_eq!(cpu.pc,DRAM_BASE + 4);
+        cpu.skip(10);
+        set_mtip(&cpu,true);
+        cpu.step().unwrap();
+        assert!(!cpu.is_waiting());
+        // MIE is clear, so the hart resumes rather than trapping
+        assert_eq!((cpu.pc,cpu.csr.csrs[MCAUSE]),(DRAM_BASE + 4,0));
+        // only the wfi retired, but every step and skipped step took a cycle
+        assert_eq!((cpu.csr.csrs[MCYCLE],cpu.csr.csrs[MINSTRET]),(13,1));
+    }
+
+    #[test]
+    fn an_exception_without_a_handler_is_fatal(){
+        let mut cpu = hart(MACHINE);
+        cpu.csr.csrs[MTVEC] = 0;
+        put(&cpu,DRAM_BASE,0);
+        assert!(matches!(cpu.step(),Err(Exception::IllegalInstruction(0))));
+    }
+
+    #[test]
+    fn seip_is_the_written_bit_or_the_plic_line(){
+        const CSRRS_MIP_T0: u32 = 0x3442_a073;
+        const CSRRC_MIP_T0: u32 = 0x3442_b073;
+        let seip = |cpu:&Cpu| cpu.csr.csrs[MIP] & MASK_SEIP != 0;
+        let mut cpu = hart(MACHINE);
+        for (i,inst) in [CSRRC_MIP_T0,NOP,CSRRS_MIP_T0,NOP,CSRRC_MIP_T0,NOP].into_iter().enumerate(){
+            put(&cpu,DRAM_BASE + 4 * i as u32,inst);
+        }
+        set_external(&cpu,false,true);
+        // clearing STIP writes back the software bit, not the line's level
+        cpu.set_reg(5,MASK_STIP);
+        cpu.step().unwrap();
+        assert!(seip(&cpu));
+        set_external(&cpu,false,false);
+        cpu.step().unwrap();
+        assert!(!seip(&cpu));
+        cpu.set_reg(5,MASK_SEIP);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(seip(&cpu));
+        set_external(&cpu,false,true);
+        cpu.step().unwrap();
+        assert!(seip(&cpu));
+        set_external(&cpu,false,false);
+        cpu.step().unwrap();
+        assert!(!seip(&cpu));
+    }
+
+    #[test]
+    fn rv64_only_encodings_are_illegal(){
+        // addiw a0, a0, 1; slliw a0, a0, 1; sd a0, 0(sp)
+        for inst in [0x0015_051b,0x0015_151b,0x00a1_3023]{
+            let mut cpu = hart(MACHINE);
+            put(&cpu,DRAM_BASE,inst);
+            cpu.step().unwrap();
+            assert_eq!((cpu.pc,cpu.csr.csrs[MCAUSE],cpu.csr.csrs[MTVAL]),(MTVEC_BASE,2,inst));
+        }
+    }
+
+    #[test]
+    fn jumps_to_halfword_targets_need_the_c_extension(){
+        // jal ra, 6; beq zero, zero, 6; jalr ra, 2(t0) with t0 at DRAM_BASE
+        for (inst,target,links) in [(0x0060_00ef,DRAM_BASE + 6,true),(0x0000_0363,DRAM_BASE + 6,false),(0x0022_80e7,DRAM_BASE + 2,true)]{
+            for c in [true,false]{
+                let mut cpu = hart(MACHINE);
+                if !c{
+                    cpu.csr.csrs[MISA] &= !(1 << 2);
+                }
+                cpu.set_reg(5,DRAM_BASE);
+                put(&cpu,DRAM_BASE,inst);
+                cpu.step().unwrap();
+                if c{
+                    assert_eq!((cpu.pc,cpu.reg(1)),(target,if links {DRAM_BASE + 4} else {0}));
+                } else{
+                    // the link register is left alone
+                    assert_eq!((cpu.pc,cpu.reg(1)),(MTVEC_BASE,0));
+                    assert_eq!((cpu.csr.csrs[MCAUSE],cpu.csr.csrs[MTVAL]),(0,target));
+                }
+            }
+        }
+    }
+
+    /// Page tables for the Sv32 tests: a root table, a second-level table
+    /// mapping VA's page and a data page it maps to.
+    const ROOT: u32 = DRAM_BASE + 0x10000;
+    const TABLE: u32 = ROOT + 0x1000;
+    const PAGE: u32 = ROOT + 0x2000;
+    const VA: u32 = 0x4000_0123;
+    const DIRTY: u32 = PTE_V | PTE_A | PTE_D;
+
+    /// A hart in `mode` with Sv32 on and VA's second-level table in place.
+    fn paged_hart(mode:u32) -> Cpu{
+        let mut cpu = hart(mode);
+        cpu.bus.borrow_mut().add_memory("tables",ROOT,Memory::new(0x3000,false)).unwrap();
+        put(&cpu,ROOT + (VA >> 22) * PTE_SIZE,(TABLE >> 12) << 10 | PTE_V);
+        set_satp(&mut cpu,ROOT >> 12);
+        cpu
+    }
+    fn set_satp(cpu:&mut Cpu,ppn:u32){
+        cpu.csr.store(SATP,MASK_MODE | ppn).unwrap();
+        cpu.csr_written(SATP);
+    }
+    /// Map VA's page to `ppn` with the `flags` of its PTE.
+    fn map(cpu:&mut Cpu,ppn:u32,flags:u32){
+        put(cpu,TABLE,ppn << 10 | flags);
+        cpu.tlb.flush_all();
+    }
+    fn leaf(cpu:&Cpu) -> u32{
+        cpu.bus.borrow_mut().load(TABLE,32).unwrap()
+    }
+
+    #[test]
+    fn supervisor_touches_user_pages_only_with_sum_and_never_runs_them(){
+        let mut cpu = paged_hart(SUPERVISOR);
+        map(&mut cpu,PAGE >> 12,DIRTY | PTE_R | PTE_W | PTE_X | PTE_U);
+        assert!(matches!(cpu.translate(VA,AccessType::Load),Err(Exception::LoadPageFault(VA))));
+        cpu.csr.csrs[MSTATUS] |= MASK_SUM;
+        assert_eq!(cpu.translate(VA,AccessType::Load).unwrap(),PAGE + 0x123);
+        assert_eq!(cpu.translate(VA,AccessType::Store).unwrap(),PAGE + 0x123);
+        assert!(matches!(cpu.translate(VA,AccessType::Instruction),Err(Exception::InstructionPageFault(VA))));
+        cpu.mode = USER;
+        assert_eq!(cpu.translate(VA,AccessType::Instruction).unwrap(),PAGE + 0x123);
+        map(&mut cpu,PAGE >> 12,DIRTY | PTE_R | PTE_W | PTE_X);
+        assert!(matches!(cpu.translate(VA,AccessType::Load),Err(Exception::LoadPageFault(VA))));
+    }
+
+    #[test]
+    fn mxr_makes_execute_only_pages_readable(){
+        let mut cpu = paged_hart(SUPERVISOR);
+        map(&mut cpu,PAGE >> 12,DIRTY | PTE_X);
+        assert!(matches!(cpu.translate(VA,AccessType::Load),Err(Exception::LoadPageFault(VA))));
+        cpu.csr.csrs[MSTATUS] |= MASK_MXR;
+        assert_eq!(cpu.translate(VA,AccessType::Load).unwrap(),PAGE + 0x123);
+        assert!(matches!(cpu.translate(VA,AccessType::Store),Err(Exception::StoreAMOPageFault(VA))));
+    }
+
+    #[test]
+    fn mprv_translates_machine_loads_and_stores_as_mpp(){
+        let mut cpu = paged_hart(MACHINE);
+        map(&mut cpu,PAGE >> 12,DIRTY | PTE_R | PTE_W | PTE_X);
+        assert_eq!(cpu.translate(VA,AccessType::Load).unwrap(),VA);
+        cpu.csr.csrs[MSTATUS] |= MASK_MPRV | SUPERVISOR << 11;
+        assert_eq!(cpu.translate(VA,AccessType::Load).unwrap(),PAGE + 0x123);
+        assert_eq!(cpu.translate(VA,AccessType::Store).unwrap(),PAGE + 0x123);
+        // fetches are never translated in M-mode
+        assert_eq!(cpu.translate(VA,AccessType::Instruction).unwrap(),VA);
+        // a U-mode access may not touch the supervisor page
+        cpu.csr.csrs[MSTATUS] &= !MASK_MPP;
+        assert!(matches!(cpu.translate(VA,AccessType::Store),Err(Exception::StoreAMOPageFault(VA))));
+    }
+
+    #[test]
+    fn accessed_and_dirty_bits_are_set_in_the_page_table(){
+        let mut cpu = paged_hart(SUPERVISOR);
+        let flags = PTE_V | PTE_R | PTE_W;
+        map(&mut cpu,PAGE >> 12,flags);
+        cpu.translate(VA,AccessType::Load).unwrap();
+        assert_eq!(leaf(&cpu),(PAGE >> 12) << 10 | flags | PTE_A);
+        // the cached entry has no D bit, so the store walks again to set it
+        cpu.translate(VA,AccessType::Store).unwrap();
+        assert_eq!(leaf(&cpu),(PAGE >> 12) << 10 | flags | PTE_A | PTE_D);
+        // so both were misses
+        cpu.translate(VA,AccessType::Load).unwrap();
+        assert_eq!(cpu.tlb_stats(),(1,2));
+        // W without R is reserved
+        map(&mut cpu,PAGE >> 12,DIRTY | PTE_W);
+        assert!(matches!(cpu.translate(VA,AccessType::Store),Err(Exception::StoreAMOPageFault(VA))));
+    }
+
+    #[test]
+    fn superpages_must_be_aligned(){
+        let mut cpu = paged_hart(SUPERVISOR);
+        let va = 0x0040_0123;
+        put(&cpu,ROOT + (va >> 22) * PTE_SIZE,(DRAM_BASE >> 12) << 10 | DIRTY | PTE_R);
+        assert_eq!(cpu.translate(va,AccessType::Load).unwrap(),DRAM_BASE + 0x123);
+        put(&cpu,ROOT + (va >> 22) * PTE_SIZE,((DRAM_BASE >> 12) + 1) << 10 | DIRTY | PTE_R);
+        cpu.tlb.flush_all();
+        assert!(matches!(cpu.translate(va,AccessType::Load),Err(Exception::LoadPageFault(0x0040_0123))));
+    }
+
+    #[test]
+    fn physical_addresses_beyond_32_bits_are_access_faults(){
+        let mut cpu = paged_hart(SUPERVISOR);
+        // a leaf mapping 0x1_0000_0000
+        map(&mut cpu,0x10_0000,DIRTY | PTE_R);
+        assert!(matches!(cpu.translate(VA,AccessType::Load),Err(Exception::LoadAccessFault(VA))));
+        // a pointer to a second-level table there
+        let va = 0x0080_0000;
+        put(&cpu,ROOT + (va >> 22) * PTE_SIZE,0x10_0000 << 10 | PTE_V);
+        assert!(matches!(cpu.translate(va,AccessType::Load),Err(Exception::LoadAccessFault(0x0080_0000))));
+        // a root table there
+        set_satp(&mut cpu,MASK_PPN);
+        assert!(matches!(cpu.translate(VA,AccessType::Store),Err(Exception::StoreAMOAccessFault(VA))));
+    }
+}
@@ -1,15 +1,33 @@
+use crate::cpu::{MACHINE,SUPERVISOR,USER};
 use crate::exceptions::Exception;
+use crate::pmp::{PMP_A,PMP_L,PMP_R,PMP_TOR,PMP_W};
 
 pub const NUM_CSRS: usize = 4096;
+// Unprivileged floating-point CSRs.
+/// Floating-point accrued exceptions.
+pub const FFLAGS: usize = 0x001;
+/// Floating-point dynamic rounding mode.
+pub const FRM: usize = 0x002;
+/// Floating-point control and status register (frm + fflags).
+pub const FCSR: usize = 0x003;
+
 // Machine-level CSRs.
 /// ISA that this cpu supported
 pub const MISA: usize = 0x301;
 /// Vendor ID
 pub const MVendorid: usize = 0xf11;
+/// Architecture ID.
+pub const MARCHID: usize = 0xf12;
+/// Implementation ID.
+pub const MIMPID: usize = 0xf13;
 /// Hardware thread ID.
 pub const MHARTID: usize = 0xf14;
+/// Pointer to the configuration data structure.
+pub const MCONFIGPTR: usize = 0xf15;
 /// Machine status register.
 pub const MSTATUS: usize = 0x300;
+/// Upper half of mstatus on RV32, all of whose fields are zero here.
+pub const MSTATUSH: usize = 0x310;
 /// Machine exception delefation register.
 pub const MEDELEG: usize = 0x302;
 /// Machine interrupt delefation register.
@@ -38,6 +56,8 @@ pub const SSTATUS: usize = 0x100;
 pub const SIE: usize = 0x104;
 /// Supervisor trap handler base address.
 pub const STVEC: usize = 0x105;
+/// Supervisor counter enable.
+pub const SCOUNTEREN: usize = 0x106;
 /// Scratch register for supervisor trap handlers.
 pub const SSCRATCH: usize = 0x140;
 /// Supervisor exception program counter.
@@ -51,6 +71,53 @@ pub const SIP: usize = 0x144;
 /// Supervisor address translation and protection.
 pub const SATP: usize = 0x180;
 
+// Physical memory protection: 16 entries, 4 configuration bytes per pmpcfg
+pub const PMPCFG0: usize = 0x3a0;
+pub const PMPCFG3: usize = 0x3a3;
+pub const PMPADDR0: usize = 0x3b0;
+pub const PMPADDR15: usize = 0x3bf;
+pub const NUM_PMP: usize = 16;
+
+// Counters and timers. Counter i is mcycle + i, with its upper half 0x80
+// above and its unprivileged read-only alias 0x100 above; time is read from
+// the CLINT, so there is no mtime CSR.
+pub const CYCLE: usize = 0xc00;
+pub const TIME: usize = 0xc01;
+pub const HPMCOUNTER31: usize = 0xc1f;
+pub const CYCLEH: usize = 0xc80;
+pub const TIMEH: usize = 0xc81;
+pub const HPMCOUNTER31H: usize = 0xc9f;
+pub const MCYCLE: usize = 0xb00;
+pub const MINSTRET: usize = 0xb02;
+pub const MHPMCOUNTER3: usize = 0xb03;
+pub const MHPMCOUNTER31: usize = 0xb1f;
+pub const MCYCLEH: usize = 0xb80;
+pub const MINSTRETH: usize = 0xb82;
+pub const MHPMCOUNTER3H: usize = 0xb83;
+pub const MHPMCOUNTER31H: usize = 0xb9f;
+/// Machine counter-inhibit register.
+pub const MCOUNTINHIBIT: usize = 0x320;
+/// Event selectors of mhpmcounter3..31.
+pub const MHPMEVENT3: usize = 0x323;
+pub const MHPMEVENT31: usize = 0x33f;
+const COUNTER_CYCLE: usize = 0;
+const COUNTER_INSTRET: usize = 2;
+const FIRST_HPM_COUNTER: usize = 3;
+const NUM_COUNTERS: usize = 32;
+
+// Events mhpmevent can select; any other value reads back as 0, counting nothing.
+/// Retired conditional branches.
+pub const EVENT_BRANCH: u32 = 1;
+/// Retired load instructions, integer or floating-point.
+pub const EVENT_LOAD: u32 = 2;
+/// Retired store instructions, integer or floating-point.
+pub const EVENT_STORE: u32 = 3;
+/// Exceptions and interrupts taken.
+pub const EVENT_TRAP: u32 = 4;
+/// Address translations that missed the TLB and walked the page tables.
+pub const EVENT_TLB_MISS: u32 = 5;
+const LAST_EVENT: u32 = EVENT_TLB_MISS;
+
 
 // mstatus and sstatus field mask
 pub const MASK_SIE: u32 = 1 << 1; 
@@ -69,8 +136,18 @@ pub const MASK_MXR: u32 = 1 << 19;
 pub const MASK_TVM: u32 = 1 << 20;
 pub const MASK_TW: u32 = 1 << 21;
 pub const MASK_TSR: u32 = 1 << 22;
+pub const MASK_SD: u32 = 1 << 31;
 pub const MASK_SSTATUS: u32 = MASK_SIE | MASK_SPIE | MASK_UBE | MASK_SPP | MASK_FS 
-                            | MASK_XS  | MASK_SUM  | MASK_MXR ;
+                            | MASK_XS  | MASK_SUM  | MASK_MXR | MASK_SD;
+
+// mstatus.FS states
+pub const FS_OFF: u32 = 0;
+pub const FS_INITIAL: u32 = 1 << 13;
+pub const FS_DIRTY: u32 = 3 << 13;
+
+// fcsr fields
+pub const MASK_FFLAGS: u32 = 0x1f;
+pub const MASK_FRM: u32 = 0x7 << 5;
 
 
 // MIP / SIP field mask
@@ -80,24 +157,55 @@ pub const MASK_STIP: u32 = 1 << 5;
 pub const MASK_MTIP: u32 = 1 << 7;
 pub const MASK_SEIP: u32 = 1 << 9;
 pub const MASK_MEIP: u32 = 1 << 11;
+/// Interrupts every hart has.
+pub const MASK_MACHINE_INTERRUPTS: u32 = MASK_MSIP | MASK_MTIP | MASK_MEIP;
+/// Interrupts that can be delegated to S-mode.
+pub const MASK_DELEGABLE_INTERRUPTS: u32 = MASK_SSIP | MASK_STIP | MASK_SEIP;
+/// mip bits software can write; the rest follow the CLINT and the PLIC.
+/// SEIP reads as the written bit ORed with the PLIC's line.
+pub const MASK_MIP_WRITABLE: u32 = MASK_SSIP | MASK_STIP | MASK_SEIP;
+/// Exceptions that can be delegated to S-mode: all but the M-mode ecall
+/// and the reserved causes 10 and 14.
+pub const MASK_DELEGABLE_EXCEPTIONS: u32 = 0xb3ff;
 
 // MMU related(SV32)
 pub const MASK_PPN: u32 = 0x3fffff;
+pub const MASK_ASID: u32 = 0x1ff << 22;
 pub const MASK_MODE: u32 = 1 << 31;
 
 pub struct Csr{
-    pub csrs: [u32;NUM_CSRS]
+    pub csrs: [u32;NUM_CSRS],
+    /// Counters written by the current instruction, which it does not
+    /// also advance.
+    written_counters: u32,
+    /// Events counted by some uninhibited hpmcounter.
+    active_events: u32,
+    /// SEIP as software last wrote it, and the PLIC's S-mode line.
+    seip: bool,
+    seip_line: bool
 }
 
 impl Csr{
-    pub fn new() -> Self{
-        Self{csrs:[0;NUM_CSRS]}
+    /// The CSRs of a hart implementing the extensions in `misa`.
+    pub fn new(misa:u32) -> Self{
+        let mut csrs = [0;NUM_CSRS];
+        csrs[MISA] = misa;
+        let mut csr = Self{csrs,written_counters:0,active_events:0,seip:false,seip_line:false};
+        // let bare-metal programs use the FPU without enabling it first
+        if csr.has_extension('f'){
+            csr.csrs[MSTATUS] = FS_INITIAL;
+        }
+        csr
     }
     pub fn load(&self,addr:usize) -> Result<u32,Exception>{
         match addr{
+            FFLAGS | FRM | FCSR if !self.fp_enabled() => Err(Exception::IllegalInstruction(addr as u32)),
+            FFLAGS => Ok(self.csrs[FCSR] & MASK_FFLAGS),
+            FRM => Ok((self.csrs[FCSR] & MASK_FRM) >> 5),
             SIE => Ok(self.csrs[MIE] & self.csrs[MIDELEG]),
             SIP => Ok(self.csrs[MIP] & self.csrs[MIDELEG]),
             SSTATUS => Ok(self.csrs[MSTATUS] & MASK_SSTATUS),
+            CYCLE..=HPMCOUNTER31 | CYCLEH..=HPMCOUNTER31H => Ok(self.csrs[addr - 0x100]),
             0..=4095 => Ok(self.csrs[addr]),
             _ => Err(Exception::IllegalInstruction(addr as u32))
         }
@@ -105,13 +213,277 @@ impl Csr{
 
     pub fn store(&mut self,addr:usize,value:u32) -> Result<(),Exception>{
         match addr{
-            SIE => Ok(self.csrs[MIE] = (self.csrs[MIE] & !self.csrs[MIDELEG]) | (value & self.csrs[MIDELEG])),
-            SIP => Ok(self.csrs[MIP] = (self.csrs[MIE] & !self.csrs[MIDELEG]) | (value & self.csrs[MIDELEG])),
-            SSTATUS => Ok(self.csrs[MSTATUS] = (self.csrs[MSTATUS] & !MASK_SSTATUS) | (value & MASK_SSTATUS)),
-            0..=4095 => Ok(self.csrs[addr] = value),
+            FFLAGS | FRM | FCSR if !self.fp_enabled() => Err(Exception::IllegalInstruction(addr as u32)),
+            FFLAGS => {
+                self.set_fcsr((self.csrs[FCSR] & !MASK_FFLAGS) | (value & MASK_FFLAGS));
+                Ok(())
+            }
+            FRM => {
+                self.set_fcsr((self.csrs[FCSR] & !MASK_FRM) | (value << 5));
+                Ok(())
+            }
+            FCSR => {
+                self.set_fcsr(value);
+                Ok(())
+            }
+            SIE => {
+                self.csrs[MIE] = (self.csrs[MIE] & !self.csrs[MIDELEG]) | (value & self.csrs[MIDELEG]);
+                Ok(())
+            }
+            SIP => {
+                // only SSIP is writable through sip, and only if delegated
+                let mask = self.csrs[MIDELEG] & MASK_SSIP;
+                self.csrs[MIP] = (self.csrs[MIP] & !mask) | (value & mask);
+                Ok(())
+            }
+            SSTATUS => {
+                self.write_status((self.csrs[MSTATUS] & !MASK_SSTATUS) | (value & MASK_SSTATUS));
+                Ok(())
+            }
+            MSTATUS => {
+                self.write_status(value);
+                Ok(())
+            }
+            // the extensions are fixed by the machine description
+            MISA | MSTATUSH => Ok(()),
+            MEDELEG | MIDELEG | MIE | MIP | MTVEC | STVEC | MEPC | SEPC => {
+                self.csrs[addr] = match addr{
+                    // without S-mode there is nothing to delegate to
+                    MEDELEG => value & MASK_DELEGABLE_EXCEPTIONS & self.supervisor_mask(),
+                    MIDELEG => value & MASK_DELEGABLE_INTERRUPTS & self.supervisor_mask(),
+                    MIE => value & self.interrupts(),
+                    MIP => {
+                        let value = value & self.interrupts();
+                        self.seip = value & MASK_SEIP != 0;
+                        let seip = if self.seip || self.seip_line {MASK_SEIP} else {0};
+                        (self.csrs[MIP] & !MASK_MIP_WRITABLE) | (value & (MASK_SSIP | MASK_STIP)) | seip
+                    }
+                    // direct and vectored are the only modes
+                    MTVEC | STVEC => value & !0b10,
+                    // without C, instructions and so trap return addresses are 4-byte aligned
+                    _ => value & if self.has_extension('c') {!0b1} else {!0b11}
+                };
+                Ok(())
+            }
+            PMPCFG0..=PMPCFG3 => {
+                self.set_pmpcfg(addr,value);
+                Ok(())
+            }
+            PMPADDR0..=PMPADDR15 => {
+                self.set_pmpaddr(addr - PMPADDR0,value);
+                Ok(())
+            }
+            MCYCLE..=MHPMCOUNTER31 | MCYCLEH..=MHPMCOUNTER31H => {
+                self.csrs[addr] = value;
+                self.written_counters |= 1 << (addr & 0x1f);
+                Ok(())
+            }
+            MCOUNTINHIBIT | MHPMEVENT3..=MHPMEVENT31 => {
+                self.csrs[addr] = match addr{
+                    // time cannot be inhibited
+                    MCOUNTINHIBIT => value & !0b10,
+                    _ => if value <= LAST_EVENT {value} else {0}
+                };
+                self.update_active_events();
+                Ok(())
+            }
+            0..=4095 => {
+                self.csrs[addr] = value;
+                Ok(())
+            }
             _ => Err(Exception::IllegalInstruction(addr as u32))
         }
     }
+    fn has_extension(&self,letter:char) -> bool{
+        self.csrs[MISA] & (1 << (letter as u32 - 'a' as u32)) != 0
+    }
+    /// All ones with S-mode, for fields that only exist with it, else 0.
+    fn supervisor_mask(&self) -> u32{
+        if self.has_extension('s') {u32::MAX} else {0}
+    }
+    /// The interrupts the hart implements, in the layout of mie and mip.
+    fn interrupts(&self) -> u32{
+        MASK_MACHINE_INTERRUPTS | (MASK_DELEGABLE_INTERRUPTS & self.supervisor_mask())
+    }
+    /// The mstatus fields software can write; the others, including those
+    /// of modes and extensions the hart lacks, are hardwired to zero.
+    fn status_writable(&self) -> u32{
+        let mut mask = MASK_MIE | MASK_MPIE | MASK_MPP;
+        if self.has_extension('s'){
+            mask |= MASK_SIE | MASK_SPIE | MASK_SPP | MASK_SUM | MASK_MXR | MASK_TVM | MASK_TSR;
+        }
+        if self.has_extension('u'){
+            mask |= MASK_MPRV | MASK_TW;
+        }
+        if self.has_extension('f'){
+            mask |= MASK_FS;
+        }
+        mask
+    }
+    /// Whether a CSR exists on this hart.
+    fn is_implemented(&self,addr:usize) -> bool{
+        match addr{
+            FFLAGS | FRM | FCSR => self.has_extension('f'),
+            SSTATUS | SIE | STVEC | SCOUNTEREN | SSCRATCH | SEPC | SCAUSE | STVAL | SIP | SATP => self.has_extension('s'),
+            MCOUNTEREN => self.has_extension('u'),
+            MCYCLE | MINSTRET | MHPMCOUNTER3..=MHPMCOUNTER31
+            | MCYCLEH | MINSTRETH | MHPMCOUNTER3H..=MHPMCOUNTER31H
+            | MCOUNTINHIBIT | MHPMEVENT3..=MHPMEVENT31
+            | CYCLE..=HPMCOUNTER31 | CYCLEH..=HPMCOUNTER31H => true,
+            MVendorid | MARCHID | MIMPID | MHARTID | MCONFIGPTR
+            | MSTATUS | MISA | MEDELEG | MIDELEG | MIE | MTVEC | MSTATUSH
+            | MSCRATCH | MEPC | MCAUSE | MTVAL | MIP
+            | PMPCFG0..=PMPCFG3 | PMPADDR0..=PMPADDR15 => true,
+            _ => false
+        }
+    }
+    /// Whether a CSR instruction in privilege `mode` may read the CSR at
+    /// `addr`, and also write it if `write`. Bits 9:8 of the address give
+    /// the lowest privilege allowed and bits 11:10 == 0b11 mark it read-only.
+    /// With mstatus.TVM set, S-mode may not touch satp either, and S- and
+    /// U-mode read only the counters mcounteren (and for U-mode scounteren)
+    /// enable.
+    pub fn is_accessible(&self,addr:usize,mode:u32,write:bool) -> bool{
+        let privilege = ((addr >> 8) & 0b11) as u32;
+        let read_only = addr >> 10 == 0b11;
+        let trapped = match addr{
+            SATP => mode == SUPERVISOR && self.csrs[MSTATUS] & MASK_TVM != 0,
+            CYCLE..=HPMCOUNTER31 | CYCLEH..=HPMCOUNTER31H => {
+                let bit = 1 << (addr & 0x1f);
+                let scounteren = if self.has_extension('s') {self.csrs[SCOUNTEREN]} else {u32::MAX};
+                (mode < MACHINE && self.csrs[MCOUNTEREN] & bit == 0) || (mode == USER && scounteren & bit == 0)
+            }
+            _ => false
+        };
+        self.is_implemented(addr) && mode >= privilege && !(write && read_only) && !trapped
+    }
+    /// The 64-bit value of counter `i`.
+    fn counter(&self,i:usize) -> u64{
+        (self.csrs[MCYCLEH + i] as u64) << 32 | self.csrs[MCYCLE + i] as u64
+    }
+    /// Advance counter `i` unless it is inhibited or was just written.
+    fn increment_counter(&mut self,i:usize,by:u64){
+        if (self.csrs[MCOUNTINHIBIT] | self.written_counters) & (1 << i) != 0{
+            return;
+        }
+        let value = self.counter(i).wrapping_add(by);
+        self.csrs[MCYCLE + i] = value as u32;
+        self.csrs[MCYCLEH + i] = (value >> 32) as u32;
+    }
+    /// Advance mcycle, and minstret if an instruction `retired` rather
+    /// than trapped, at the end of a step.
+    pub fn advance_counters(&mut self,retired:bool){
+        self.increment_counter(COUNTER_CYCLE,1);
+        if retired{
+            self.increment_counter(COUNTER_INSTRET,1);
+        }
+        self.written_counters = 0;
+    }
+    /// Advance mcycle by the `cycles` steps a waiting hart skipped.
+    pub fn skip_cycles(&mut self,cycles:u64){
+        self.increment_counter(COUNTER_CYCLE,cycles);
+    }
+    /// Advance the hpmcounters selecting `event`.
+    pub fn count_event(&mut self,event:u32){
+        if self.active_events & (1 << event) == 0{
+            return;
+        }
+        for i in FIRST_HPM_COUNTER..NUM_COUNTERS{
+            if self.csrs[MHPMEVENT3 + i - FIRST_HPM_COUNTER] == event{
+                self.increment_counter(i,1);
+            }
+        }
+    }
+    fn update_active_events(&mut self){
+        self.active_events = (FIRST_HPM_COUNTER..NUM_COUNTERS)
+            .filter(|i| self.csrs[MCOUNTINHIBIT] & (1 << i) == 0)
+            .fold(0,|events,i| events | 1 << self.csrs[MHPMEVENT3 + i - FIRST_HPM_COUNTER]);
+    }
+    /// Write mstatus from software: fields that are not writable stay zero
+    /// and an MPP naming a mode the hart lacks keeps its old value.
+    fn write_status(&mut self,value:u32){
+        let mut status = value & self.status_writable();
+        let mpp = (status & MASK_MPP) >> 11;
+        let legal = match mpp{
+            MACHINE => true,
+            SUPERVISOR => self.has_extension('s'),
+            USER => self.has_extension('u'),
+            _ => false
+        };
+        if !legal{
+            status = (status & !MASK_MPP) | (self.csrs[MSTATUS] & MASK_MPP);
+        }
+        self.set_status(status);
+    }
+    /// Write mstatus, keeping the read-only SD summary bit in sync with FS.
+    fn set_status(&mut self,value:u32){
+        let sd = if value & MASK_FS == FS_DIRTY {MASK_SD} else {0};
+        self.csrs[MSTATUS] = (value & !MASK_SD) | sd;
+    }
+    /// Configuration byte of PMP entry `i`.
+    pub fn pmpcfg(&self,i:usize) -> u8{
+        (self.csrs[PMPCFG0 + i / 4] >> (8 * (i % 4))) as u8
+    }
+    /// Write the four configuration bytes in a pmpcfg register, except
+    /// those of locked entries. The reserved R=0 W=1 combination reads as
+    /// neither.
+    fn set_pmpcfg(&mut self,addr:usize,value:u32){
+        let mut cfgs = self.csrs[addr].to_le_bytes();
+        for (i,cfg) in cfgs.iter_mut().enumerate(){
+            if *cfg & PMP_L != 0{
+                continue;
+            }
+            let mut new = (value >> (8 * i)) as u8 & !0x60;
+            if new & (PMP_R | PMP_W) == PMP_W{
+                new &= !PMP_W;
+            }
+            *cfg = new;
+        }
+        self.csrs[addr] = u32::from_le_bytes(cfgs);
+    }
+    /// Write pmpaddr`i` unless its entry is locked, or the next entry is a
+    /// locked TOR range using it as its base.
+    fn set_pmpaddr(&mut self,i:usize,value:u32){
+        let next = (i + 1 < NUM_PMP).then(|| self.pmpcfg(i + 1));
+        let locked = self.pmpcfg(i) & PMP_L != 0
+            || next.is_some_and(|cfg| cfg & PMP_L != 0 && cfg & PMP_A == PMP_TOR);
+        if !locked{
+            self.csrs[PMPADDR0 + i] = value;
+        }
+    }
+    /// Write fcsr, which modifies the floating-point state.
+    fn set_fcsr(&mut self,value:u32){
+        self.csrs[FCSR] = value & (MASK_FRM | MASK_FFLAGS);
+        self.set_fp_dirty();
+    }
+    /// Drive a device-controlled mip bit to `level`.
+    pub fn set_interrupt_line(&mut self,mask:u32,level:bool){
+        if mask == MASK_SEIP{
+            self.seip_line = level;
+        }
+        if level || (mask == MASK_SEIP && self.seip){
+            self.csrs[MIP] |= mask;
+        } else{
+            self.csrs[MIP] &= !mask;
+        }
+    }
+    /// mip as csrrs and csrrc modify it, with SEIP as software wrote it
+    /// rather than ORed with the PLIC's line.
+    pub fn written_mip(&self) -> u32{
+        (self.csrs[MIP] & !MASK_SEIP) | if self.seip {MASK_SEIP} else {0}
+    }
+    /// OR the exceptions raised by a floating-point instruction into fflags.
+    pub fn accrue_fflags(&mut self,flags:u32){
+        self.csrs[FCSR] |= flags & MASK_FFLAGS;
+    }
+    /// Whether mstatus.FS allows floating-point instructions and CSRs.
+    pub fn fp_enabled(&self) -> bool{
+        self.csrs[MSTATUS] & MASK_FS != FS_OFF
+    }
+    /// Record that the floating-point state has been modified.
+    pub fn set_fp_dirty(&mut self){
+        self.set_status(self.csrs[MSTATUS] | FS_DIRTY);
+    }
     pub fn is_medelegate(&self,cause:u32) -> bool{
         (self.csrs[MEDELEG].wrapping_shr(cause) & 1) == 1
     }
@@ -119,4 +491,140 @@ impl Csr{
         (self.csrs[MIDELEG].wrapping_shr(cause) & 1) == 1
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// The CSRs of a hart with the extensions named in `isa`.
+    fn with_isa(isa:&str) -> Csr{
+        Csr::new(isa.bytes().fold(1 << 30,|misa,c| misa | 1 << (c - b'a')))
+    }
+    /// Write `value` to `addr` and read it back.
+    fn write(csr:&mut Csr,addr:usize,value:u32) -> u32{
+        csr.store(addr,value).unwrap();
+        csr.load(addr).unwrap()
+    }
+
+    #[test]
+    fn access_needs_the_privilege_encoded_in_the_address(){
+        let csr = with_isa("imafdcsu");
+        assert!(csr.is_accessible(MSCRATCH,MACHINE,true));
+        assert!(!csr.is_accessible(MSCRATCH,SUPERVISOR,false));
+        assert!(csr.is_accessible(SSCRATCH,SUPERVISOR,true));
+        assert!(!csr.is_accessible(SSCRATCH,USER,false));
+        assert!(csr.is_accessible(FCSR,USER,true));
+        // the 0b11 read-only range
+        assert!(csr.is_accessible(MHARTID,MACHINE,false));
+        assert!(!csr.is_accessible(MHARTID,MACHINE,true));
+        assert!(!csr.is_accessible(CYCLE,MACHINE,true));
+    }
+
+    #[test]
+    fn csrs_of_missing_extensions_do_not_exist(){
+        let csr = with_isa("imac");
+        for addr in [SSTATUS,SIE,SATP,SCOUNTEREN,MCOUNTEREN,FFLAGS,FCSR]{
+            assert!(!csr.is_accessible(addr,MACHINE,false),"{:#x}",addr);
+        }
+        assert!(!csr.is_accessible(0x7c0,MACHINE,false));
+    }
+
+    #[test]
+    fn tvm_and_counter_enables_trap_lower_modes(){
+        let mut csr = with_isa("imafdcsu");
+        csr.store(MSTATUS,MASK_TVM).unwrap();
+        assert!(!csr.is_accessible(SATP,SUPERVISOR,false));
+        assert!(csr.is_accessible(SATP,MACHINE,true));
+        assert!(!csr.is_accessible(CYCLE,SUPERVISOR,false));
+        csr.store(MCOUNTEREN,0b001).unwrap();
+        assert!(csr.is_accessible(CYCLE,SUPERVISOR,false));
+        assert!(!csr.is_accessible(CYCLE,USER,false));
+        assert!(!csr.is_accessible(TIME,SUPERVISOR,false));
+        csr.store(SCOUNTEREN,0b001).unwrap();
+        assert!(csr.is_accessible(CYCLE,USER,false));
+    }
+
+    #[test]
+    fn mie_and_mip_hold_only_implemented_interrupts(){
+        let mut csr = with_isa("imafdcsu");
+        assert_eq!(write(&mut csr,MIE,u32::MAX),MASK_MACHINE_INTERRUPTS | MASK_DELEGABLE_INTERRUPTS);
+        // the machine-level pending bits follow the CLINT and PLIC
+        assert_eq!(write(&mut csr,MIP,u32::MAX),MASK_MIP_WRITABLE);
+        let mut csr = with_isa("imac");
+        assert_eq!(write(&mut csr,MIE,u32::MAX),MASK_MACHINE_INTERRUPTS);
+        assert_eq!(write(&mut csr,MIP,u32::MAX),0);
+        assert_eq!(write(&mut csr,MEDELEG,u32::MAX),0);
+        assert_eq!(write(&mut csr,MIDELEG,u32::MAX),0);
+    }
+
+    #[test]
+    fn delegation_is_limited_to_delegable_causes(){
+        let mut csr = with_isa("imafdcsu");
+        assert_eq!(write(&mut csr,MEDELEG,u32::MAX),MASK_DELEGABLE_EXCEPTIONS);
+        assert_eq!(write(&mut csr,MIDELEG,u32::MAX),MASK_DELEGABLE_INTERRUPTS);
+        // sie and sip only show delegated interrupts
+        csr.store(MIE,u32::MAX).unwrap();
+        csr.store(MIDELEG,MASK_STIP).unwrap();
+        assert_eq!(csr.load(SIE).unwrap(),MASK_STIP);
+    }
+
+    #[test]
+    fn mstatus_fields_follow_misa(){
+        let mut csr = with_isa("imafdcsu");
+        let all = MASK_SIE | MASK_MIE | MASK_SPIE | MASK_MPIE | MASK_SPP | MASK_FS | MASK_MPRV
+            | MASK_SUM | MASK_MXR | MASK_TVM | MASK_TW | MASK_TSR;
+        assert_eq!(write(&mut csr,MSTATUS,u32::MAX) & !(MASK_MPP | MASK_SD),all);
+        // sstatus is a view of the S-mode fields
+        assert_eq!(csr.load(SSTATUS).unwrap() & !MASK_SD,all & MASK_SSTATUS);
+
+        let mut csr = with_isa("imac");
+        assert_eq!(csr.load(MSTATUS).unwrap(),0);
+        assert_eq!(write(&mut csr,MSTATUS,u32::MAX),MASK_MIE | MASK_MPIE | MASK_MPP);
+        let mut csr = with_isa("imacu");
+        assert_eq!(write(&mut csr,MSTATUS,u32::MAX) & !MASK_MPP,MASK_MIE | MASK_MPIE | MASK_MPRV | MASK_TW);
+    }
+
+    #[test]
+    fn mpp_keeps_its_value_for_missing_modes(){
+        let mut csr = with_isa("imacu");
+        assert_eq!(write(&mut csr,MSTATUS,USER << 11) & MASK_MPP,USER << 11);
+        assert_eq!(write(&mut csr,MSTATUS,SUPERVISOR << 11) & MASK_MPP,USER << 11);
+        assert_eq!(write(&mut csr,MSTATUS,2 << 11) & MASK_MPP,USER << 11);
+    }
+
+    #[test]
+    fn trap_vectors_and_return_addresses_are_warl(){
+        let mut csr = with_isa("imafdcsu");
+        assert_eq!(write(&mut csr,MTVEC,0x8000_0003),0x8000_0001);
+        assert_eq!(write(&mut csr,STVEC,0x8000_0002),0x8000_0000);
+        assert_eq!(write(&mut csr,MEPC,0x8000_0003),0x8000_0002);
+        let mut csr = with_isa("ima");
+        assert_eq!(write(&mut csr,MEPC,0x8000_0003),0x8000_0000);
+    }
+
+    #[test]
+    fn fcsr_fields_are_views_of_one_register(){
+        let mut csr = with_isa("imafdcsu");
+        assert_eq!(write(&mut csr,FCSR,u32::MAX),MASK_FRM | MASK_FFLAGS);
+        assert_eq!(write(&mut csr,FRM,0b010),0b010);
+        assert_eq!(write(&mut csr,FFLAGS,0),0);
+        assert_eq!(csr.load(FCSR).unwrap(),0b010 << 5);
+        // writing any of them dirties the floating-point state
+        assert_eq!(csr.load(MSTATUS).unwrap() & (MASK_FS | MASK_SD),FS_DIRTY | MASK_SD);
+        csr.store(MSTATUS,FS_OFF).unwrap();
+        assert!(csr.load(FCSR).is_err());
+    }
+
+    #[test]
+    fn counter_controls_are_warl(){
+        let mut csr = with_isa("imafdcsu");
+        // time cannot be inhibited
+        assert_eq!(write(&mut csr,MCOUNTINHIBIT,u32::MAX),!0b10);
+        assert_eq!(write(&mut csr,MHPMEVENT3,EVENT_TLB_MISS),EVENT_TLB_MISS);
+        assert_eq!(write(&mut csr,MHPMEVENT3,LAST_EVENT + 1),0);
+        // writes to the read-only views are refused by is_accessible; the
+        // machine counters themselves are plain registers
+        assert_eq!(write(&mut csr,MCYCLE,7),7);
+        assert_eq!(csr.load(CYCLE).unwrap(),7);
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::{Condvar,Mutex};
+use std::time::Duration;
+use crate::exceptions::Exception;
+use crate::fdt::Fdt;
+
+/// A memory-mapped device. Addresses are offsets from the base the device
+/// is mapped at; any error is reported to the hart as an access fault at
+/// the absolute address.
+pub trait Device{
+    fn load(&mut self,offset:u32,size:u32) -> Result<u32,Exception>;
+    fn store(&mut self,offset:u32,size:u32,value:u32) -> Result<(),Exception>;
+    /// Return to the power-on state.
+    fn reset(&mut self){}
+    /// Advance by one instruction.
+    fn tick(&mut self){}
+    /// Level of the device's interrupt line, routed to the PLIC source
+    /// given when the device was attached.
+    fn is_interrupting(&self) -> bool{
+        false
+    }
+    /// Generic node name and `compatible` string of the device in the
+    /// guest's device tree, which leaves the device out if there are none.
+    fn fdt_name(&self) -> Option<(&'static str,&'static str)>{
+        None
+    }
+    /// Properties of the device's node beyond `compatible`, `reg` and its
+    /// interrupt.
+    fn fdt_properties(&self,_fdt:&mut Fdt){}
+}
+
+/// Lets devices fed from host threads, like a UART reading stdin, wake the
+/// machine while every hart waits for an interrupt.
+#[derive(Default)]
+pub struct Wakeup{
+    /// Set by `notify` until a wait consumes it.
+    pending: Mutex<bool>,
+    cond: Condvar
+}
+
+impl Wakeup{
+    pub fn notify(&self){
+        *self.pending.lock().unwrap() = true;
+        self.cond.notify_all();
+    }
+    /// Block until notified, or for at most `timeout` if one is given.
+    /// Returns at once if notified since the last wait.
+    pub fn wait(&self,timeout:Option<Duration>){
+        let pending = self.pending.lock().unwrap();
+        let mut pending = match timeout{
+            Some(timeout) => self.cond.wait_timeout_while(pending,timeout,|p| !*p).unwrap().0,
+            None => self.cond.wait_while(pending,|p| !*p).unwrap()
+        };
+        *pending = false;
+    }
+}
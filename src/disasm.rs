@@ -0,0 +1,208 @@
+// Disassembly of 32-bit instructions for the instruction trace, in the
+// assembler's syntax with ABI register names. Branch and jump targets are
+// shown relative to the pc, as Spike does.
+
+use crate::csr::*;
+
+const X_NAMES: [&str;32] = [
+    "zero","ra","sp","gp","tp","t0","t1","t2","s0","s1","a0","a1","a2","a3","a4","a5",
+    "a6","a7","s2","s3","s4","s5","s6","s7","s8","s9","s10","s11","t3","t4","t5","t6"
+];
+
+const F_NAMES: [&str;32] = [
+    "ft0","ft1","ft2","ft3","ft4","ft5","ft6","ft7","fs0","fs1","fa0","fa1","fa2","fa3","fa4","fa5",
+    "fa6","fa7","fs2","fs3","fs4","fs5","fs6","fs7","fs8","fs9","fs10","fs11","ft8","ft9","ft10","ft11"
+];
+
+fn x(r:u32) -> &'static str{
+    X_NAMES[r as usize]
+}
+
+fn f(r:u32) -> &'static str{
+    F_NAMES[r as usize]
+}
+
+/// The name of a CSR, or its number if it has none here.
+fn csr_name(addr:u32) -> String{
+    let name = match addr as usize{
+        FFLAGS => "fflags",
+        FRM => "frm",
+        FCSR => "fcsr",
+        CYCLE => "cycle",
+        TIME => "time",
+        CYCLEH => "cycleh",
+        TIMEH => "timeh",
+        SSTATUS => "sstatus",
+        SIE => "sie",
+        STVEC => "stvec",
+        SCOUNTEREN => "scounteren",
+        SSCRATCH => "sscratch",
+        SEPC => "sepc",
+        SCAUSE => "scause",
+        STVAL => "stval",
+        SIP => "sip",
+        SATP => "satp",
+        MSTATUS => "mstatus",
+        MISA => "misa",
+        MEDELEG => "medeleg",
+        MIDELEG => "mideleg",
+        MIE => "mie",
+        MTVEC => "mtvec",
+        MCOUNTEREN => "mcounteren",
+        MSTATUSH => "mstatush",
+        MSCRATCH => "mscratch",
+        MEPC => "mepc",
+        MCAUSE => "mcause",
+        MTVAL => "mtval",
+        MIP => "mip",
+        MCYCLE => "mcycle",
+        MCYCLEH => "mcycleh",
+        MHARTID => "mhartid",
+        _ => return format!("{:#x}",addr)
+    };
+    name.to_string()
+}
+
+/// A pc-relative target, like `pc + 8`.
+fn target(offset:i32) -> String{
+    if offset < 0 {format!("pc - {}",offset.unsigned_abs())} else {format!("pc + {}",offset)}
+}
+
+/// The .aq/.rl suffix of an AMO.
+fn ordering(inst:u32) -> &'static str{
+    match (inst >> 25) & 0b11{
+        0b11 => ".aqrl",
+        0b10 => ".aq",
+        0b01 => ".rl",
+        _ => ""
+    }
+}
+
+/// Disassemble a 32-bit instruction; compressed ones are disassembled
+/// after expansion.
+pub fn disassemble(inst:u32) -> String{
+    let opcode = inst & 0x7f;
+    let rd = (inst >> 7) & 0x1f;
+    let funct3 = (inst >> 12) & 0x7;
+    let rs1 = (inst >> 15) & 0x1f;
+    let rs2 = (inst >> 20) & 0x1f;
+    let funct7 = inst >> 25;
+    let i_imm = inst as i32 >> 20;
+    let s_imm = ((inst & 0xfe000000) as i32 >> 20) | ((inst >> 7) & 0x1f) as i32;
+    let b_imm = ((inst & 0x80000000) as i32 >> 19) | ((inst & 0x80) << 4) as i32
+        | ((inst >> 20) & 0x7e0) as i32 | ((inst >> 7) & 0x1e) as i32;
+    let j_imm = ((inst & 0x80000000) as i32 >> 11) | (inst & 0xff000) as i32
+        | ((inst >> 9) & 0x800) as i32 | ((inst >> 20) & 0x7fe) as i32;
+    let fmt = if funct7 & 0b11 == 1 {"d"} else {"s"};
+    let unknown = || "unknown".to_string();
+    match opcode{
+        0x37 => format!("lui {}, {:#x}",x(rd),inst >> 12),
+        0x17 => format!("auipc {}, {:#x}",x(rd),inst >> 12),
+        0x6f => format!("jal {}, {}",x(rd),target(j_imm)),
+        0x67 => format!("jalr {}, {}({})",x(rd),i_imm,x(rs1)),
+        0x63 => match ["beq","bne","","","blt","bge","bltu","bgeu"][funct3 as usize]{
+            "" => unknown(),
+            name => format!("{} {}, {}, {}",name,x(rs1),x(rs2),target(b_imm))
+        }
+        0x03 => match ["lb","lh","lw","","lbu","lhu","",""][funct3 as usize]{
+            "" => unknown(),
+            name => format!("{} {}, {}({})",name,x(rd),i_imm,x(rs1))
+        }
+        0x23 => match ["sb","sh","sw","","","","",""][funct3 as usize]{
+            "" => unknown(),
+            name => format!("{} {}, {}({})",name,x(rs2),s_imm,x(rs1))
+        }
+        0x13 => match (funct3,funct7){
+            (0x1,0x00) => format!("slli {}, {}, {}",x(rd),x(rs1),rs2),
+            (0x5,0x00) => format!("srli {}, {}, {}",x(rd),x(rs1),rs2),
+            (0x5,0x20) => format!("srai {}, {}, {}",x(rd),x(rs1),rs2),
+            (0x1 | 0x5,_) => unknown(),
+            _ => {
+                let name = ["addi","","slti","sltiu","xori","","ori","andi"][funct3 as usize];
+                format!("{} {}, {}, {}",name,x(rd),x(rs1),i_imm)
+            }
+        }
+        0x33 => {
+            let name = match (funct7,funct3){
+                (0x00,_) => ["add","sll","slt","sltu","xor","srl","or","and"][funct3 as usize],
+                (0x20,0x0) => "sub",
+                (0x20,0x5) => "sra",
+                (0x01,_) => ["mul","mulh","mulhsu","mulhu","div","divu","rem","remu"][funct3 as usize],
+                _ => return unknown()
+            };
+            format!("{} {}, {}, {}",name,x(rd),x(rs1),x(rs2))
+        }
+        0x0f => match funct3{
+            0x0 => "fence".to_string(),
+            0x1 => "fence.i".to_string(),
+            _ => unknown()
+        }
+        0x73 => match funct3{
+            0x0 => match inst{
+                0x00000073 => "ecall".to_string(),
+                0x00100073 => "ebreak".to_string(),
+                0x10200073 => "sret".to_string(),
+                0x30200073 => "mret".to_string(),
+                0x10500073 => "wfi".to_string(),
+                _ if funct7 == 0x09 && rd == 0 => format!("sfence.vma {}, {}",x(rs1),x(rs2)),
+                _ => unknown()
+            }
+            0x4 => unknown(),
+            _ => {
+                let name = ["","csrrw","csrrs","csrrc","","csrrwi","csrrsi","csrrci"][funct3 as usize];
+                let source = if funct3 & 0x4 != 0 {rs1.to_string()} else {x(rs1).to_string()};
+                format!("{} {}, {}, {}",name,x(rd),csr_name(inst >> 20),source)
+            }
+        }
+        0x2f if funct3 == 0x2 => {
+            let name = match funct7 >> 2{
+                0x02 if rs2 == 0 => return format!("lr.w{} {}, ({})",ordering(inst),x(rd),x(rs1)),
+                0x03 => "sc.w",
+                0x01 => "amoswap.w",
+                0x00 => "amoadd.w",
+                0x04 => "amoxor.w",
+                0x0c => "amoand.w",
+                0x08 => "amoor.w",
+                0x10 => "amomin.w",
+                0x14 => "amomax.w",
+                0x18 => "amominu.w",
+                0x1c => "amomaxu.w",
+                _ => return unknown()
+            };
+            format!("{}{} {}, {}, ({})",name,ordering(inst),x(rd),x(rs2),x(rs1))
+        }
+        0x07 | 0x27 => {
+            let name = match (opcode,funct3){
+                (0x07,0x2) => "flw",
+                (0x07,0x3) => "fld",
+                (0x27,0x2) => "fsw",
+                (0x27,0x3) => "fsd",
+                _ => return unknown()
+            };
+            let (reg,offset) = if opcode == 0x07 {(rd,i_imm)} else {(rs2,s_imm)};
+            format!("{} {}, {}({})",name,f(reg),offset,x(rs1))
+        }
+        0x43 | 0x47 | 0x4b | 0x4f if funct7 & 0b10 == 0 => {
+            let name = ["fmadd","fmsub","fnmsub","fnmadd"][((opcode >> 2) & 0b11) as usize];
+            format!("{}.{} {}, {}, {}, {}",name,fmt,f(rd),f(rs1),f(rs2),f(inst >> 27))
+        }
+        0x53 if funct7 & 0b10 == 0 => match funct7 >> 2{
+            0x00 => format!("fadd.{} {}, {}, {}",fmt,f(rd),f(rs1),f(rs2)),
+            0x01 => format!("fsub.{} {}, {}, {}",fmt,f(rd),f(rs1),f(rs2)),
+            0x02 => format!("fmul.{} {}, {}, {}",fmt,f(rd),f(rs1),f(rs2)),
+            0x03 => format!("fdiv.{} {}, {}, {}",fmt,f(rd),f(rs1),f(rs2)),
+            0x0b => format!("fsqrt.{} {}, {}",fmt,f(rd),f(rs1)),
+            0x04 if funct3 < 3 => format!("{}.{} {}, {}, {}",["fsgnj","fsgnjn","fsgnjx"][funct3 as usize],fmt,f(rd),f(rs1),f(rs2)),
+            0x05 if funct3 < 2 => format!("{}.{} {}, {}, {}",["fmin","fmax"][funct3 as usize],fmt,f(rd),f(rs1),f(rs2)),
+            0x08 => format!("fcvt.{}.{} {}, {}",fmt,if rs2 == 1 {"d"} else {"s"},f(rd),f(rs1)),
+            0x14 if funct3 < 3 => format!("{}.{} {}, {}, {}",["fle","flt","feq"][funct3 as usize],fmt,x(rd),f(rs1),f(rs2)),
+            0x18 => format!("fcvt.{}.{} {}, {}",if rs2 & 1 == 1 {"wu"} else {"w"},fmt,x(rd),f(rs1)),
+            0x1a => format!("fcvt.{}.{} {}, {}",fmt,if rs2 & 1 == 1 {"wu"} else {"w"},f(rd),x(rs1)),
+            0x1c if funct3 == 0 && fmt == "s" => format!("fmv.x.w {}, {}",x(rd),f(rs1)),
+            0x1c if funct3 == 1 => format!("fclass.{} {}, {}",fmt,x(rd),f(rs1)),
+            0x1e if funct3 == 0 && fmt == "s" => format!("fmv.w.x {}, {}",f(rd),x(rs1)),
+            _ => unknown()
+        }
+        _ => unknown()
+    }
+}
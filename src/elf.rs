@@ -0,0 +1,158 @@
+use std::fs::{read,File};
+use std::io::{self,Read};
+
+const ELF_MAGIC: [u8;4] = [0x7f,b'E',b'L',b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const EM_RISCV: u16 = 243;
+const EHDR_SIZE: usize = 52;
+const PHDR_SIZE: usize = 32;
+const SHDR_SIZE: usize = 40;
+const SYM_SIZE: usize = 16;
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+
+/// A PT_LOAD segment: `data` is copied to `paddr` and the remaining
+/// `mem_size - data.len()` bytes (the .bss part) are zero-filled.
+pub struct Segment{
+    pub paddr: u32,
+    pub data: Vec<u8>,
+    pub mem_size: u32
+}
+
+pub struct Symbol{
+    pub name: String,
+    pub addr: u32,
+    pub size: u32
+}
+
+/// Function and object symbols sorted by address.
+#[derive(Default)]
+pub struct SymbolTable{
+    symbols: Vec<Symbol>
+}
+
+pub struct Elf{
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    pub symbols: SymbolTable
+}
+
+impl SymbolTable{
+    /// The symbol containing `addr` and the offset of `addr` into it.
+    pub fn lookup(&self,addr:u32) -> Option<(&str,u32)>{
+        let i = self.symbols.partition_point(|s| s.addr <= addr).checked_sub(1)?;
+        let sym = &self.symbols[i];
+        let offset = addr - sym.addr;
+        if sym.size != 0 && offset >= sym.size{
+            return None;
+        }
+        Some((&sym.name,offset))
+    }
+    /// The address of the symbol called `name`.
+    pub fn find(&self,name:&str) -> Option<u32>{
+        self.symbols.iter().find(|s| s.name == name).map(|s| s.addr)
+    }
+    /// Add the symbols of another image.
+    pub fn merge(&mut self,other:SymbolTable){
+        self.symbols.extend(other.symbols);
+        self.symbols.sort_by_key(|s| s.addr);
+    }
+    /// Format `addr` as `0x80000010 <main+0x10>`, or plain hex without a symbol.
+    pub fn describe(&self,addr:u32) -> String{
+        match self.lookup(addr){
+            Some((name,0)) => format!("{:#x} <{}>",addr,name),
+            Some((name,offset)) => format!("{:#x} <{}+{:#x}>",addr,name,offset),
+            None => format!("{:#x}",addr)
+        }
+    }
+}
+
+/// Whether `filename` starts with the ELF magic number.
+pub fn is_elf(filename:&str) -> bool{
+    let mut magic = [0;4];
+    File::open(filename).and_then(|mut f| f.read_exact(&mut magic)).is_ok() && magic == ELF_MAGIC
+}
+
+pub fn load(filename:&str) -> io::Result<Elf>{
+    parse(&read(filename)?)
+}
+
+fn invalid(msg:&str) -> io::Error{
+    io::Error::new(io::ErrorKind::InvalidData,msg.to_string())
+}
+
+fn u16_at(bytes:&[u8],off:usize) -> io::Result<u16>{
+    bytes.get(off..off + 2)
+        .map(|b| u16::from_le_bytes([b[0],b[1]]))
+        .ok_or_else(|| invalid("truncated ELF file"))
+}
+
+fn u32_at(bytes:&[u8],off:usize) -> io::Result<u32>{
+    bytes.get(off..off + 4)
+        .map(|b| u32::from_le_bytes([b[0],b[1],b[2],b[3]]))
+        .ok_or_else(|| invalid("truncated ELF file"))
+}
+
+fn slice(bytes:&[u8],off:u32,len:u32) -> io::Result<&[u8]>{
+    bytes.get(off as usize..off as usize + len as usize).ok_or_else(|| invalid("truncated ELF file"))
+}
+
+pub fn parse(bytes:&[u8]) -> io::Result<Elf>{
+    if bytes.len() < EHDR_SIZE || bytes[0..4] != ELF_MAGIC{
+        return Err(invalid("not an ELF file"));
+    }
+    if bytes[4] != ELFCLASS32 || bytes[5] != ELFDATA2LSB || u16_at(bytes,0x12)? != EM_RISCV{
+        return Err(invalid("not a little-endian ELF32 RISC-V file"));
+    }
+    let entry = u32_at(bytes,0x18)?;
+    let phoff = u32_at(bytes,0x1c)? as usize;
+    let shoff = u32_at(bytes,0x20)? as usize;
+    let phnum = u16_at(bytes,0x2c)? as usize;
+    let shnum = u16_at(bytes,0x30)? as usize;
+
+    let mut segments = Vec::new();
+    for i in 0..phnum{
+        let ph = phoff + i*PHDR_SIZE;
+        if u32_at(bytes,ph)? != PT_LOAD{
+            continue;
+        }
+        let (offset,paddr) = (u32_at(bytes,ph + 4)?,u32_at(bytes,ph + 12)?);
+        let (file_size,mem_size) = (u32_at(bytes,ph + 16)?,u32_at(bytes,ph + 20)?);
+        if file_size > mem_size{
+            return Err(invalid("segment file size exceeds its memory size"));
+        }
+        segments.push(Segment{paddr,data:slice(bytes,offset,file_size)?.to_vec(),mem_size});
+    }
+
+    let mut symbols = Vec::new();
+    for i in 0..shnum{
+        let sh = shoff + i*SHDR_SIZE;
+        if u32_at(bytes,sh + 4)? != SHT_SYMTAB{
+            continue;
+        }
+        let symtab = slice(bytes,u32_at(bytes,sh + 16)?,u32_at(bytes,sh + 20)?)?;
+        let strsh = shoff + u32_at(bytes,sh + 24)? as usize*SHDR_SIZE;
+        let strtab = slice(bytes,u32_at(bytes,strsh + 16)?,u32_at(bytes,strsh + 20)?)?;
+        for sym in symtab.chunks_exact(SYM_SIZE){
+            let kind = sym[12] & 0xf;
+            if ![STT_NOTYPE,STT_OBJECT,STT_FUNC].contains(&kind) || u16_at(sym,14)? == SHN_UNDEF{
+                continue;
+            }
+            let name = strtab.get(u32_at(sym,0)? as usize..).unwrap_or(&[]);
+            let name = String::from_utf8_lossy(name.split(|&c| c == 0).next().unwrap_or(&[]));
+            // skip the assembler's local labels and the mapping symbols
+            if name.is_empty() || name.starts_with(".L") || name.starts_with('$'){
+                continue;
+            }
+            symbols.push(Symbol{name:name.into_owned(),addr:u32_at(sym,4)?,size:u32_at(sym,8)?});
+        }
+    }
+    symbols.sort_by_key(|s| s.addr);
+    Ok(Elf{entry,segments,symbols:SymbolTable{symbols}})
+}
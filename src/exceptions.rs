@@ -78,15 +78,16 @@ impl Exception{
             StoreAMOPageFault(_) => 15,
         }
     }
-    pub fn is_fatal(&self) -> bool{
-        match &self{
-            IllegalInstruction(_)
-            |InstructionAccessFault(_)
-            |LoadAccessFault(_)
-            |StoreAMOAccessFault(_)
-            |StoreAMOAddrMisaligned(_)
-            => true,
-            _ => false
+    /// What xtval gets: the faulting address or instruction, or 0 for an
+    /// environment call.
+    pub fn tval(self) -> u32 {
+        match self {
+            EnvironmentCallFromUMode(_) | EnvironmentCallFromSMode(_) | EnvironmentCallFromMMode(_) => 0,
+            _ => self.value()
         }
     }
+    /// Whether the exception was raised fetching an instruction.
+    pub fn is_fetch_fault(&self) -> bool{
+        matches!(self,InstructionAccessFault(_) | InstructionPageFault(_))
+    }
 }
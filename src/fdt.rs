@@ -0,0 +1,396 @@
+// A writer for flattened device trees (the DTB format of the devicetree
+// specification, version 17) and the tree describing a machine to its guest.
+
+use crate::bus::{Bus,Mapping};
+use crate::param::*;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const FDT_HEADER_SIZE: usize = 40;
+/// An empty memory reservation map: its terminating all-zero entry.
+const FDT_RSVMAP_SIZE: usize = 16;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+
+/// misa bits of the single-letter extensions named in `riscv,isa`, in
+/// canonical order. S and U are implied by the privilege modes instead.
+const ISA_LETTERS: &str = "imafdc";
+
+/// Builds the structure and strings blocks of a tree node by node.
+#[derive(Default)]
+pub struct Fdt{
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    /// Property names already in `strings`, with their offsets.
+    names: Vec<(String,u32)>,
+    depth: usize
+}
+
+impl Fdt{
+    fn token(&mut self,token:u32){
+        self.structure.extend_from_slice(&token.to_be_bytes());
+    }
+    fn pad(&mut self){
+        while !self.structure.len().is_multiple_of(4){
+            self.structure.push(0);
+        }
+    }
+    fn name_offset(&mut self,name:&str) -> u32{
+        if let Some((_,offset)) = self.names.iter().find(|(n,_)| n == name){
+            return *offset;
+        }
+        let offset = self.strings.len() as u32;
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        self.names.push((name.to_string(),offset));
+        offset
+    }
+    /// Open a child of the current node; the root is named "".
+    pub fn begin_node(&mut self,name:&str){
+        self.token(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.pad();
+        self.depth += 1;
+    }
+    pub fn end_node(&mut self){
+        self.token(FDT_END_NODE);
+        self.depth -= 1;
+    }
+    pub fn property(&mut self,name:&str,value:&[u8]){
+        let offset = self.name_offset(name);
+        self.token(FDT_PROP);
+        self.token(value.len() as u32);
+        self.token(offset);
+        self.structure.extend_from_slice(value);
+        self.pad();
+    }
+    /// A property whose presence is its meaning, like `interrupt-controller`.
+    pub fn property_empty(&mut self,name:&str){
+        self.property(name,&[]);
+    }
+    pub fn property_u32(&mut self,name:&str,value:u32){
+        self.property(name,&value.to_be_bytes());
+    }
+    pub fn property_cells(&mut self,name:&str,cells:&[u32]){
+        let value:Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+        self.property(name,&value);
+    }
+    pub fn property_string(&mut self,name:&str,value:&str){
+        self.property_strings(name,&[value]);
+    }
+    pub fn property_strings(&mut self,name:&str,values:&[&str]){
+        let mut value = Vec::new();
+        for s in values{
+            value.extend_from_slice(s.as_bytes());
+            value.push(0);
+        }
+        self.property(name,&value);
+    }
+    /// Close the tree and lay out the blob: header, empty reservation map,
+    /// structure block, strings block.
+    pub fn finish(mut self) -> Vec<u8>{
+        assert!(self.depth == 0,"unbalanced device tree nodes");
+        self.token(FDT_END);
+        let off_rsvmap = FDT_HEADER_SIZE;
+        let off_struct = off_rsvmap + FDT_RSVMAP_SIZE;
+        let off_strings = off_struct + self.structure.len();
+        let total = off_strings + self.strings.len();
+        let header = [
+            FDT_MAGIC,
+            total as u32,
+            off_struct as u32,
+            off_strings as u32,
+            off_rsvmap as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            0,
+            self.strings.len() as u32,
+            self.structure.len() as u32
+        ];
+        let mut blob:Vec<u8> = header.iter().flat_map(|w| w.to_be_bytes()).collect();
+        blob.resize(off_struct,0);
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+        blob
+    }
+}
+
+/// The `riscv,isa` string for a hart with the given misa.
+fn isa_string(misa:u32) -> String{
+    let mut isa = "rv32".to_string();
+    for c in ISA_LETTERS.chars(){
+        if misa & 1 << (c as u8 - b'a') != 0{
+            isa.push(c);
+        }
+    }
+    isa
+}
+
+/// Describe the harts and everything mapped on `bus` in the layout Linux and
+/// OpenSBI expect from QEMU's virt board. `initrd` is the address range of
+/// the initial ramdisk, if any.
+pub fn generate(bus:&Bus,harts:usize,misa:u32,bootargs:&str,initrd:Option<(u32,u32)>) -> Vec<u8>{
+    // phandles: 1..=harts for the harts' local interrupt controllers, then the PLIC
+    let intc = |hart:usize| hart as u32 + 1;
+    let plic = harts as u32 + 1;
+    let per_hart = |causes:&[u32]| -> Vec<u32>{
+        (0..harts).flat_map(|h| causes.iter().flat_map(move |&c| [intc(h),c])).collect()
+    };
+
+    let mut fdt = Fdt::default();
+    fdt.begin_node("");
+    fdt.property_u32("#address-cells",1);
+    fdt.property_u32("#size-cells",1);
+    fdt.property_string("compatible","riscv-virtio");
+    fdt.property_string("model","remu");
+
+    fdt.begin_node("chosen");
+    fdt.property_string("bootargs",bootargs);
+    if let Some((start,end)) = initrd{
+        fdt.property_u32("linux,initrd-start",start);
+        fdt.property_u32("linux,initrd-end",end);
+    }
+    let console = bus.mappings().find(|(_,_,_,m)| matches!(m,Mapping::Device(d,_) if d.fdt_name() == Some(("serial","ns16550a"))));
+    if let Some((_,base,_,_)) = console{
+        fdt.property_string("stdout-path",&format!("/soc/serial@{:x}",base));
+    }
+    fdt.end_node();
+
+    // only harts with S-mode translate addresses
+    let mmu_type = if misa & (1 << (b's' - b'a')) != 0 {"riscv,sv32"} else {"riscv,none"};
+    fdt.begin_node("cpus");
+    fdt.property_u32("#address-cells",1);
+    fdt.property_u32("#size-cells",0);
+    fdt.property_u32("timebase-frequency",TIMEBASE_FREQ);
+    for hart in 0..harts{
+        fdt.begin_node(&format!("cpu@{}",hart));
+        fdt.property_string("device_type","cpu");
+        fdt.property_u32("reg",hart as u32);
+        fdt.property_string("status","okay");
+        fdt.property_string("compatible","riscv");
+        fdt.property_string("riscv,isa",&isa_string(misa));
+        fdt.property_string("mmu-type",mmu_type);
+        fdt.begin_node("interrupt-controller");
+        fdt.property_u32("#interrupt-cells",1);
+        fdt.property_empty("interrupt-controller");
+        fdt.property_string("compatible","riscv,cpu-intc");
+        fdt.property_u32("phandle",intc(hart));
+        fdt.end_node();
+        fdt.end_node();
+    }
+    fdt.end_node();
+
+    for (_,base,size,mapping) in bus.mappings(){
+        if let Mapping::Ram = mapping{
+            fdt.begin_node(&format!("memory@{:x}",base));
+            fdt.property_string("device_type","memory");
+            fdt.property_cells("reg",&[base,size]);
+            fdt.end_node();
+        }
+    }
+
+    let has_plic = bus.mappings().any(|(_,_,_,m)| matches!(m,Mapping::Plic));
+    fdt.begin_node("soc");
+    fdt.property_u32("#address-cells",1);
+    fdt.property_u32("#size-cells",1);
+    fdt.property_string("compatible","simple-bus");
+    fdt.property_empty("ranges");
+    for (_,base,size,mapping) in bus.mappings(){
+        match mapping{
+            Mapping::Clint => {
+                fdt.begin_node(&format!("clint@{:x}",base));
+                fdt.property_strings("compatible",&["sifive,clint0","riscv,clint0"]);
+                fdt.property_cells("reg",&[base,size]);
+                // machine software and machine timer interrupts
+                fdt.property_cells("interrupts-extended",&per_hart(&[3,7]));
+                fdt.end_node();
+            }
+            Mapping::Plic => {
+                fdt.begin_node(&format!("plic@{:x}",base));
+                fdt.property_strings("compatible",&["sifive,plic-1.0.0","riscv,plic0"]);
+                fdt.property_cells("reg",&[base,size]);
+                fdt.property_u32("#address-cells",0);
+                fdt.property_u32("#interrupt-cells",1);
+                fdt.property_empty("interrupt-controller");
+                // contexts 2h and 2h+1 drive hart h's MEIP and SEIP
+                fdt.property_cells("interrupts-extended",&per_hart(&[11,9]));
+                fdt.property_u32("riscv,ndev",PLIC_SOURCES - 1);
+                fdt.property_u32("phandle",plic);
+                fdt.end_node();
+            }
+            Mapping::Device(device,irq) => {
+                if let Some((name,compatible)) = device.fdt_name(){
+                    fdt.begin_node(&format!("{}@{:x}",name,base));
+                    fdt.property_string("compatible",compatible);
+                    fdt.property_cells("reg",&[base,size]);
+                    // without a PLIC the line goes nowhere
+                    if let Some(irq) = irq.filter(|_| has_plic){
+                        fdt.property_u32("interrupts",irq);
+                        fdt.property_u32("interrupt-parent",plic);
+                    }
+                    device.fdt_properties(&mut fdt);
+                    fdt.end_node();
+                }
+            }
+            Mapping::Ram | Mapping::Rom => ()
+        }
+    }
+    fdt.end_node();
+
+    fdt.end_node();
+    fdt.finish()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::memory::Memory;
+    use crate::uart::UartController;
+
+    fn misa(extensions:&str) -> u32{
+        extensions.bytes().fold(1 << 30,|misa,c| misa | 1 << (c - b'a'))
+    }
+
+    fn word(blob:&[u8],offset:usize) -> u32{
+        u32::from_be_bytes(blob[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn string(bytes:&[u8]) -> &str{
+        let end = bytes.iter().position(|&b| b == 0).unwrap();
+        std::str::from_utf8(&bytes[..end]).unwrap()
+    }
+
+    /// Check the header and walk the structure block, returning every
+    /// property as (node path, name, value).
+    fn parse(blob:&[u8]) -> Vec<(String,String,Vec<u8>)>{
+        assert_eq!(word(blob,0),FDT_MAGIC);
+        assert_eq!(word(blob,4) as usize,blob.len());
+        let (off_struct,off_strings,off_rsvmap) = (word(blob,8) as usize,word(blob,12) as usize,word(blob,16) as usize);
+        assert_eq!((word(blob,20),word(blob,24)),(FDT_VERSION,FDT_LAST_COMP_VERSION));
+        assert_eq!(word(blob,32) as usize,blob.len() - off_strings);
+        assert_eq!(off_struct + word(blob,36) as usize,off_strings);
+        assert!(blob[off_rsvmap..off_rsvmap + FDT_RSVMAP_SIZE].iter().all(|&b| b == 0));
+        let mut properties = Vec::new();
+        let mut path:Vec<String> = Vec::new();
+        let mut offset = off_struct;
+        loop{
+            let token = word(blob,offset);
+            offset += 4;
+            match token{
+                FDT_BEGIN_NODE => {
+                    let name = string(&blob[offset..]).to_string();
+                    offset += (name.len() + 4) & !3;
+                    path.push(name);
+                }
+                FDT_END_NODE => {
+                    path.pop().unwrap();
+                }
+                FDT_PROP => {
+                    let len = word(blob,offset) as usize;
+                    let name = string(&blob[off_strings + word(blob,offset + 4) as usize..]).to_string();
+                    offset += 8;
+                    properties.push((path.join("/"),name,blob[offset..offset + len].to_vec()));
+                    offset += (len + 3) & !3;
+                }
+                FDT_END => break,
+                _ => panic!("bad token {} at {}",token,offset - 4)
+            }
+        }
+        assert!(path.is_empty(),"unbalanced nodes");
+        assert_eq!(offset,off_strings);
+        properties
+    }
+
+    fn find<'a>(tree:&'a [(String,String,Vec<u8>)],path:&str,name:&str) -> Option<&'a [u8]>{
+        tree.iter().find(|(p,n,_)| p == path && n == name).map(|(_,_,value)| value.as_slice())
+    }
+
+    fn cells(value:&[u8]) -> Vec<u32>{
+        value.chunks(4).map(|c| word(c,0)).collect()
+    }
+
+    fn bus(harts:usize,plic:bool) -> Bus{
+        let mut bus = Bus::new(harts);
+        bus.add_memory("ram",DRAM_BASE,Memory::new(0x10000,false)).unwrap();
+        bus.map_clint("clint",CLINT_BASE,CLINT_SIZE).unwrap();
+        if plic{
+            bus.map_plic("plic",PLIC_BASE,PLIC_SIZE).unwrap();
+        }
+        bus.attach("uart",UART_BASE,UART_SIZE,Some(UART_IRQ),Box::new(UartController::new())).unwrap();
+        bus
+    }
+
+    #[test]
+    fn names_are_shared_and_values_padded(){
+        let mut fdt = Fdt::default();
+        fdt.begin_node("");
+        fdt.property_string("model","abcde");
+        fdt.begin_node("child");
+        fdt.property_u32("reg",7);
+        fdt.property_strings("model",&["a","b"]);
+        fdt.property_empty("ranges");
+        fdt.end_node();
+        fdt.end_node();
+        let blob = fdt.finish();
+        let tree = parse(&blob);
+        assert_eq!(find(&tree,"","model"),Some(&b"abcde\0"[..]));
+        assert_eq!(find(&tree,"/child","reg"),Some(&[0,0,0,7][..]));
+        assert_eq!(find(&tree,"/child","model"),Some(&b"a\0b\0"[..]));
+        assert_eq!(find(&tree,"/child","ranges"),Some(&[][..]));
+        assert_eq!(word(&blob,32),"model\0reg\0ranges\0".len() as u32);
+    }
+
+    #[test]
+    #[should_panic(expected = "unbalanced")]
+    fn unbalanced_trees_are_rejected(){
+        let mut fdt = Fdt::default();
+        fdt.begin_node("");
+        fdt.finish();
+    }
+
+    #[test]
+    fn machine_tree(){
+        let blob = generate(&bus(2,true),2,misa("imafdcsu"),"console=ttyS0",Some((0x8100_0000,0x8200_0000)));
+        let tree = parse(&blob);
+        assert_eq!(find(&tree,"/chosen","bootargs"),Some(&b"console=ttyS0\0"[..]));
+        assert_eq!(cells(find(&tree,"/chosen","linux,initrd-start").unwrap()),[0x8100_0000]);
+        assert_eq!(cells(find(&tree,"/chosen","linux,initrd-end").unwrap()),[0x8200_0000]);
+        assert_eq!(find(&tree,"/chosen","stdout-path"),Some(&b"/soc/serial@10000000\0"[..]));
+        assert_eq!(cells(find(&tree,"/cpus","timebase-frequency").unwrap()),[TIMEBASE_FREQ]);
+        for hart in 0..2{
+            let cpu = format!("/cpus/cpu@{}",hart);
+            assert_eq!(cells(find(&tree,&cpu,"reg").unwrap()),[hart]);
+            assert_eq!(find(&tree,&cpu,"riscv,isa"),Some(&b"rv32imafdc\0"[..]));
+            assert_eq!(find(&tree,&cpu,"mmu-type"),Some(&b"riscv,sv32\0"[..]));
+            assert_eq!(cells(find(&tree,&format!("{}/interrupt-controller",cpu),"phandle").unwrap()),[hart + 1]);
+        }
+        assert_eq!(cells(find(&tree,"/memory@80000000","reg").unwrap()),[DRAM_BASE,0x10000]);
+        assert_eq!(cells(find(&tree,"/soc/clint@2000000","interrupts-extended").unwrap()),[1,3,1,7,2,3,2,7]);
+        assert_eq!(cells(find(&tree,"/soc/plic@c000000","interrupts-extended").unwrap()),[1,11,1,9,2,11,2,9]);
+        assert_eq!(cells(find(&tree,"/soc/plic@c000000","phandle").unwrap()),[3]);
+        assert_eq!(cells(find(&tree,"/soc/serial@10000000","interrupts").unwrap()),[UART_IRQ]);
+        assert_eq!(cells(find(&tree,"/soc/serial@10000000","interrupt-parent").unwrap()),[3]);
+    }
+
+    #[test]
+    fn harts_without_s_mode_have_no_mmu(){
+        let tree = parse(&generate(&bus(1,true),1,misa("imacu"),"",None));
+        assert_eq!(find(&tree,"/cpus/cpu@0","riscv,isa"),Some(&b"rv32imac\0"[..]));
+        assert_eq!(find(&tree,"/cpus/cpu@0","mmu-type"),Some(&b"riscv,none\0"[..]));
+        assert_eq!(find(&tree,"/chosen","linux,initrd-start"),None);
+    }
+
+    #[test]
+    fn devices_have_no_interrupts_without_a_plic(){
+        let tree = parse(&generate(&bus(1,false),1,misa("imafdcsu"),"",None));
+        assert!(find(&tree,"/soc/serial@10000000","reg").is_some());
+        assert_eq!(find(&tree,"/soc/serial@10000000","interrupts"),None);
+        assert_eq!(find(&tree,"/soc/serial@10000000","interrupt-parent"),None);
+        assert!(!tree.iter().any(|(path,_,_)| path.starts_with("/soc/plic")));
+    }
+}
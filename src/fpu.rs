@@ -0,0 +1,492 @@
+// Software IEEE-754 binary floating point, so that results, rounding and
+// exception flags do not depend on the host FPU. Values are passed around
+// as raw bit patterns; every operation takes the rounding mode and ORs the
+// exceptions it raises into `flags` using the fflags bit layout.
+use std::cmp::Ordering;
+
+/// Inexact.
+pub const FLAG_NX: u32 = 1 << 0;
+/// Underflow.
+pub const FLAG_UF: u32 = 1 << 1;
+/// Overflow.
+pub const FLAG_OF: u32 = 1 << 2;
+/// Divide by zero.
+pub const FLAG_DZ: u32 = 1 << 3;
+/// Invalid operation.
+pub const FLAG_NV: u32 = 1 << 4;
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum Rounding{
+    NearestEven,
+    TowardZero,
+    Down,
+    Up,
+    NearestMaxMagnitude
+}
+
+impl Rounding{
+    /// Decode an rm/frm field; 5 and 6 are reserved and 7 (dynamic) must
+    /// already have been replaced by frm.
+    pub fn from_bits(rm:u32) -> Option<Self>{
+        match rm{
+            0 => Some(Rounding::NearestEven),
+            1 => Some(Rounding::TowardZero),
+            2 => Some(Rounding::Down),
+            3 => Some(Rounding::Up),
+            4 => Some(Rounding::NearestMaxMagnitude),
+            _ => None
+        }
+    }
+}
+
+#[derive(Copy,Clone,PartialEq)]
+pub struct Format{
+    exp_bits: u32,
+    frac_bits: u32
+}
+
+pub const F32: Format = Format{exp_bits:8,frac_bits:23};
+pub const F64: Format = Format{exp_bits:11,frac_bits:52};
+
+/// Shift `sig` right, ORing every bit shifted out into the lowest bit.
+fn shift_right_jam(sig:u128,shift:u32) -> u128{
+    match shift{
+        0 => sig,
+        1..=127 => (sig >> shift) | ((sig & ((1 << shift) - 1)) != 0) as u128,
+        _ => (sig != 0) as u128
+    }
+}
+
+/// Round `sig >> shift` to an integer; returns the result and whether it is inexact.
+fn round_shift(sig:u128,shift:i32,sign:bool,rm:Rounding) -> (u128,bool){
+    if shift <= 0{
+        return (sig << -shift,false);
+    }
+    let shift = shift as u32;
+    let kept = if shift >= 128 {0} else {sig >> shift};
+    let rest = if shift >= 128 {sig} else {sig & ((1 << shift) - 1)};
+    let half = if shift > 128 {Ordering::Less} else {rest.cmp(&(1 << (shift - 1)))};
+    let inexact = rest != 0;
+    let up = match rm{
+        Rounding::NearestEven => half == Ordering::Greater || (half == Ordering::Equal && kept & 1 == 1),
+        Rounding::NearestMaxMagnitude => half != Ordering::Less,
+        Rounding::TowardZero => false,
+        Rounding::Down => inexact && sign,
+        Rounding::Up => inexact && !sign
+    };
+    (kept + up as u128,inexact)
+}
+
+/// Integer square root.
+fn isqrt(n:u128) -> u128{
+    let (mut rest,mut root,mut bit) = (n,0u128,1u128 << 126);
+    while bit > rest{
+        bit >>= 2;
+    }
+    while bit != 0{
+        if rest >= root + bit{
+            rest -= root + bit;
+            root = (root >> 1) + bit;
+        } else{
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+impl Format{
+    fn bias(self) -> i32{
+        (1 << (self.exp_bits - 1)) - 1
+    }
+    fn exp_max(self) -> u64{
+        (1 << self.exp_bits) - 1
+    }
+    pub fn sign_mask(self) -> u64{
+        1 << (self.exp_bits + self.frac_bits)
+    }
+    fn frac_mask(self) -> u64{
+        (1 << self.frac_bits) - 1
+    }
+    fn exp(self,x:u64) -> u64{
+        (x >> self.frac_bits) & self.exp_max()
+    }
+    fn sign(self,x:u64) -> bool{
+        x & self.sign_mask() != 0
+    }
+    pub fn canonical_nan(self) -> u64{
+        (self.exp_max() << self.frac_bits) | (1 << (self.frac_bits - 1))
+    }
+    fn inf(self,sign:bool) -> u64{
+        self.zero(sign) | (self.exp_max() << self.frac_bits)
+    }
+    fn zero(self,sign:bool) -> u64{
+        if sign {self.sign_mask()} else {0}
+    }
+    pub fn is_nan(self,x:u64) -> bool{
+        self.exp(x) == self.exp_max() && x & self.frac_mask() != 0
+    }
+    fn is_snan(self,x:u64) -> bool{
+        self.is_nan(x) && x & (1 << (self.frac_bits - 1)) == 0
+    }
+    fn is_inf(self,x:u64) -> bool{
+        self.exp(x) == self.exp_max() && x & self.frac_mask() == 0
+    }
+    fn is_zero(self,x:u64) -> bool{
+        x & !self.sign_mask() == 0
+    }
+
+    /// Split a finite non-zero value into `(sig,exp)` with value = sig * 2^exp
+    /// and the most significant bit of `sig` at bit `frac_bits`.
+    fn unpack(self,x:u64) -> (u128,i32){
+        let frac = (x & self.frac_mask()) as u128;
+        let exp = self.exp(x) as i32;
+        if exp == 0{
+            let shift = frac.leading_zeros() as i32 - (127 - self.frac_bits as i32);
+            (frac << shift,1 - self.bias() - self.frac_bits as i32 - shift)
+        } else{
+            (frac | (1 << self.frac_bits),exp - self.bias() - self.frac_bits as i32)
+        }
+    }
+
+    /// Round the exact value (-1)^sign * sig * 2^exp to this format.
+    fn round_pack(self,sign:bool,exp:i32,sig:u128,rm:Rounding,flags:&mut u32) -> u64{
+        if sig == 0{
+            return self.zero(sign);
+        }
+        let p = self.frac_bits as i32;
+        let emin = 1 - self.bias();
+        // exponent of the most significant bit and of the result's lsb
+        let top = exp + 127 - sig.leading_zeros() as i32;
+        let mut lsb = top.max(emin) - p;
+        let (mut kept,inexact) = round_shift(sig,lsb - exp,sign,rm);
+        if kept >> (p + 1) != 0{
+            kept >>= 1;
+            lsb += 1;
+        }
+        if inexact{
+            *flags |= FLAG_NX;
+            // tininess is detected after rounding, i.e. as if the exponent
+            // range were unbounded
+            if top < emin - 1 || (top == emin - 1 && round_shift(sig,top - p - exp,sign,rm).0 >> (p + 1) == 0){
+                *flags |= FLAG_UF;
+            }
+        }
+        let biased = lsb + p + self.bias();
+        if biased as u64 >= self.exp_max(){
+            *flags |= FLAG_OF | FLAG_NX;
+            let to_inf = match rm{
+                Rounding::NearestEven | Rounding::NearestMaxMagnitude => true,
+                Rounding::TowardZero => false,
+                Rounding::Down => sign,
+                Rounding::Up => !sign
+            };
+            return if to_inf {self.inf(sign)} else {self.inf(sign) - 1};
+        }
+        // a normal `kept` carries its implicit bit into the exponent field;
+        // a subnormal one has biased == 1 and no implicit bit
+        self.zero(sign) | ((((biased - 1) as u64) << self.frac_bits) + kept as u64)
+    }
+
+    /// The canonical NaN, raising invalid if any operand is signaling.
+    fn propagate_nan(self,operands:&[u64],flags:&mut u32) -> u64{
+        if operands.iter().any(|&x| self.is_snan(x)){
+            *flags |= FLAG_NV;
+        }
+        self.canonical_nan()
+    }
+
+    fn invalid(self,flags:&mut u32) -> u64{
+        *flags |= FLAG_NV;
+        self.canonical_nan()
+    }
+
+    /// Add two finite non-zero unpacked `(sign,exp,sig)` values.
+    fn add_unpacked(self,a:(bool,i32,u128),b:(bool,i32,u128),rm:Rounding,flags:&mut u32) -> u64{
+        // line both significands up at bit 125, leaving room for a carry
+        let align = |(sign,exp,sig):(bool,i32,u128)|{
+            let shift = sig.leading_zeros() as i32 - 2;
+            (sign,exp - shift,sig << shift)
+        };
+        let (mut a,mut b) = (align(a),align(b));
+        if a.1 < b.1{
+            (a,b) = (b,a);
+        }
+        let ((sa,exp,siga),(sb,eb,sigb)) = (a,b);
+        let sigb = shift_right_jam(sigb,(exp - eb) as u32);
+        let (sign,sig) = if sa == sb{
+            (sa,siga + sigb)
+        } else if siga >= sigb{
+            (sa,siga - sigb)
+        } else{
+            (sb,sigb - siga)
+        };
+        if sig == 0{
+            // exact cancellation gives +0, or -0 when rounding down
+            return self.zero(rm == Rounding::Down);
+        }
+        self.round_pack(sign,exp,sig,rm,flags)
+    }
+
+    pub fn add(self,a:u64,b:u64,rm:Rounding,flags:&mut u32) -> u64{
+        if self.is_nan(a) || self.is_nan(b){
+            return self.propagate_nan(&[a,b],flags);
+        }
+        let (sa,sb) = (self.sign(a),self.sign(b));
+        if self.is_inf(a) || self.is_inf(b){
+            if self.is_inf(a) && self.is_inf(b) && sa != sb{
+                return self.invalid(flags);
+            }
+            return if self.is_inf(a) {a} else {b};
+        }
+        if self.is_zero(a) && self.is_zero(b){
+            return self.zero(if sa == sb {sa} else {rm == Rounding::Down});
+        }
+        if self.is_zero(a){
+            return b;
+        }
+        if self.is_zero(b){
+            return a;
+        }
+        let ((siga,ea),(sigb,eb)) = (self.unpack(a),self.unpack(b));
+        self.add_unpacked((sa,ea,siga),(sb,eb,sigb),rm,flags)
+    }
+
+    pub fn sub(self,a:u64,b:u64,rm:Rounding,flags:&mut u32) -> u64{
+        self.add(a,b ^ self.sign_mask(),rm,flags)
+    }
+
+    pub fn mul(self,a:u64,b:u64,rm:Rounding,flags:&mut u32) -> u64{
+        if self.is_nan(a) || self.is_nan(b){
+            return self.propagate_nan(&[a,b],flags);
+        }
+        let sign = self.sign(a) != self.sign(b);
+        if self.is_inf(a) || self.is_inf(b){
+            if self.is_zero(a) || self.is_zero(b){
+                return self.invalid(flags);
+            }
+            return self.inf(sign);
+        }
+        if self.is_zero(a) || self.is_zero(b){
+            return self.zero(sign);
+        }
+        let ((siga,ea),(sigb,eb)) = (self.unpack(a),self.unpack(b));
+        self.round_pack(sign,ea + eb,siga * sigb,rm,flags)
+    }
+
+    pub fn div(self,a:u64,b:u64,rm:Rounding,flags:&mut u32) -> u64{
+        if self.is_nan(a) || self.is_nan(b){
+            return self.propagate_nan(&[a,b],flags);
+        }
+        let sign = self.sign(a) != self.sign(b);
+        if self.is_inf(a){
+            return if self.is_inf(b) {self.invalid(flags)} else {self.inf(sign)};
+        }
+        if self.is_inf(b){
+            return self.zero(sign);
+        }
+        if self.is_zero(b){
+            if self.is_zero(a){
+                return self.invalid(flags);
+            }
+            *flags |= FLAG_DZ;
+            return self.inf(sign);
+        }
+        if self.is_zero(a){
+            return self.zero(sign);
+        }
+        let ((siga,ea),(sigb,eb)) = (self.unpack(a),self.unpack(b));
+        // enough quotient bits for the result, a round bit and a sticky bit
+        let shift = self.frac_bits + 4;
+        let num = siga << shift;
+        let sig = ((num / sigb) << 1) | !num.is_multiple_of(sigb) as u128;
+        self.round_pack(sign,ea - eb - shift as i32 - 1,sig,rm,flags)
+    }
+
+    pub fn sqrt(self,a:u64,rm:Rounding,flags:&mut u32) -> u64{
+        if self.is_nan(a){
+            return self.propagate_nan(&[a],flags);
+        }
+        if self.is_zero(a){
+            return a;
+        }
+        if self.sign(a){
+            return self.invalid(flags);
+        }
+        if self.is_inf(a){
+            return a;
+        }
+        let (mut sig,mut exp) = self.unpack(a);
+        if exp % 2 != 0{
+            sig <<= 1;
+            exp -= 1;
+        }
+        // scale the radicand so the root has frac_bits + 3 bits
+        let k = (self.frac_bits + 6) / 2;
+        let rad = sig << (2*k);
+        let root = isqrt(rad);
+        let sig = (root << 1) | (rad != root*root) as u128;
+        self.round_pack(false,exp/2 - k as i32 - 1,sig,rm,flags)
+    }
+
+    /// a * b + c with a single rounding.
+    pub fn mul_add(self,a:u64,b:u64,c:u64,rm:Rounding,flags:&mut u32) -> u64{
+        let inf_times_zero = (self.is_inf(a) && self.is_zero(b)) || (self.is_zero(a) && self.is_inf(b));
+        if self.is_nan(a) || self.is_nan(b) || self.is_nan(c){
+            // inf * 0 is invalid even when the addend is a quiet NaN
+            if inf_times_zero{
+                *flags |= FLAG_NV;
+            }
+            return self.propagate_nan(&[a,b,c],flags);
+        }
+        let (sp,sc) = (self.sign(a) != self.sign(b),self.sign(c));
+        if self.is_inf(a) || self.is_inf(b){
+            if inf_times_zero || (self.is_inf(c) && sc != sp){
+                return self.invalid(flags);
+            }
+            return self.inf(sp);
+        }
+        if self.is_inf(c){
+            return c;
+        }
+        if self.is_zero(a) || self.is_zero(b){
+            if self.is_zero(c){
+                return self.zero(if sp == sc {sp} else {rm == Rounding::Down});
+            }
+            return c;
+        }
+        let ((siga,ea),(sigb,eb)) = (self.unpack(a),self.unpack(b));
+        if self.is_zero(c){
+            return self.round_pack(sp,ea + eb,siga * sigb,rm,flags);
+        }
+        let (sigc,ec) = self.unpack(c);
+        self.add_unpacked((sp,ea + eb,siga * sigb),(sc,ec,sigc),rm,flags)
+    }
+
+    /// Convert to a signed or unsigned 32-bit integer, saturating on overflow.
+    pub fn to_int(self,a:u64,signed:bool,rm:Rounding,flags:&mut u32) -> u32{
+        let (min,max) = if signed {(i32::MIN as i128,i32::MAX as i128)} else {(0,u32::MAX as i128)};
+        let sign = self.sign(a);
+        if self.is_nan(a) || self.is_inf(a){
+            *flags |= FLAG_NV;
+            return if sign && !self.is_nan(a) {min as u32} else {max as u32};
+        }
+        if self.is_zero(a){
+            return 0;
+        }
+        let (sig,exp) = self.unpack(a);
+        // anything shifted this far is out of range anyway
+        let (mag,inexact) = round_shift(sig,-exp.min(64),sign,rm);
+        let value = if sign {-(mag as i128)} else {mag as i128};
+        if value < min || value > max{
+            *flags |= FLAG_NV;
+            return if sign {min as u32} else {max as u32};
+        }
+        if inexact{
+            *flags |= FLAG_NX;
+        }
+        value as u32
+    }
+
+    /// Convert a signed or unsigned 32-bit integer.
+    pub fn convert_from_int(self,x:u32,signed:bool,rm:Rounding,flags:&mut u32) -> u64{
+        let sign = signed && (x as i32) < 0;
+        let mag = if sign {(x as i32).unsigned_abs()} else {x};
+        self.round_pack(sign,0,mag as u128,rm,flags)
+    }
+
+    /// Convert to another format.
+    pub fn convert(self,to:Format,a:u64,rm:Rounding,flags:&mut u32) -> u64{
+        if self.is_nan(a){
+            self.propagate_nan(&[a],flags);
+            return to.canonical_nan();
+        }
+        let sign = self.sign(a);
+        if self.is_inf(a){
+            return to.inf(sign);
+        }
+        if self.is_zero(a){
+            return to.zero(sign);
+        }
+        let (sig,exp) = self.unpack(a);
+        to.round_pack(sign,exp,sig,rm,flags)
+    }
+
+    /// Order two non-NaN values, with -0 == +0.
+    fn compare(self,a:u64,b:u64) -> Ordering{
+        if self.is_zero(a) && self.is_zero(b){
+            return Ordering::Equal;
+        }
+        match (self.sign(a),self.sign(b)){
+            (true,false) => Ordering::Less,
+            (false,true) => Ordering::Greater,
+            (sign,_) => {
+                let mag = (a & !self.sign_mask()).cmp(&(b & !self.sign_mask()));
+                if sign {mag.reverse()} else {mag}
+            }
+        }
+    }
+
+    /// fmin/fmax: a NaN operand is ignored in favour of the other one and
+    /// -0 is considered less than +0.
+    pub fn min_max(self,a:u64,b:u64,max:bool,flags:&mut u32) -> u64{
+        if self.is_snan(a) || self.is_snan(b){
+            *flags |= FLAG_NV;
+        }
+        match (self.is_nan(a),self.is_nan(b)){
+            (true,true) => self.canonical_nan(),
+            (true,false) => b,
+            (false,true) => a,
+            _ if self.is_zero(a) && self.is_zero(b) => {
+                let (sa,sb) = (self.sign(a),self.sign(b));
+                self.zero(if max {sa && sb} else {sa || sb})
+            }
+            _ => {
+                let a_first = (self.compare(a,b) == Ordering::Less) != max;
+                if a_first {a} else {b}
+            }
+        }
+    }
+
+    /// Quiet equality: only signaling NaNs raise invalid.
+    pub fn equal(self,a:u64,b:u64,flags:&mut u32) -> bool{
+        if self.is_nan(a) || self.is_nan(b){
+            if self.is_snan(a) || self.is_snan(b){
+                *flags |= FLAG_NV;
+            }
+            return false;
+        }
+        self.compare(a,b) == Ordering::Equal
+    }
+
+    /// Signaling less-than (`or_equal` for less-or-equal): any NaN raises invalid.
+    pub fn less(self,a:u64,b:u64,or_equal:bool,flags:&mut u32) -> bool{
+        if self.is_nan(a) || self.is_nan(b){
+            *flags |= FLAG_NV;
+            return false;
+        }
+        match self.compare(a,b){
+            Ordering::Less => true,
+            Ordering::Equal => or_equal,
+            Ordering::Greater => false
+        }
+    }
+
+    /// The fclass bit mask.
+    pub fn classify(self,a:u64) -> u32{
+        let sign = self.sign(a);
+        let bit = if self.is_nan(a){
+            if self.is_snan(a) {8} else {9}
+        } else if self.is_inf(a){
+            if sign {0} else {7}
+        } else if self.is_zero(a){
+            if sign {3} else {4}
+        } else if self.exp(a) == 0{
+            if sign {2} else {5}
+        } else{
+            if sign {1} else {6}
+        };
+        1 << bit
+    }
+}
+
@@ -0,0 +1,132 @@
+// The Berkeley host-target interface used by riscv-tests, riscv-arch-test
+// and proxy kernels: the guest writes a request to the 64-bit `tohost`
+// word in memory and the host answers through `fromhost`.
+
+use std::io::{self,Write};
+use crate::bus::Bus;
+
+/// Device 0 is the syscall proxy.
+const DEVICE_SYSCALL: u64 = 0;
+/// A syscall-proxy payload with bit 0 set is an exit code shifted left by one.
+const MASK_EXIT: u64 = 1;
+/// Syscalls are made through 8 doublewords: the number, then the arguments,
+/// with the result written back over the number.
+const MAGIC_MEM_WORDS: u32 = 8;
+
+/// Bytes of guest memory a `write` copies at a time.
+const WRITE_CHUNK: u32 = 4096;
+
+// syscall numbers and errors of the RISC-V Linux ABI
+const SYS_WRITE: u64 = 64;
+const SYS_EXIT: u64 = 93;
+const EBADF: u64 = 9;
+const ENOSYS: u64 = 38;
+
+/// The `tohost` and `fromhost` words of a guest, found via its ELF symbols.
+pub struct Htif{
+    tohost: u32,
+    fromhost: Option<u32>
+}
+
+fn load64(bus:&mut Bus,addr:u32) -> u64{
+    let low = bus.load(addr,32).unwrap_or(0) as u64;
+    let high = bus.load(addr.wrapping_add(4),32).unwrap_or(0) as u64;
+    high << 32 | low
+}
+
+fn store64(bus:&mut Bus,addr:u32,value:u64){
+    bus.store(addr,32,value as u32).unwrap_or(());
+    bus.store(addr.wrapping_add(4),32,(value >> 32) as u32).unwrap_or(());
+}
+
+impl Htif{
+    pub fn new(tohost:u32,fromhost:Option<u32>) -> Self{
+        Self{tohost,fromhost}
+    }
+    /// Serve the request in `tohost`, if there is one. Returns the guest's
+    /// exit code once it asks to exit.
+    pub fn poll(&mut self,bus:&mut Bus) -> Option<u32>{
+        let request = load64(bus,self.tohost);
+        if request == 0{
+            return None;
+        }
+        let device = request >> 56;
+        let command = (request >> 48) & 0xff;
+        let payload = request & 0xffff_ffff_ffff;
+        store64(bus,self.tohost,0);
+        if device != DEVICE_SYSCALL{
+            // other devices, like the console, are not emulated
+            return None;
+        }
+        if payload & MASK_EXIT != 0{
+            return Some((payload >> 1) as u32);
+        }
+        let magic = payload as u32;
+        let args:Vec<u64> = (0..MAGIC_MEM_WORDS).map(|i| load64(bus,magic.wrapping_add(8*i))).collect();
+        let result = match args[0]{
+            SYS_EXIT => return Some(args[1] as u32),
+            SYS_WRITE => Self::write(bus,args[1],args[2] as u32,args[3] as u32),
+            _ => ENOSYS.wrapping_neg()
+        };
+        store64(bus,magic,result);
+        if let Some(fromhost) = self.fromhost{
+            store64(bus,fromhost,device << 56 | command << 48 | 1);
+        }
+        None
+    }
+    /// `write(fd,buf,len)` to the host's stdout or stderr, copied from
+    /// guest memory a chunk at a time.
+    fn write(bus:&mut Bus,fd:u64,buf:u32,len:u32) -> u64{
+        let mut out:Box<dyn Write> = match fd{
+            1 => Box::new(io::stdout().lock()),
+            2 => Box::new(io::stderr().lock()),
+            _ => return EBADF.wrapping_neg()
+        };
+        let mut chunk = Vec::with_capacity(WRITE_CHUNK as usize);
+        for start in (0..len).step_by(WRITE_CHUNK as usize){
+            let end = start.saturating_add(WRITE_CHUNK).min(len);
+            chunk.clear();
+            chunk.extend((start..end).map(|i| bus.load(buf.wrapping_add(i),8).unwrap_or(0) as u8));
+            if out.write_all(&chunk).is_err(){
+                return EBADF.wrapping_neg();
+            }
+        }
+        match out.flush(){
+            Ok(()) => len as u64,
+            Err(_) => EBADF.wrapping_neg()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::memory::Memory;
+    use crate::param::DRAM_BASE;
+
+    const TOHOST: u32 = DRAM_BASE;
+    const FROMHOST: u32 = DRAM_BASE + 8;
+
+    fn bus() -> Bus{
+        let mut bus = Bus::new(1);
+        bus.add_memory("ram",DRAM_BASE,Memory::new(0x1000,false)).unwrap();
+        bus
+    }
+
+    #[test]
+    fn exit_clears_tohost_and_returns_the_code(){
+        let (mut bus,mut htif) = (bus(),Htif::new(TOHOST,Some(FROMHOST)));
+        assert_eq!(htif.poll(&mut bus),None);
+        store64(&mut bus,TOHOST,7 << 1 | 1);
+        assert_eq!(htif.poll(&mut bus),Some(7));
+        assert_eq!(load64(&mut bus,TOHOST),0);
+    }
+
+    #[test]
+    fn syscall_at_the_top_of_the_address_space_does_not_overflow(){
+        let (mut bus,mut htif) = (bus(),Htif::new(TOHOST,Some(FROMHOST)));
+        store64(&mut bus,TOHOST,0xffff_fff8);
+        assert_eq!(htif.poll(&mut bus),None);
+        assert_eq!(load64(&mut bus,FROMHOST),1);
+    }
+}
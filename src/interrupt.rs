@@ -11,7 +11,23 @@ pub enum Interrupt{
 }
 
 use Interrupt::*;
+
+/// The order in which interrupts pending for the same privilege mode are
+/// taken.
+pub const PRIORITY: [Interrupt;6] = [
+    MachineExternalInterrupt,
+    MachineSoftwareInterrupt,
+    MachineTimerInterrupt,
+    SupervisorExternalInterrupt,
+    SupervisorSoftwareInterrupt,
+    SupervisorTimerInterrupt
+];
+
 impl Interrupt{
+    /// The interrupt's bit in mip and mie.
+    pub fn mask(self) -> u32{
+        1 << (self.code() & !MASK_INTERRUPT_BIT)
+    }
     pub fn code(self) -> u32{
         match self{
             SupervisorSoftwareInterrupt => MASK_INTERRUPT_BIT | 1,
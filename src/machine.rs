@@ -0,0 +1,798 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+use crate::boot::{self,Region};
+use crate::bus::Bus;
+use crate::cli::{parse_size,parse_u32};
+use crate::clint::{self,Timebase};
+use crate::cpu::Cpu;
+use crate::device::Wakeup;
+use crate::elf::{self,SymbolTable};
+use crate::exceptions::Exception;
+use crate::fdt;
+use crate::htif::Htif;
+use crate::trace::Tracer;
+use crate::memory::Memory;
+use crate::plic;
+use crate::param::*;
+use crate::toml::{self,Table,Value};
+use crate::uart::UartController;
+
+const DEFAULT_ISA: &str = "rv32imafdcsu";
+/// misa.MXL for a 32-bit hart.
+const MXL_32: u32 = 1 << 30;
+/// Alignment of the device tree in RAM.
+const DTB_ALIGN: u32 = 0x1000;
+
+pub struct MemoryConfig{
+    pub name: String,
+    pub base: u32,
+    pub size: u32,
+    /// Image copied to the start of the region before the harts start.
+    pub file: Option<String>
+}
+
+pub struct DeviceConfig{
+    /// One of `clint`, `plic` or `ns16550a`.
+    pub kind: String,
+    pub name: String,
+    pub base: u32,
+    pub size: u32,
+    pub irq: Option<u32>
+}
+
+/// An ELF executable, or a flat binary copied to `addr`. Boot images and
+/// the command line's image always carry an `addr`, which ELF files ignore.
+pub struct ImageConfig{
+    pub file: String,
+    pub addr: Option<u32>
+}
+
+/// Everything needed to build a `Machine`, as read from a machine
+/// description file:
+///
+/// ```toml
+/// isa = "rv32imac"            # default rv32imafdcsu
+/// harts = 2                   # default 1, at most 4095
+/// entry = 0x1000              # default: entry of the first image
+/// bootargs = "console=ttyS0"  # /chosen/bootargs of the generated device tree
+/// dtb = "board.dtb"           # pass this device tree instead of generating one
+///
+/// bios = "fw_jump.bin"        # firmware at the start of the first RAM
+/// kernel = "Image"            # kernel the firmware or the reset ROM jumps to
+/// kernel_addr = 0x80400000    # default: 4 MiB boundary after the firmware
+/// initrd = "rootfs.cpio"      # placed above the kernel
+///
+/// [[ram]]
+/// base = 0x80000000
+/// size = "128M"
+///
+/// [[rom]]
+/// base = 0x1000
+/// file = "boot.bin"           # size defaults to the file's
+///
+/// [[device]]
+/// type = "ns16550a"           # or clint, plic
+/// name = "uart0"
+/// base = 0x10000000
+/// irq = 10
+///
+/// [[image]]
+/// file = "firmware.elf"       # flat binaries need addr, ELF files take none
+/// ```
+///
+/// Relative paths are taken from the directory of the description file.
+pub struct MachineConfig{
+    pub misa: u32,
+    pub harts: usize,
+    pub ram: Vec<MemoryConfig>,
+    pub rom: Vec<MemoryConfig>,
+    pub devices: Vec<DeviceConfig>,
+    pub images: Vec<ImageConfig>,
+    pub entry: Option<u32>,
+    /// Kernel command line put in the generated device tree.
+    pub bootargs: String,
+    /// Device tree blob handed to the harts instead of the generated one.
+    pub dtb: Option<String>,
+    /// Firmware loaded at the start of the first RAM and entered from the
+    /// reset vector ROM.
+    pub bios: Option<String>,
+    /// Kernel the firmware is told to boot, or that the reset vector ROM
+    /// enters directly without firmware.
+    pub kernel: Option<String>,
+    /// Load address of a flat kernel.
+    pub kernel_addr: Option<u32>,
+    /// Initial ramdisk for the kernel, announced in the device tree.
+    pub initrd: Option<String>
+}
+
+/// The misa value for an ISA string such as `rv32imac` or `rv32gc_zifencei`.
+pub fn parse_isa(isa:&str) -> Result<u32,String>{
+    let lower = isa.to_ascii_lowercase();
+    let mut parts = lower.split('_');
+    let letters = parts.next().unwrap_or("").strip_prefix("rv32").ok_or(format!("ISA '{}' is not rv32",isa))?;
+    let letters = match letters.strip_prefix('g'){
+        Some(rest) => format!("imafd{}",rest),
+        None => letters.to_string()
+    };
+    if !letters.starts_with('i'){
+        return Err(format!("ISA '{}' does not start with rv32i or rv32g",isa));
+    }
+    let mut misa = MXL_32;
+    for c in letters.chars(){
+        if !"imafdcsu".contains(c){
+            return Err(format!("extension '{}' of ISA '{}' is not supported",c,isa));
+        }
+        misa |= 1 << (c as u32 - 'a' as u32);
+    }
+    if let Some(z) = parts.find(|z| !["zicsr","zifencei"].contains(z)){
+        return Err(format!("extension '{}' of ISA '{}' is not supported",z,isa));
+    }
+    let has = |c:char| misa & (1 << (c as u32 - 'a' as u32)) != 0;
+    if has('d') && !has('f'){
+        return Err(format!("ISA '{}' has D without F",isa));
+    }
+    if has('s') && !has('u'){
+        return Err(format!("ISA '{}' has S-mode without U-mode",isa));
+    }
+    Ok(misa)
+}
+
+/// Typed access to the keys of a table, reporting the ones left unused.
+struct Fields{
+    table: Table,
+    context: String
+}
+
+impl Fields{
+    fn new(value:Value,context:String) -> Result<Self,String>{
+        match value{
+            Value::Table(table) => Ok(Self{table,context}),
+            v => Err(format!("{} is a {}, not a table",context,v.type_name()))
+        }
+    }
+    fn wrong_type(&self,key:&str,value:&Value,want:&str) -> String{
+        format!("{}: '{}' is a {}, not {}",self.context,key,value.type_name(),want)
+    }
+    fn string(&mut self,key:&str) -> Result<Option<String>,String>{
+        match self.table.remove(key){
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(v) => Err(self.wrong_type(key,&v,"a string"))
+        }
+    }
+    fn u32(&mut self,key:&str) -> Result<Option<u32>,String>{
+        match self.table.remove(key){
+            None => Ok(None),
+            Some(Value::Integer(n)) => u32::try_from(n)
+                .map(Some)
+                .map_err(|_| format!("{}: '{}' is out of range",self.context,key)),
+            // also accept the command line's notation, "0x8000_0000"
+            Some(Value::String(s)) => parse_u32(&s).map(Some).map_err(|e| format!("{}: {}",self.context,e)),
+            Some(v) => Err(self.wrong_type(key,&v,"an integer"))
+        }
+    }
+    /// A byte count, either an integer or a string like "512M".
+    fn size(&mut self,key:&str) -> Result<Option<u32>,String>{
+        match self.table.remove(key){
+            Some(Value::String(s)) => parse_size(&s).map(Some).map_err(|e| format!("{}: {}",self.context,e)),
+            Some(v) => {
+                self.table.insert(key.to_string(),v);
+                self.u32(key)
+            }
+            None => Ok(None)
+        }
+    }
+    /// A key that must be present, read with `get`.
+    fn required<T>(&mut self,key:&str,get:fn(&mut Self,&str) -> Result<Option<T>,String>) -> Result<T,String>{
+        get(self,key)?.ok_or(format!("{}: '{}' is missing",self.context,key))
+    }
+    /// The elements of an array of tables.
+    fn tables(&mut self,key:&str) -> Result<Vec<Fields>,String>{
+        match self.table.remove(key){
+            None => Ok(Vec::new()),
+            Some(Value::Array(elements)) => elements
+                .into_iter()
+                .enumerate()
+                .map(|(i,e)| Fields::new(e,format!("{}[{}]",key,i)))
+                .collect(),
+            Some(v) => Err(self.wrong_type(key,&v,"an array of tables"))
+        }
+    }
+    fn finish(self) -> Result<(),String>{
+        match self.table.keys().next(){
+            Some(key) => Err(format!("{}: unknown key '{}'",self.context,key)),
+            None => Ok(())
+        }
+    }
+}
+
+impl MachineConfig{
+    /// The machine used without a description file: one hart with `dram_size`
+    /// bytes of RAM at DRAM_BASE, a CLINT, a PLIC and a UART.
+    pub fn builtin(dram_size:u32) -> Self{
+        let device = |kind:&str,name:&str,base,size,irq| DeviceConfig{
+            kind:kind.to_string(),name:name.to_string(),base,size,irq
+        };
+        Self{
+            misa: parse_isa(DEFAULT_ISA).unwrap(),
+            harts: 1,
+            ram: vec![MemoryConfig{name:"ram".to_string(),base:DRAM_BASE,size:dram_size,file:None}],
+            rom: Vec::new(),
+            devices: vec![
+                device("clint","clint",CLINT_BASE,CLINT_SIZE,None),
+                device("plic","plic",PLIC_BASE,PLIC_SIZE,None),
+                device("ns16550a","uart0",UART_BASE,UART_SIZE,Some(UART_IRQ))
+            ],
+            images: Vec::new(),
+            entry: None,
+            bootargs: String::new(),
+            dtb: None,
+            bios: None,
+            kernel: None,
+            kernel_addr: None,
+            initrd: None
+        }
+    }
+    /// Read a machine description file.
+    pub fn load(path:&str) -> Result<Self,String>{
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        let resolve = |file:String| dir.join(file).to_string_lossy().into_owned();
+        let mut root = Fields::new(Value::Table(toml::parse(&text)?),"machine".to_string())?;
+
+        let misa = parse_isa(&root.string("isa")?.unwrap_or(DEFAULT_ISA.to_string()))?;
+        let harts = root.u32("harts")?.unwrap_or(1) as usize;
+        if harts == 0{
+            return Err("a machine needs at least one hart".to_string());
+        }
+        let max_harts = clint::MAX_HARTS.min(plic::MAX_HARTS);
+        if harts > max_harts{
+            return Err(format!("a machine has at most {} harts, as the CLINT and PLIC can address no more",max_harts));
+        }
+        let entry = root.u32("entry")?;
+        let bootargs = root.string("bootargs")?.unwrap_or_default();
+        let dtb = root.string("dtb")?.map(resolve);
+        let bios = root.string("bios")?.map(resolve);
+        let kernel = root.string("kernel")?.map(resolve);
+        let kernel_addr = root.u32("kernel_addr")?;
+        let initrd = root.string("initrd")?.map(resolve);
+
+        let mut ram = Vec::new();
+        for (i,mut t) in root.tables("ram")?.into_iter().enumerate(){
+            let name = t.string("name")?.unwrap_or(format!("ram{}",i));
+            let base = t.required("base",Fields::u32)?;
+            let size = t.required("size",Fields::size)?;
+            let file = t.string("file")?.map(resolve);
+            t.finish()?;
+            ram.push(MemoryConfig{name,base,size,file});
+        }
+        let mut rom = Vec::new();
+        for (i,mut t) in root.tables("rom")?.into_iter().enumerate(){
+            let name = t.string("name")?.unwrap_or(format!("rom{}",i));
+            let base = t.required("base",Fields::u32)?;
+            let file = t.string("file")?.map(resolve);
+            let size = match (t.size("size")?,&file){
+                (Some(size),_) => size,
+                (None,Some(file)) => {
+                    let len = fs::metadata(file).map_err(|e| format!("{}: {}: {}",t.context,file,e))?.len();
+                    u32::try_from(len).map_err(|_| format!("{}: {} is too large",t.context,file))?
+                }
+                (None,None) => return Err(format!("{}: a ROM needs a size or a file",t.context))
+            };
+            t.finish()?;
+            rom.push(MemoryConfig{name,base,size,file});
+        }
+        let mut devices = Vec::new();
+        for mut t in root.tables("device")?{
+            let kind = t.required("type",Fields::string)?;
+            let default_size = match kind.as_str(){
+                "clint" => CLINT_SIZE,
+                "plic" => PLIC_SIZE,
+                "ns16550a" => UART_SIZE,
+                _ => return Err(format!("{}: unknown device type '{}'",t.context,kind))
+            };
+            if kind != "ns16550a" && devices.iter().any(|d:&DeviceConfig| d.kind == kind){
+                return Err(format!("{}: a machine has at most one {}",t.context,kind));
+            }
+            let name = t.string("name")?.unwrap_or(kind.clone());
+            let base = t.required("base",Fields::u32)?;
+            let size = t.size("size")?.unwrap_or(default_size);
+            let irq = t.u32("irq")?;
+            if irq.is_some() && kind != "ns16550a"{
+                return Err(format!("{}: a {} has no interrupt line of its own",t.context,kind));
+            }
+            t.finish()?;
+            devices.push(DeviceConfig{kind,name,base,size,irq});
+        }
+        let mut images = Vec::new();
+        for mut t in root.tables("image")?{
+            let file = resolve(t.required("file",Fields::string)?);
+            let addr = t.u32("addr")?;
+            if addr.is_some() && elf::is_elf(&file){
+                return Err(format!("{}: {} is an ELF file, loaded at its own addresses, so it takes no addr",t.context,file));
+            }
+            t.finish()?;
+            images.push(ImageConfig{file,addr});
+        }
+        root.finish()?;
+        Ok(Self{misa,harts,ram,rom,devices,images,entry,bootargs,dtb,bios,kernel,kernel_addr,initrd})
+    }
+}
+
+/// Where `Machine::load_boot_images` put things.
+struct Boot{
+    /// Where the reset vector ROM jumps to.
+    start: u32,
+    kernel: Option<u32>,
+    initrd: Option<(u32,u32)>,
+    /// The memory taken by the firmware, kernel and initrd.
+    regions: Vec<Region>
+}
+
+/// The harts of a machine and the bus they share.
+pub struct Machine{
+    harts: Vec<Cpu>,
+    bus: Rc<RefCell<Bus>>,
+    symbols: SymbolTable,
+    /// The device tree passed to the harts.
+    dtb: Vec<u8>,
+    tracer: Option<Tracer>,
+    max_instructions: Option<u64>,
+    /// The guest's `tohost` interface, if an image defines one.
+    htif: Option<Htif>,
+    /// Ends an idle wait when input arrives from the host.
+    wakeup: Arc<Wakeup>,
+    /// Where the harts start, if not at DRAM_BASE.
+    entry: Option<u32>,
+    /// Address of the device tree in RAM, or 0 without RAM.
+    dtb_addr: u32
+}
+
+impl Machine{
+    /// Build the machine described by `config`, load its images and point
+    /// every hart at the entry point, if there is one. The first UART reads
+    /// the host's stdin if `stdin` is set.
+    ///
+    /// As the RISC-V boot convention requires, each hart starts with its id
+    /// in a0 and the address of the device tree in a1. The tree is placed at
+    /// the top of the first RAM, below which the stack starts.
+    ///
+    /// With firmware or a kernel to boot, the harts start in a reset vector
+    /// ROM at MROM_BASE instead, which sets up the same registers and jumps
+    /// to the firmware, or straight to the kernel if there is none.
+    pub fn new(config:&MachineConfig,stdin:bool) -> Result<Self,String>{
+        let bus = Rc::new(RefCell::new(Bus::new(config.harts)));
+        let wakeup = Arc::new(Wakeup::default());
+        {
+            let mut bus = bus.borrow_mut();
+            for (mem,read_only) in config.ram.iter().map(|m| (m,false)).chain(config.rom.iter().map(|m| (m,true))){
+                bus.add_memory(&mem.name,mem.base,Memory::new(mem.size,read_only))?;
+                if let Some(file) = &mem.file{
+                    bus.load_binary(file,mem.base).map_err(|e| format!("failed to load {}: {}",file,e))?;
+                }
+            }
+            let mut stdin = stdin;
+            for dev in &config.devices{
+                match dev.kind.as_str(){
+                    "clint" => bus.map_clint(&dev.name,dev.base,dev.size)?,
+                    "plic" => bus.map_plic(&dev.name,dev.base,dev.size)?,
+                    _ => {
+                        let mut uart = UartController::new();
+                        if stdin{
+                            uart.connect_stdin(Arc::clone(&wakeup));
+                            stdin = false;
+                        }
+                        bus.attach(&dev.name,dev.base,dev.size,dev.irq,Box::new(uart))?;
+                    }
+                }
+            }
+        }
+        let harts = (0..config.harts).map(|id| Cpu::new(id,config.misa,Rc::clone(&bus))).collect();
+        let mut machine = Self{harts,bus,symbols:SymbolTable::default(),dtb:Vec::new(),tracer:None,max_instructions:None,htif:None,wakeup,
+            entry:None,dtb_addr:0};
+
+        let mut entry = config.entry;
+        for image in &config.images{
+            let (image_entry,_,_) = machine.load_image(image)?;
+            entry = entry.or(Some(image_entry));
+        }
+        let mut boot = match (&config.bios,&config.kernel){
+            (None,None) => None,
+            _ => Some(machine.load_boot_images(config)?)
+        };
+        if boot.is_some(){
+            entry = config.entry.or(Some(MROM_BASE));
+        }
+        machine.htif = machine.symbols.find("tohost").map(|tohost| Htif::new(tohost,machine.symbols.find("fromhost")));
+        machine.dtb = match &config.dtb{
+            Some(file) => fs::read(file).map_err(|e| format!("failed to load {}: {}",file,e))?,
+            None => {
+                let initrd = boot.as_ref().and_then(|b| b.initrd);
+                fdt::generate(&machine.bus.borrow(),config.harts,config.misa,&config.bootargs,initrd)
+            }
+        };
+        let dtb_addr = match config.ram.first(){
+            Some(ram) => {
+                let len = machine.dtb.len() as u32;
+                let addr = ram.size.checked_sub(len)
+                    .map(|room| (ram.base + room) & !(DTB_ALIGN - 1))
+                    .filter(|&addr| addr >= ram.base)
+                    .ok_or(format!("the device tree does not fit in {}",ram.name))?;
+                if let Some(boot) = &mut boot{
+                    boot::claim(&mut boot.regions,Region{name:"device tree",start:addr,end:addr + len})?;
+                }
+                machine.bus.borrow_mut().write_bytes(addr,&machine.dtb,len).map_err(|e| e.to_string())?;
+                addr
+            }
+            None => 0
+        };
+        if let Some(boot) = boot{
+            let rom = boot::reset_vector(boot.start,dtb_addr,boot.kernel.unwrap_or(0));
+            let mut bus = machine.bus.borrow_mut();
+            bus.add_memory("mrom",MROM_BASE,Memory::new(MROM_SIZE,true))?;
+            bus.write_bytes(MROM_BASE,&rom,rom.len() as u32).map_err(|e| e.to_string())?;
+        }
+        machine.entry = entry;
+        machine.dtb_addr = dtb_addr;
+        machine.reset();
+        Ok(machine)
+    }
+    /// Return the devices and harts to their power-on state and point the
+    /// harts at the entry point again. Memory keeps its contents.
+    pub fn reset(&mut self){
+        self.bus.borrow_mut().reset();
+        for (id,hart) in self.harts.iter_mut().enumerate(){
+            hart.reset();
+            if let Some(entry) = self.entry{
+                hart.set_pc(entry);
+            }
+            hart.set_reg(10,id as u32);
+            hart.set_reg(11,self.dtb_addr);
+            if self.dtb_addr != 0{
+                hart.set_reg(2,self.dtb_addr);
+            }
+        }
+    }
+    /// Load an ELF executable at its physical addresses, keeping its
+    /// symbols, or a flat binary at its `addr`. Returns the entry point and
+    /// the start and end of the memory loaded.
+    fn load_image(&mut self,image:&ImageConfig) -> Result<(u32,u32,u32),String>{
+        let mut bus = self.bus.borrow_mut();
+        let loaded = if elf::is_elf(&image.file){
+            elf::load(&image.file).and_then(|elf| {
+                bus.load_elf(&elf)?;
+                let start = elf.segments.iter().map(|s| s.paddr).min().unwrap_or(elf.entry);
+                let end = elf.segments.iter().map(|s| s.paddr.saturating_add(s.mem_size)).max().unwrap_or(elf.entry);
+                self.symbols.merge(elf.symbols);
+                Ok((elf.entry,start,end))
+            })
+        } else{
+            match image.addr{
+                Some(addr) => fs::read(&image.file).and_then(|code| {
+                    bus.write_bytes(addr,&code,code.len() as u32)?;
+                    Ok((addr,addr,addr.saturating_add(code.len() as u32)))
+                }),
+                None => Err(io::Error::new(io::ErrorKind::InvalidInput,"a flat binary needs a load address"))
+            }
+        };
+        loaded.map_err(|e| format!("failed to load {}: {}",image.file,e))
+    }
+    /// Place the firmware, kernel and initrd in the first RAM: the firmware
+    /// at its start, the kernel on the next KERNEL_ALIGN boundary after it
+    /// and the initrd well above the kernel. Fails if any two overlap.
+    fn load_boot_images(&mut self,config:&MachineConfig) -> Result<Boot,String>{
+        let ram = config.ram.first().ok_or("booting firmware or a kernel needs RAM")?;
+        let image = |file:&String,addr| ImageConfig{file:file.clone(),addr:Some(addr)};
+        let mut regions = Vec::new();
+        let firmware = match &config.bios{
+            Some(file) => {
+                let (entry,start,end) = self.load_image(&image(file,ram.base))?;
+                boot::claim(&mut regions,Region{name:"firmware",start,end})?;
+                Some((entry,end))
+            }
+            None => None
+        };
+        let kernel_addr = match (config.kernel_addr,firmware){
+            (Some(addr),_) => addr,
+            (None,Some((_,end))) => boot::align_up(end,boot::KERNEL_ALIGN).ok_or("no room for the kernel after the firmware")?,
+            (None,None) => ram.base
+        };
+        let kernel = match &config.kernel{
+            Some(file) => {
+                let (entry,start,end) = self.load_image(&image(file,kernel_addr))?;
+                boot::claim(&mut regions,Region{name:"kernel",start,end})?;
+                Some(entry)
+            }
+            None => None
+        };
+        let initrd = match (&config.initrd,kernel){
+            (None,_) => None,
+            (Some(_),None) => return Err("an initrd needs a kernel".to_string()),
+            (Some(file),Some(kernel)) => {
+                let data = fs::read(file).map_err(|e| format!("failed to load {}: {}",file,e))?;
+                let len = data.len() as u32;
+                let start = boot::initrd_addr(kernel,ram.size).ok_or("no room for the initrd")?;
+                let end = start.checked_add(len).ok_or("no room for the initrd")?;
+                boot::claim(&mut regions,Region{name:"initrd",start,end})?;
+                self.bus.borrow_mut().write_bytes(start,&data,len).map_err(|e| format!("failed to load {}: {}",file,e))?;
+                Some((start,end))
+            }
+        };
+        let start = firmware.map(|(entry,_)| entry).or(kernel).unwrap();
+        Ok(Boot{start,kernel,initrd,regions})
+    }
+    /// The flattened device tree the harts were started with.
+    pub fn dtb(&self) -> &[u8]{
+        &self.dtb
+    }
+    /// The words from the `begin_signature` symbol up to `end_signature`,
+    /// where riscv-arch-test programs leave their results.
+    pub fn signature(&self) -> Result<Vec<u32>,String>{
+        let find = |name:&str| self.symbols.find(name).ok_or(format!("no {} symbol in the image",name));
+        let (begin,end) = (find("begin_signature")?,find("end_signature")?);
+        let mut bus = self.bus.borrow_mut();
+        (begin..end).step_by(4)
+            .map(|addr| bus.load(addr,32).map_err(|e| format!("signature word at {:#x}: {}",addr,e)))
+            .collect()
+    }
+    /// TLB hits and misses of each hart.
+    pub fn tlb_stats(&self) -> Vec<(u64,u64)>{
+        self.harts.iter().map(|hart| hart.tlb_stats()).collect()
+    }
+    /// Trace what the harts do with `tracer`, or stop tracing.
+    pub fn set_tracer(&mut self,tracer:Option<Tracer>){
+        for hart in &mut self.harts{
+            hart.set_tracing(tracer.is_some());
+        }
+        self.tracer = tracer;
+    }
+    /// Stop `run` successfully after every hart has executed `limit` instructions.
+    pub fn set_instruction_limit(&mut self,limit:Option<u64>){
+        self.max_instructions = limit;
+    }
+    /// Choose what advances the CLINT's mtime.
+    pub fn set_timebase(&mut self,timebase:Timebase){
+        self.bus.borrow_mut().clint.set_timebase(timebase);
+    }
+    /// Wait while every hart is stalled in WFI rather than spinning. The
+    /// instruction timebase skips mtime straight to the next timer deadline
+    /// and the wall clock sleeps until it; input from the host ends the
+    /// wait early. Returns how many instructions' worth of time was
+    /// skipped, at most `budget`.
+    ///
+    /// Deadlines that have already passed raise mtip rather than being
+    /// waited for, so the wall clock is read afresh first and a hart whose
+    /// timer interrupt that wakes does not wait at all.
+    fn idle(&mut self,budget:Option<u64>) -> u64{
+        self.bus.borrow_mut().clint.sample();
+        if !self.harts.iter_mut().all(|hart| hart.is_waiting()){
+            return 0;
+        }
+        let mut bus = self.bus.borrow_mut();
+        let deadline = bus.clint.next_deadline();
+        match bus.clint.timebase(){
+            Timebase::Instructions => match deadline.map(|d| d - bus.clint.mtime()).into_iter().chain(budget).min(){
+                Some(ticks) => {
+                    bus.clint.skip(ticks);
+                    for hart in &mut self.harts{
+                        hart.skip(ticks);
+                    }
+                    ticks
+                }
+                None => {
+                    drop(bus);
+                    self.wakeup.wait(None);
+                    0
+                }
+            }
+            Timebase::WallClock => {
+                let timeout = deadline.map(|d| bus.clint.time_until(d));
+                drop(bus);
+                self.wakeup.wait(timeout);
+                self.bus.borrow_mut().clint.sample();
+                0
+            }
+        }
+    }
+    /// Step the harts in turn until one of them stops on a fatal exception,
+    /// the instruction limit is reached or the guest exits through HTIF.
+    /// Returns the guest's exit code in the last case.
+    pub fn run(&mut self) -> Result<Option<u32>,Exception>{
+        let mut executed:u64 = 0;
+        let smp = self.harts.len() > 1;
+        loop{
+            if self.max_instructions.is_some_and(|limit| executed >= limit){
+                break Ok(None);
+            }
+            if self.harts.iter_mut().all(|hart| hart.is_waiting()){
+                executed += self.idle(self.max_instructions.map(|limit| limit - executed));
+                if self.max_instructions.is_some_and(|limit| executed >= limit){
+                    break Ok(None);
+                }
+            }
+            executed += 1;
+            self.bus.borrow_mut().tick();
+            for (id,hart) in self.harts.iter_mut().enumerate(){
+                let pc = hart.pc();
+                let result = hart.step();
+                if let (Some(tracer),Some(record)) = (&mut self.tracer,hart.take_record()){
+                    if let Err(e) = tracer.write(id,&record,&self.symbols){
+                        eprintln!("remu: trace: {}",e);
+                        self.tracer = None;
+                    }
+                }
+                if let Err(e) = result{
+                    let who = if smp {format!("hart {}: ",id)} else {String::new()};
+                    eprintln!("remu: {}{} at pc {}",who,e,self.symbols.describe(pc));
+                    return Err(e);
+                }
+            }
+            if let Some(htif) = &mut self.htif{
+                if let Some(code) = htif.poll(&mut self.bus.borrow_mut()){
+                    break Ok(Some(code));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::path::PathBuf;
+
+    const FIRMWARE_SIZE: usize = 0x3000;
+    /// Instructions in the reset vector ROM before the jump is taken.
+    const ROM_INSTRUCTIONS: usize = 6;
+
+    /// Write `len` bytes of `byte` to a file named after `name` in the
+    /// temporary directory.
+    fn image(name:&str,len:usize,byte:u8) -> String{
+        let path:PathBuf = std::env::temp_dir().join(format!("remu-{}-{}",std::process::id(),name));
+        fs::write(&path,vec![byte;len]).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+    fn load(machine:&Machine,addr:u32) -> u32{
+        machine.bus.borrow_mut().load(addr,32).unwrap()
+    }
+    fn boot_config(name:&str) -> MachineConfig{
+        let mut config = MachineConfig::builtin(64 << 20);
+        config.harts = 2;
+        config.bios = Some(image(&format!("{}-fw",name),FIRMWARE_SIZE,0x13));
+        config.kernel = Some(image(&format!("{}-kernel",name),0x100,0x13));
+        config
+    }
+
+    #[test]
+    fn reset_vector_hands_over_to_the_firmware(){
+        let mut config = boot_config("handover");
+        config.initrd = Some(image("handover-initrd",0x20,0xa5));
+        let mut machine = Machine::new(&config,false).unwrap();
+        let kernel = DRAM_BASE + boot::KERNEL_ALIGN;
+        let initrd = boot::initrd_addr(kernel,64 << 20).unwrap();
+        assert_eq!(load(&machine,initrd),0xa5a5_a5a5);
+        for id in 0..2{
+            assert_eq!(machine.harts[id].pc(),MROM_BASE);
+            for _ in 0..ROM_INSTRUCTIONS{
+                machine.harts[id].step().unwrap();
+            }
+        }
+        for (id,hart) in machine.harts.iter().enumerate(){
+            assert_eq!(hart.pc(),DRAM_BASE);
+            assert_eq!(hart.reg(10),id as u32);
+            // the device tree, whose magic number is big-endian
+            assert_eq!(load(&machine,hart.reg(11)),0xedfe_0dd0);
+            // fw_dynamic_info: magic, version, next address, next mode, options, boot hart
+            let info:Vec<u32> = (0..6).map(|i| load(&machine,hart.reg(12) + 4*i)).collect();
+            assert_eq!(info,[0x4942_534f,2,kernel,1,0,u32::MAX]);
+        }
+    }
+
+    #[test]
+    fn kernel_without_firmware_is_entered_from_the_rom(){
+        let mut config = boot_config("kernel-only");
+        config.bios = None;
+        let mut machine = Machine::new(&config,false).unwrap();
+        for _ in 0..ROM_INSTRUCTIONS{
+            machine.harts[1].step().unwrap();
+        }
+        assert_eq!(machine.harts[1].pc(),DRAM_BASE);
+        assert_eq!(machine.harts[1].reg(10),1);
+    }
+
+    #[test]
+    fn overlapping_boot_images_are_rejected(){
+        let mut config = boot_config("overlap-kernel");
+        config.kernel_addr = Some(DRAM_BASE + 0x1000);
+        let err = Machine::new(&config,false).err().unwrap();
+        assert!(err.contains("the kernel at 0x80001000..0x80001100 overlaps the firmware"),"{}",err);
+
+        let mut config = boot_config("overlap-initrd");
+        config.ram[0].size = 16 << 20;
+        config.initrd = Some(image("overlap-initrd",4 << 20,0));
+        let err = Machine::new(&config,false).err().unwrap();
+        assert!(err.contains("the device tree at") && err.contains("overlaps the initrd"),"{}",err);
+    }
+
+    /// Read a machine description file holding `text`.
+    fn machine_file(name:&str,text:&str) -> Result<MachineConfig,String>{
+        let path = std::env::temp_dir().join(format!("remu-{}-{}.toml",std::process::id(),name));
+        fs::write(&path,text).unwrap();
+        MachineConfig::load(&path.to_string_lossy())
+    }
+
+    #[test]
+    fn harts_are_limited_to_what_the_clint_can_address(){
+        assert_eq!(machine_file("harts","harts = 4095\n").unwrap().harts,4095);
+        let err = machine_file("too-many-harts","harts = 4096\n").err().unwrap();
+        assert!(err.contains("at most 4095 harts"),"{}",err);
+    }
+
+    #[test]
+    fn elf_images_take_no_load_address(){
+        let elf = image("image.elf",16,0);
+        fs::write(&elf,b"\x7fELF").unwrap();
+        let text = format!("[[image]]\nfile = \"{}\"\n",elf);
+        assert_eq!(machine_file("elf",&text).unwrap().images[0].addr,None);
+        let err = machine_file("elf-addr",&format!("{}addr = 0x80000000\n",text)).err().unwrap();
+        assert!(err.contains("takes no addr"),"{}",err);
+    }
+
+    /// A machine running `program` from DRAM_BASE, stopped after 1000
+    /// instructions so that a hart waiting for good cannot hang the test.
+    fn program(name:&str,program:&[u32]) -> Machine{
+        let file = image(name,0,0);
+        fs::write(&file,program.iter().flat_map(|inst| inst.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
+        let mut config = MachineConfig::builtin(1 << 20);
+        config.images.push(ImageConfig{file,addr:Some(DRAM_BASE)});
+        let mut machine = Machine::new(&config,false).unwrap();
+        machine.set_instruction_limit(Some(1000));
+        machine
+    }
+
+    #[test]
+    fn wfi_with_an_interrupt_already_pending_does_not_wait(){
+        // csrsi mip, 2; csrsi mie, 2; wfi; ebreak
+        let mut machine = program("wfi-pending",&[0x3441_6073,0x3041_6073,0x1050_0073,0x0010_0073]);
+        assert!(matches!(machine.run(),Err(Exception::Breakpoint(_))));
+    }
+
+    #[test]
+    fn wfi_after_the_timer_deadline_does_not_wait(){
+        // mtimecmp = 0, which mtime has passed; enable MTIE; wfi; ebreak
+        let mut machine = program("wfi-deadline",&[
+            0x0200_42b7, // lui t0, 0x2004
+            0x0002_a023, // sw zero, 0(t0)
+            0x0002_a223, // sw zero, 4(t0)
+            0x0800_0313, // li t1, 0x80
+            0x3043_2073, // csrs mie, t1
+            0x1050_0073, // wfi
+            0x0010_0073  // ebreak
+        ]);
+        assert!(matches!(machine.run(),Err(Exception::Breakpoint(_))));
+    }
+
+    #[test]
+    fn reset_restores_the_devices_and_harts_but_not_memory(){
+        let mut machine = program("reset",&[
+            0x0200_42b7, // lui t0, 0x2004
+            0x0002_a023, // sw zero, 0(t0), the low half of mtimecmp
+            0x1000_0337, // lui t1, 0x10000
+            0x05a0_0393, // li t2, 0x5a
+            0x0073_03a3, // sb t2, 7(t1), the UART's scratch register
+            0x0010_0073  // ebreak
+        ]);
+        assert!(matches!(machine.run(),Err(Exception::Breakpoint(_))));
+        assert_eq!(load(&machine,CLINT_BASE + 0x4000),0);
+        machine.reset();
+        let hart = &machine.harts[0];
+        assert_eq!((hart.pc(),hart.reg(5),hart.reg(10),hart.reg(11)),(DRAM_BASE,0,0,machine.dtb_addr));
+        assert_eq!(load(&machine,CLINT_BASE + 0x4000),u32::MAX);
+        assert_eq!(machine.bus.borrow_mut().load(UART_BASE + 7,8).unwrap(),0);
+        assert_eq!(load(&machine,DRAM_BASE),0x0200_42b7);
+    }
+}
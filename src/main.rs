@@ -1,15 +1,136 @@
 mod cpu;
 mod bus;
-mod dram;
+mod memory;
 mod exceptions;
 mod param;
 mod csr;
 mod interrupt;
 mod uart;
-use cpu::Cpu;
+mod cli;
+mod elf;
+mod rvc;
+mod fpu;
+mod clint;
+mod plic;
+mod device;
+mod machine;
+mod toml;
+mod fdt;
+mod boot;
+mod tlb;
+mod pmp;
+mod htif;
+mod disasm;
+mod trace;
+use std::env;
+use std::fs;
+use std::process::ExitCode;
+use machine::{ImageConfig,Machine,MachineConfig};
+use trace::Tracer;
+use param::*;
+use cli::*;
 
-fn main() {
-    let mut cpu = Cpu::new();
-    cpu.load_binary("/Users/zhangximing/Downloads/complex.bin");
-    cpu.run();
+fn main() -> ExitCode {
+    let opts = match Options::parse(env::args().skip(1)){
+        Ok(opts) => opts,
+        Err(msg) => {
+            eprintln!("remu: {}\n\n{}",msg,USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    if opts.help{
+        println!("{}",USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let config = match &opts.machine{
+        Some(path) => MachineConfig::load(path).map_err(|e| format!("{}: {}",path,e)),
+        None => Ok(MachineConfig::builtin(opts.dram_size.unwrap_or(DRAM_SIZE)))
+    };
+    let mut config = match config{
+        Ok(config) => config,
+        Err(msg) => {
+            eprintln!("remu: {}",msg);
+            return ExitCode::from(EXIT_LOAD_ERROR);
+        }
+    };
+    if let Some(image) = &opts.image{
+        config.images.push(ImageConfig{file:image.clone(),addr:Some(opts.load_addr)});
+    }
+    if opts.entry.is_some(){
+        config.entry = opts.entry;
+    }
+    if let Some(bootargs) = &opts.bootargs{
+        config.bootargs = bootargs.clone();
+    }
+    if opts.dtb.is_some(){
+        config.dtb = opts.dtb.clone();
+    }
+    if opts.bios.is_some(){
+        config.bios = opts.bios.clone();
+    }
+    if opts.kernel.is_some(){
+        config.kernel = opts.kernel.clone();
+    }
+    if opts.kernel_addr.is_some(){
+        config.kernel_addr = opts.kernel_addr;
+    }
+    if opts.initrd.is_some(){
+        config.initrd = opts.initrd.clone();
+    }
+    let bootable = config.bios.is_some() || config.kernel.is_some();
+    if opts.dump_dtb.is_none() && config.images.is_empty() && config.entry.is_none() && !bootable{
+        eprintln!("remu: nothing to run: no image and no entry point");
+        return ExitCode::from(EXIT_LOAD_ERROR);
+    }
+    let mut machine = match Machine::new(&config,!opts.no_stdin){
+        Ok(machine) => machine,
+        Err(msg) => {
+            eprintln!("remu: {}",msg);
+            return ExitCode::from(EXIT_LOAD_ERROR);
+        }
+    };
+    if let Some(path) = &opts.dump_dtb{
+        return match fs::write(path,machine.dtb()){
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("remu: {}: {}",path,e);
+                ExitCode::from(EXIT_LOAD_ERROR)
+            }
+        };
+    }
+    machine.set_timebase(opts.timebase);
+    if opts.trace != 0{
+        match Tracer::new(opts.trace,opts.trace_file.as_deref(),opts.trace_pc,opts.trace_modes){
+            Ok(tracer) => machine.set_tracer(Some(tracer)),
+            Err(e) => {
+                eprintln!("remu: {}: {}",opts.trace_file.as_deref().unwrap_or("trace"),e);
+                return ExitCode::from(EXIT_LOAD_ERROR);
+            }
+        }
+    }
+    machine.set_instruction_limit(opts.max_instructions);
+    let result = machine.run();
+    if opts.stats{
+        for (id,(hits,misses)) in machine.tlb_stats().into_iter().enumerate(){
+            eprintln!("hart {}: tlb {} hits, {} misses",id,hits,misses);
+        }
+    }
+    if let Some(path) = &opts.signature{
+        let written = machine.signature().and_then(|words| {
+            let text:String = words.iter().map(|word| format!("{:08x}\n",word)).collect();
+            fs::write(path,text).map_err(|e| format!("{}: {}",path,e))
+        });
+        if let Err(msg) = written{
+            eprintln!("remu: {}",msg);
+            return ExitCode::from(EXIT_LOAD_ERROR);
+        }
+    }
+    match result{
+        Ok(None) | Ok(Some(0)) => ExitCode::SUCCESS,
+        Ok(Some(code)) => {
+            eprintln!("remu: guest exited with code {}",code);
+            ExitCode::from(u8::try_from(code).unwrap_or(u8::MAX))
+        }
+        Err(e) => ExitCode::from(EXIT_EXCEPTION_BASE + e.code() as u8)
+    }
 }
@@ -0,0 +1,54 @@
+use crate::device::Device;
+use crate::exceptions::Exception;
+
+/// RAM, or ROM when `read_only` is set. ROM contents come from images
+/// copied in by the loader, which bypasses the bus.
+pub struct Memory{
+    pub data:Vec<u8>,
+    read_only:bool
+}
+
+impl Memory{
+    pub fn new(size:u32,read_only:bool) -> Self{
+        Self{data:vec![0;size as usize],read_only}
+    }
+    pub fn size(&self) -> u32{
+        self.data.len() as u32
+    }
+    pub fn is_read_only(&self) -> bool{
+        self.read_only
+    }
+    /// Copy `bytes` to `offset` and zero-fill the rest of a `len` byte
+    /// region; the caller checks that the region fits.
+    pub fn write_bytes(&mut self,offset:u32,bytes:&[u8],len:u32){
+        let index = offset as usize;
+        self.data[index..index + bytes.len()].copy_from_slice(bytes);
+        self.data[index + bytes.len()..index + len as usize].fill(0);
+    }
+}
+
+impl Device for Memory{
+    fn load(&mut self,offset:u32,size:u32) -> Result<u32,Exception>{
+        if ![8,16,32].contains(&size) || self.size() - offset < size / 8{
+            return Err(Exception::LoadAccessFault(offset));
+        }
+        let index = offset as usize;
+        let mut code = self.data[index] as u32;
+        let nbytes = size / 8;
+        for i in 1..nbytes{
+            code |= (self.data[index + i as usize] as u32) << (i*8);
+        }
+        Ok(code)
+    }
+    fn store(&mut self,offset:u32,size:u32,value:u32) -> Result<(),Exception>{
+        if self.read_only || ![8,16,32].contains(&size) || self.size() - offset < size / 8{
+            return Err(Exception::StoreAMOAccessFault(offset));
+        }
+        let index = offset as usize;
+        let nbytes = size / 8;
+        for i in 0..nbytes{
+            self.data[index + i as usize] = ((value >> (i*8)) & 0xff) as u8;
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,33 @@
 pub const DRAM_BASE: u32 = 0x8000_0000;
 pub const DRAM_SIZE: u32 = 512*1024*1024;
-pub const DRAM_END : u32 = DRAM_SIZE + DRAM_BASE;
+
+/// Reset vector ROM used when booting firmware or a kernel.
+pub const MROM_BASE: u32 = 0x1000;
+pub const MROM_SIZE: u32 = 0xf000;
+
+pub const CLINT_BASE: u32 = 0x0200_0000;
+pub const CLINT_SIZE: u32 = 0x1_0000;
+/// mtime ticks per second.
+pub const TIMEBASE_FREQ: u32 = 10_000_000;
+
+pub const PLIC_BASE: u32 = 0x0c00_0000;
+pub const PLIC_SIZE: u32 = 0x0400_0000;
+/// Interrupt source ids, including the nonexistent source 0.
+pub const PLIC_SOURCES: u32 = 64;
 
 pub const UART_BASE: u32 = 0x1000_0000;
 pub const UART_SIZE: u32 = 0x100;
-pub const UART_END : u32 = UART_BASE + UART_SIZE;
+pub const UART_IRQ: u32 = 10;
 
 pub const PTE_SIZE:  u32 = 4;
-pub const PAGE_SIZE: u32 = 1024;
+pub const PAGE_SIZE: u32 = 4096;
+
+// Sv32 page table entry bits
+pub const PTE_V: u32 = 1 << 0;
+pub const PTE_R: u32 = 1 << 1;
+pub const PTE_W: u32 = 1 << 2;
+pub const PTE_X: u32 = 1 << 3;
+pub const PTE_U: u32 = 1 << 4;
+pub const PTE_G: u32 = 1 << 5;
+pub const PTE_A: u32 = 1 << 6;
+pub const PTE_D: u32 = 1 << 7;
@@ -0,0 +1,285 @@
+use crate::device::Device;
+use crate::exceptions::Exception;
+use crate::param::*;
+
+// register offsets (SiFive layout)
+const PRIORITY: u32 = 0x0;
+const PENDING: u32 = 0x1000;
+const ENABLE: u32 = 0x2000;
+const ENABLE_STRIDE: u32 = 0x80;
+const CONTEXT: u32 = 0x20_0000;
+const CONTEXT_STRIDE: u32 = 0x1000;
+const THRESHOLD: u32 = 0x0;
+const CLAIM: u32 = 0x4;
+
+/// Harts whose two contexts' registers fit in PLIC_SIZE; their enable bits
+/// then also end below the context registers.
+pub const MAX_HARTS: usize = ((PLIC_SIZE - CONTEXT) / CONTEXT_STRIDE / 2) as usize;
+
+/// Priorities and thresholds are WARL fields of this many bits.
+const PRIORITY_MASK: u32 = 0x7;
+const WORDS: usize = (PLIC_SOURCES / 32) as usize;
+
+/// Platform-level interrupt controller. Every hart has two contexts,
+/// `2*hart` for machine mode and `2*hart + 1` for supervisor mode;
+/// source 0 does not exist.
+pub struct Plic{
+    priority: [u32;PLIC_SOURCES as usize],
+    pending: [u32;WORDS],
+    /// Sources claimed by some context and not yet completed.
+    claimed: [u32;WORDS],
+    enable: Vec<[u32;WORDS]>,
+    threshold: Vec<u32>
+}
+
+fn bit(irq:u32) -> (usize,u32){
+    ((irq / 32) as usize,1 << (irq % 32))
+}
+
+impl Plic{
+    pub fn new(harts:usize) -> Self{
+        Self{
+            priority: [0;PLIC_SOURCES as usize],
+            pending: [0;WORDS],
+            claimed: [0;WORDS],
+            enable: vec![[0;WORDS];2*harts],
+            threshold: vec![0;2*harts]
+        }
+    }
+    /// Drive the level-triggered line of source `irq`.
+    pub fn set_level(&mut self,irq:u32,level:bool){
+        if irq == 0 || irq >= PLIC_SOURCES{
+            return;
+        }
+        let (word,mask) = bit(irq);
+        if level{
+            self.pending[word] |= mask;
+        } else{
+            self.pending[word] &= !mask;
+        }
+    }
+    /// The pending, unclaimed and enabled source with the highest priority
+    /// above the threshold of `context`; ties go to the lowest id.
+    fn best(&self,context:usize) -> Option<u32>{
+        let ready = |word:usize| self.pending[word] & !self.claimed[word] & self.enable[context][word];
+        if (0..WORDS).all(|word| ready(word) == 0){
+            return None;
+        }
+        let mut best = None;
+        let mut max = self.threshold[context];
+        for irq in 1..PLIC_SOURCES{
+            let (word,mask) = bit(irq);
+            if ready(word) & mask != 0 && self.priority[irq as usize] > max{
+                max = self.priority[irq as usize];
+                best = Some(irq);
+            }
+        }
+        best
+    }
+    /// Level of the external interrupt line into `context`.
+    pub fn is_context_interrupting(&self,context:usize) -> bool{
+        self.best(context).is_some()
+    }
+    fn claim(&mut self,context:usize) -> u32{
+        match self.best(context){
+            Some(irq) => {
+                let (word,mask) = bit(irq);
+                self.pending[word] &= !mask;
+                self.claimed[word] |= mask;
+                irq
+            }
+            None => 0
+        }
+    }
+    fn complete(&mut self,context:usize,irq:u32){
+        if irq == 0 || irq >= PLIC_SOURCES{
+            return;
+        }
+        let (word,mask) = bit(irq);
+        // completions for sources the context has not enabled are ignored
+        if self.enable[context][word] & mask != 0{
+            self.claimed[word] &= !mask;
+        }
+    }
+}
+
+impl Device for Plic{
+    fn load(&mut self,offset:u32,size:u32) -> Result<u32,Exception>{
+        let contexts = self.threshold.len() as u32;
+        if size != 32 || !offset.is_multiple_of(4){
+            return Err(Exception::LoadAccessFault(offset));
+        }
+        match offset{
+            _ if offset < PRIORITY + 4*PLIC_SOURCES => Ok(self.priority[(offset/4) as usize]),
+            _ if (PENDING..PENDING + 4*WORDS as u32).contains(&offset) => {
+                Ok(self.pending[((offset - PENDING)/4) as usize])
+            }
+            _ if (ENABLE..ENABLE + ENABLE_STRIDE*contexts).contains(&offset) => {
+                let context = ((offset - ENABLE)/ENABLE_STRIDE) as usize;
+                let word = ((offset - ENABLE) % ENABLE_STRIDE/4) as usize;
+                Ok(self.enable[context].get(word).copied().unwrap_or(0))
+            }
+            _ if (CONTEXT..CONTEXT + CONTEXT_STRIDE*contexts).contains(&offset) => {
+                let context = ((offset - CONTEXT)/CONTEXT_STRIDE) as usize;
+                match (offset - CONTEXT) % CONTEXT_STRIDE{
+                    THRESHOLD => Ok(self.threshold[context]),
+                    CLAIM => Ok(self.claim(context)),
+                    _ => Ok(0)
+                }
+            }
+            _ => Ok(0)
+        }
+    }
+    fn store(&mut self,offset:u32,size:u32,value:u32) -> Result<(),Exception>{
+        let contexts = self.threshold.len() as u32;
+        if size != 32 || !offset.is_multiple_of(4){
+            return Err(Exception::StoreAMOAccessFault(offset));
+        }
+        match offset{
+            // source 0 does not exist, so its priority is hardwired to zero
+            _ if offset != 0 && offset < PRIORITY + 4*PLIC_SOURCES => {
+                self.priority[(offset/4) as usize] = value & PRIORITY_MASK;
+            }
+            _ if (ENABLE..ENABLE + ENABLE_STRIDE*contexts).contains(&offset) => {
+                let context = ((offset - ENABLE)/ENABLE_STRIDE) as usize;
+                let word = ((offset - ENABLE) % ENABLE_STRIDE/4) as usize;
+                if let Some(enable) = self.enable[context].get_mut(word){
+                    *enable = if word == 0 {value & !1} else {value};
+                }
+            }
+            _ if (CONTEXT..CONTEXT + CONTEXT_STRIDE*contexts).contains(&offset) => {
+                let context = ((offset - CONTEXT)/CONTEXT_STRIDE) as usize;
+                match (offset - CONTEXT) % CONTEXT_STRIDE{
+                    THRESHOLD => self.threshold[context] = value & PRIORITY_MASK,
+                    CLAIM => self.complete(context,value),
+                    _ => ()
+                }
+            }
+            // the pending bits are read-only and the rest is reserved
+            _ => ()
+        }
+        Ok(())
+    }
+    fn reset(&mut self){
+        *self = Self::new(self.threshold.len()/2);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const M: usize = 0;
+    const S: usize = 1;
+
+    fn enable(plic:&mut Plic,context:usize,irq:u32){
+        let offset = ENABLE + ENABLE_STRIDE*context as u32 + irq/32*4;
+        let value = plic.load(offset,32).unwrap();
+        plic.store(offset,32,value | 1 << (irq % 32)).unwrap();
+    }
+
+    fn threshold(plic:&mut Plic,context:usize,value:u32){
+        plic.store(CONTEXT + CONTEXT_STRIDE*context as u32 + THRESHOLD,32,value).unwrap();
+    }
+
+    fn claim(plic:&mut Plic,context:usize) -> u32{
+        plic.load(CONTEXT + CONTEXT_STRIDE*context as u32 + CLAIM,32).unwrap()
+    }
+
+    fn complete(plic:&mut Plic,context:usize,irq:u32){
+        plic.store(CONTEXT + CONTEXT_STRIDE*context as u32 + CLAIM,32,irq).unwrap();
+    }
+
+    #[test]
+    fn priorities_and_thresholds_are_warl(){
+        let mut plic = Plic::new(1);
+        plic.store(PRIORITY + 4*10,32,0xffff_ffff).unwrap();
+        assert_eq!(plic.load(PRIORITY + 4*10,32).unwrap(),PRIORITY_MASK);
+        plic.store(PRIORITY,32,1).unwrap();
+        assert_eq!(plic.load(PRIORITY,32).unwrap(),0,"source 0 does not exist");
+        threshold(&mut plic,M,0xffff_ffff);
+        assert_eq!(plic.load(CONTEXT + THRESHOLD,32).unwrap(),PRIORITY_MASK);
+        plic.store(ENABLE,32,0xffff_ffff).unwrap();
+        assert_eq!(plic.load(ENABLE,32).unwrap(),!1,"source 0 cannot be enabled");
+    }
+
+    #[test]
+    fn sources_need_priority_above_the_threshold_and_an_enable(){
+        let mut plic = Plic::new(1);
+        plic.set_level(10,true);
+        assert_eq!(plic.load(PENDING,32).unwrap(),1 << 10);
+        assert!(!plic.is_context_interrupting(M),"priority 0 never interrupts");
+        plic.store(PRIORITY + 4*10,32,3).unwrap();
+        assert!(!plic.is_context_interrupting(M),"not enabled");
+        enable(&mut plic,M,10);
+        assert!(plic.is_context_interrupting(M));
+        assert!(!plic.is_context_interrupting(S),"enables are per context");
+        threshold(&mut plic,M,3);
+        assert!(!plic.is_context_interrupting(M),"priority must exceed the threshold");
+        threshold(&mut plic,M,2);
+        assert!(plic.is_context_interrupting(M));
+        plic.set_level(10,false);
+        assert!(!plic.is_context_interrupting(M));
+    }
+
+    #[test]
+    fn claim_takes_the_highest_priority_then_the_lowest_id(){
+        let mut plic = Plic::new(1);
+        for (irq,priority) in [(3,1),(5,2),(7,2)]{
+            plic.store(PRIORITY + 4*irq,32,priority).unwrap();
+            enable(&mut plic,M,irq);
+            plic.set_level(irq,true);
+        }
+        assert_eq!(claim(&mut plic,M),5);
+        assert_eq!(claim(&mut plic,M),7);
+        assert_eq!(claim(&mut plic,M),3);
+        assert_eq!(claim(&mut plic,M),0,"nothing left to claim");
+        assert!(!plic.is_context_interrupting(M));
+    }
+
+    #[test]
+    fn claimed_sources_wait_for_completion(){
+        let mut plic = Plic::new(1);
+        plic.store(PRIORITY + 4*10,32,1).unwrap();
+        enable(&mut plic,M,10);
+        enable(&mut plic,S,10);
+        plic.set_level(10,true);
+        assert_eq!(claim(&mut plic,M),10);
+        assert_eq!(plic.load(PENDING,32).unwrap(),0,"claiming clears the pending bit");
+        // the device still asserts its line, but the source is in service
+        plic.set_level(10,true);
+        assert!(!plic.is_context_interrupting(M));
+        assert!(!plic.is_context_interrupting(S),"claimed for every context");
+        assert_eq!(claim(&mut plic,S),0);
+        complete(&mut plic,M,10);
+        assert!(plic.is_context_interrupting(M));
+        assert!(plic.is_context_interrupting(S));
+    }
+
+    #[test]
+    fn completion_needs_the_source_enabled(){
+        let mut plic = Plic::new(2);
+        let (m1,s1) = (2,3);
+        plic.store(PRIORITY + 4*10,32,1).unwrap();
+        enable(&mut plic,s1,10);
+        plic.set_level(10,true);
+        assert_eq!(claim(&mut plic,s1),10);
+        plic.set_level(10,true);
+        complete(&mut plic,m1,10);
+        assert!(!plic.is_context_interrupting(s1),"ignored: hart 1's M context has not enabled it");
+        complete(&mut plic,s1,10);
+        assert!(plic.is_context_interrupting(s1));
+    }
+
+    #[test]
+    fn reset_clears_everything(){
+        let mut plic = Plic::new(1);
+        plic.store(PRIORITY + 4*10,32,1).unwrap();
+        enable(&mut plic,M,10);
+        plic.set_level(10,true);
+        plic.reset();
+        assert_eq!(plic.load(PRIORITY + 4*10,32).unwrap(),0);
+        assert_eq!(plic.load(ENABLE,32).unwrap(),0);
+        assert_eq!(plic.load(PENDING,32).unwrap(),0);
+    }
+}
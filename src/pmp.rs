@@ -0,0 +1,184 @@
+// Physical memory protection: the regions set up in pmpcfg0..3 and
+// pmpaddr0..15, decoded whenever one of those CSRs is written.
+
+use crate::cpu::MACHINE;
+use crate::csr::*;
+
+pub const PMP_R: u8 = 1 << 0;
+pub const PMP_W: u8 = 1 << 1;
+pub const PMP_X: u8 = 1 << 2;
+pub const PMP_A: u8 = 0b11 << 3;
+pub const PMP_L: u8 = 1 << 7;
+
+const PMP_OFF: u8 = 0;
+pub const PMP_TOR: u8 = 1 << 3;
+const PMP_NA4: u8 = 2 << 3;
+
+/// An enabled entry, matching physical addresses `start..end`.
+struct Region{
+    start: u64,
+    end: u64,
+    cfg: u8
+}
+
+pub struct Pmp{
+    /// Enabled entries in priority order.
+    regions: Vec<Region>
+}
+
+impl Pmp{
+    pub fn new() -> Self{
+        Self{regions:Vec::new()}
+    }
+    /// Decode the entries from the PMP CSRs.
+    pub fn update(&mut self,csr:&Csr){
+        self.regions.clear();
+        for i in 0..NUM_PMP{
+            let cfg = csr.pmpcfg(i);
+            // pmpaddr holds bits 33:2 of an address
+            let addr = (csr.csrs[PMPADDR0 + i] as u64) << 2;
+            let (start,end) = match cfg & PMP_A{
+                PMP_OFF => continue,
+                PMP_TOR => {
+                    let start = if i == 0 {0} else {(csr.csrs[PMPADDR0 + i - 1] as u64) << 2};
+                    // an empty range matches nothing
+                    if start >= addr{
+                        continue;
+                    }
+                    (start,addr)
+                }
+                PMP_NA4 => (addr,addr + 4),
+                // NAPOT: the trailing ones of pmpaddr encode the size
+                _ => {
+                    let ones = csr.csrs[PMPADDR0 + i].trailing_ones();
+                    let size = 8u64 << ones;
+                    let start = addr & !(size - 1);
+                    (start,start + size)
+                }
+            };
+            self.regions.push(Region{start,end,cfg});
+        }
+    }
+    /// Whether `mode` may access the `len` bytes at `addr` with permission
+    /// `perm`. The first entry matching any of the bytes decides, and an
+    /// access it only partly covers fails. Unlocked entries do not apply to
+    /// M-mode, and S- and U-mode may only access memory an entry allows.
+    pub fn check(&self,addr:u64,len:u64,perm:u8,mode:u32) -> bool{
+        let end = addr + len;
+        match self.regions.iter().find(|r| r.start < end && addr < r.end){
+            Some(r) if addr < r.start || r.end < end => false,
+            Some(r) if mode == MACHINE && r.cfg & PMP_L == 0 => true,
+            Some(r) => r.cfg & perm != 0,
+            None => mode == MACHINE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::cpu::SUPERVISOR;
+
+    const PMP_NAPOT: u8 = PMP_A;
+
+    /// Set the PMP CSRs from `entries` of (pmpcfg, address) and decode them.
+    fn pmp(entries:&[(u8,u64)]) -> (Csr,Pmp){
+        let mut csr = Csr::new(0);
+        let cfg = entries.iter().enumerate().fold(0,|cfg,(i,&(c,_))| cfg | (c as u32) << (8 * i));
+        for (i,&(_,addr)) in entries.iter().enumerate(){
+            csr.store(PMPADDR0 + i,(addr >> 2) as u32).unwrap();
+        }
+        csr.store(PMPCFG0,cfg).unwrap();
+        let mut pmp = Pmp::new();
+        pmp.update(&csr);
+        (csr,pmp)
+    }
+    /// The pmpaddr address of a NAPOT range of `size` bytes at `start`.
+    fn napot(start:u64,size:u64) -> u64{
+        start | (size / 2 - 1)
+    }
+
+    #[test]
+    fn entries_match_by_address_mode(){
+        let (_,pmp) = pmp(&[
+            (PMP_NA4 | PMP_R,0x1000),
+            (PMP_TOR | PMP_R | PMP_W,0x2000),
+            (PMP_NAPOT | PMP_X,napot(0x8000,0x1000)),
+            (PMP_OFF | PMP_R,napot(0x10000,0x1000))
+        ]);
+        // the NA4 entry comes first and shadows the start of the TOR range
+        assert!(pmp.check(0x1000,4,PMP_R,SUPERVISOR));
+        assert!(!pmp.check(0x1000,4,PMP_W,SUPERVISOR));
+        assert!(pmp.check(0x1004,4,PMP_W,SUPERVISOR));
+        assert!(pmp.check(0x1ffc,4,PMP_W,SUPERVISOR));
+        assert!(!pmp.check(0x2000,4,PMP_R,SUPERVISOR));
+        assert!(!pmp.check(0x0ffc,4,PMP_R,SUPERVISOR));
+        assert!(pmp.check(0x8000,4,PMP_X,SUPERVISOR));
+        assert!(pmp.check(0x8ffc,4,PMP_X,SUPERVISOR));
+        assert!(!pmp.check(0x8ffc,4,PMP_R,SUPERVISOR));
+        assert!(!pmp.check(0x9000,4,PMP_X,SUPERVISOR));
+        assert!(!pmp.check(0x7ffc,4,PMP_X,SUPERVISOR));
+        // an entry that is off matches nothing
+        assert!(!pmp.check(0x10000,4,PMP_R,SUPERVISOR));
+    }
+
+    #[test]
+    fn napot_covers_34_bit_addresses(){
+        let (_,pmp) = pmp(&[(PMP_NAPOT | PMP_R,u64::MAX >> 30)]);
+        assert!(pmp.check(0,4,PMP_R,SUPERVISOR));
+        assert!(pmp.check(0x3_ffff_fffc,4,PMP_R,SUPERVISOR));
+    }
+
+    #[test]
+    fn accesses_partly_covered_by_the_first_match_fail(){
+        let (_,pmp) = pmp(&[
+            (PMP_NA4 | PMP_R,0x1000),
+            (PMP_TOR | PMP_R,0x2000),
+            (PMP_NAPOT | PMP_R,napot(0,0x1_0000_0000))
+        ]);
+        // each of these lies inside the catch-all entry but straddles an
+        // edge of a higher-priority one
+        assert!(!pmp.check(0x0ffe,4,PMP_R,SUPERVISOR));
+        assert!(!pmp.check(0x1ffe,4,PMP_R,SUPERVISOR));
+        assert!(!pmp.check(0x1ffe,4,PMP_R,MACHINE));
+        assert!(pmp.check(0x2000,4,PMP_R,SUPERVISOR));
+    }
+
+    #[test]
+    fn machine_mode_bypasses_unlocked_entries_only(){
+        let (_,pmp) = pmp(&[
+            (PMP_NA4,0x1000),
+            (PMP_NA4 | PMP_L | PMP_R,0x2000)
+        ]);
+        assert!(pmp.check(0x1000,4,PMP_W,MACHINE));
+        assert!(!pmp.check(0x1000,4,PMP_R,SUPERVISOR));
+        assert!(pmp.check(0x2000,4,PMP_R,MACHINE));
+        assert!(!pmp.check(0x2000,4,PMP_W,MACHINE));
+        // memory no entry matches is M-mode's alone
+        assert!(pmp.check(0x3000,4,PMP_W,MACHINE));
+        assert!(!pmp.check(0x3000,4,PMP_R,SUPERVISOR));
+    }
+
+    #[test]
+    fn locked_entries_ignore_writes(){
+        let (mut csr,_) = pmp(&[(PMP_NA4 | PMP_L | PMP_R,0x1000),(PMP_NA4 | PMP_R,0x2000)]);
+        csr.store(PMPCFG0,(PMP_NA4 | PMP_X) as u32 * 0x101).unwrap();
+        csr.store(PMPADDR0,0).unwrap();
+        csr.store(PMPADDR0 + 1,0).unwrap();
+        assert_eq!(csr.pmpcfg(0),PMP_NA4 | PMP_L | PMP_R);
+        assert_eq!(csr.pmpcfg(1),PMP_NA4 | PMP_X);
+        assert_eq!(csr.csrs[PMPADDR0],0x1000 >> 2);
+        assert_eq!(csr.csrs[PMPADDR0 + 1],0);
+    }
+
+    #[test]
+    fn a_locked_tor_entry_locks_the_address_below_it(){
+        let (mut csr,_) = pmp(&[(PMP_OFF,0x1000),(PMP_TOR | PMP_L | PMP_R,0x2000)]);
+        csr.store(PMPADDR0,0).unwrap();
+        assert_eq!(csr.csrs[PMPADDR0],0x1000 >> 2);
+        // a locked entry of another mode leaves it writable
+        let (mut csr,_) = pmp(&[(PMP_OFF,0x1000),(PMP_NA4 | PMP_L | PMP_R,0x2000)]);
+        csr.store(PMPADDR0,0).unwrap();
+        assert_eq!(csr.csrs[PMPADDR0],0);
+    }
+}
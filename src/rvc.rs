@@ -0,0 +1,257 @@
+// Expansion of RV32C (including the RV32FC/RV32DC loads and stores) into
+// the equivalent 32-bit instructions, so that `Cpu::execute` only has to
+// know about one encoding.
+
+/// Whether the low two bits mark `inst` as a 16-bit instruction.
+pub fn is_compressed(inst:u32) -> bool{
+    inst & 0b11 != 0b11
+}
+
+fn bits(inst:u32,hi:u32,lo:u32) -> u32{
+    (inst >> lo) & ((1 << (hi - lo + 1)) - 1)
+}
+
+/// Sign-extend the low `width` bits of `value`.
+fn sext(value:u32,width:u32) -> u32{
+    ((value << (32 - width)) as i32 >> (32 - width)) as u32
+}
+
+fn r_type(funct7:u32,rs2:u32,rs1:u32,funct3:u32,rd:u32,opcode:u32) -> u32{
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn i_type(imm:u32,rs1:u32,funct3:u32,rd:u32,opcode:u32) -> u32{
+    (imm & 0xfff) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn s_type(imm:u32,rs2:u32,rs1:u32,funct3:u32,opcode:u32) -> u32{
+    bits(imm,11,5) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | bits(imm,4,0) << 7 | opcode
+}
+
+fn b_type(imm:u32,rs2:u32,rs1:u32,funct3:u32) -> u32{
+    bits(imm,12,12) << 31 | bits(imm,10,5) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12
+        | bits(imm,4,1) << 8 | bits(imm,11,11) << 7 | 0x63
+}
+
+fn j_type(imm:u32,rd:u32) -> u32{
+    bits(imm,20,20) << 31 | bits(imm,10,1) << 21 | bits(imm,11,11) << 20 | bits(imm,19,12) << 12 | rd << 7 | 0x6f
+}
+
+/// Expand a 16-bit instruction, or `None` if the encoding is reserved or
+/// belongs to RV64/RV128 only.
+pub fn expand(inst:u32) -> Option<u32>{
+    let funct3 = bits(inst,15,13);
+    // full register fields and the 3-bit "prime" fields (x8-x15)
+    let rd = bits(inst,11,7);
+    let rs2 = bits(inst,6,2);
+    let rd_p = bits(inst,4,2) + 8;
+    let rs1_p = bits(inst,9,7) + 8;
+    // CI-format immediate imm[5|4:0] = inst[12|6:2]
+    let ci_imm = sext(bits(inst,12,12) << 5 | bits(inst,6,2),6);
+    // offsets of the word and doubleword CL/CS forms
+    let lw_off = bits(inst,12,10) << 3 | bits(inst,6,6) << 2 | bits(inst,5,5) << 6;
+    let ld_off = bits(inst,12,10) << 3 | bits(inst,6,5) << 6;
+
+    let expanded = match (inst & 0b11,funct3){
+        (0b00,0b000) => {
+            // c.addi4spn
+            let imm = bits(inst,12,11) << 4 | bits(inst,10,7) << 6 | bits(inst,6,6) << 2 | bits(inst,5,5) << 3;
+            if imm == 0{
+                return None;
+            }
+            i_type(imm,2,0x0,rd_p,0x13)
+        }
+        (0b00,0b001) => i_type(ld_off,rs1_p,0x3,rd_p,0x07), // c.fld
+        (0b00,0b010) => i_type(lw_off,rs1_p,0x2,rd_p,0x03), // c.lw
+        (0b00,0b011) => i_type(lw_off,rs1_p,0x2,rd_p,0x07), // c.flw
+        (0b00,0b101) => s_type(ld_off,rd_p,rs1_p,0x3,0x27), // c.fsd
+        (0b00,0b110) => s_type(lw_off,rd_p,rs1_p,0x2,0x23), // c.sw
+        (0b00,0b111) => s_type(lw_off,rd_p,rs1_p,0x2,0x27), // c.fsw
+
+        (0b01,0b000) => i_type(ci_imm,rd,0x0,rd,0x13), // c.addi (c.nop when rd == 0)
+        (0b01,0b001) | (0b01,0b101) => {
+            // c.jal / c.j
+            // imm[11|4|9:8|10|6|7|3:1|5] = inst[12|11|10:9|8|7|6|5:3|2]
+            let imm = bits(inst,12,12) << 11 | bits(inst,11,11) << 4 | bits(inst,10,9) << 8
+                | bits(inst,8,8) << 10 | bits(inst,7,7) << 6 | bits(inst,6,6) << 7
+                | bits(inst,5,3) << 1 | bits(inst,2,2) << 5;
+            j_type(sext(imm,12),if funct3 == 0b001 {1} else {0})
+        }
+        (0b01,0b010) => i_type(ci_imm,0,0x0,rd,0x13), // c.li
+        (0b01,0b011) if rd == 2 => {
+            // c.addi16sp
+            // nzimm[9|4|6|8:7|5] = inst[12|6|5|4:3|2]
+            let imm = bits(inst,12,12) << 9 | bits(inst,6,6) << 4 | bits(inst,5,5) << 6
+                | bits(inst,4,3) << 7 | bits(inst,2,2) << 5;
+            if imm == 0{
+                return None;
+            }
+            i_type(sext(imm,10),2,0x0,2,0x13)
+        }
+        (0b01,0b011) => {
+            // c.lui
+            if ci_imm == 0{
+                return None;
+            }
+            (ci_imm << 12) | rd << 7 | 0x37
+        }
+        (0b01,0b100) => {
+            let shamt = bits(inst,6,2);
+            match (bits(inst,11,10),bits(inst,12,12)){
+                // shamt[5] must be zero on RV32
+                (0b00,0) => r_type(0x00,shamt,rs1_p,0x5,rs1_p,0x13), // c.srli
+                (0b01,0) => r_type(0x20,shamt,rs1_p,0x5,rs1_p,0x13), // c.srai
+                (0b10,_) => i_type(ci_imm,rs1_p,0x7,rs1_p,0x13),     // c.andi
+                (0b11,0) => {
+                    let (funct7,funct3) = match bits(inst,6,5){
+                        0b00 => (0x20,0x0), // c.sub
+                        0b01 => (0x00,0x4), // c.xor
+                        0b10 => (0x00,0x6), // c.or
+                        _ => (0x00,0x7)     // c.and
+                    };
+                    r_type(funct7,rd_p,rs1_p,funct3,rs1_p,0x33)
+                }
+                _ => return None
+            }
+        }
+        (0b01,0b110) | (0b01,0b111) => {
+            // c.beqz / c.bnez
+            // offset[8|4:3|7:6|2:1|5] = inst[12|11:10|6:5|4:3|2]
+            let imm = bits(inst,12,12) << 8 | bits(inst,11,10) << 3 | bits(inst,6,5) << 6
+                | bits(inst,4,3) << 1 | bits(inst,2,2) << 5;
+            b_type(sext(imm,9),0,rs1_p,funct3 & 1)
+        }
+
+        (0b10,0b000) => {
+            // c.slli
+            if bits(inst,12,12) != 0{
+                return None;
+            }
+            r_type(0x00,rs2,rd,0x1,rd,0x13)
+        }
+        (0b10,0b001) => {
+            // c.fldsp
+            let imm = bits(inst,12,12) << 5 | bits(inst,6,5) << 3 | bits(inst,4,2) << 6;
+            i_type(imm,2,0x3,rd,0x07)
+        }
+        (0b10,0b010) | (0b10,0b011) => {
+            // c.lwsp / c.flwsp
+            let imm = bits(inst,12,12) << 5 | bits(inst,6,4) << 2 | bits(inst,3,2) << 6;
+            if funct3 == 0b010 && rd == 0{
+                return None;
+            }
+            i_type(imm,2,0x2,rd,if funct3 == 0b010 {0x03} else {0x07})
+        }
+        (0b10,0b100) => {
+            match (bits(inst,12,12),rd,rs2){
+                (0,0,0) => return None,
+                (0,_,0) => i_type(0,rd,0x0,0,0x67),        // c.jr
+                (0,_,_) => r_type(0x00,rs2,0,0x0,rd,0x33),  // c.mv
+                (1,0,0) => 0x0010_0073,                    // c.ebreak
+                (1,_,0) => i_type(0,rd,0x0,1,0x67),        // c.jalr
+                _ => r_type(0x00,rs2,rd,0x0,rd,0x33)       // c.add
+            }
+        }
+        (0b10,0b101) => {
+            // c.fsdsp
+            let imm = bits(inst,12,10) << 3 | bits(inst,9,7) << 6;
+            s_type(imm,rs2,2,0x3,0x27)
+        }
+        (0b10,0b110) | (0b10,0b111) => {
+            // c.swsp / c.fswsp
+            let imm = bits(inst,12,9) << 2 | bits(inst,8,7) << 6;
+            s_type(imm,rs2,2,0x2,if funct3 == 0b110 {0x23} else {0x27})
+        }
+        _ => return None
+    };
+    Some(expanded)
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn check(cases:&[(u32,u32,&str)]){
+        for &(inst,expanded,name) in cases{
+            assert!(is_compressed(inst),"{}",name);
+            assert_eq!(expand(inst),Some(expanded),"{} ({:#06x})",name,inst);
+        }
+    }
+
+    #[test]
+    fn quadrant_0(){
+        check(&[
+            (0x1fe0,0x3fc10413,"c.addi4spn s0,sp,1020"),
+            (0x3fe8,0x0f87b507,"c.fld fa0,248(a5)"),
+            (0x5fe8,0x07c7a503,"c.lw a0,124(a5)"),
+            (0x60ac,0x0404a587,"c.flw fa1,64(s1)"),
+            (0xa788,0x00a7b427,"c.fsd fa0,8(a5)"),
+            (0xdfe8,0x06a7ae23,"c.sw a0,124(a5)"),
+            (0xe0cc,0x00b4a227,"c.fsw fa1,4(s1)")
+        ]);
+    }
+
+    #[test]
+    fn quadrant_1(){
+        check(&[
+            (0x0001,0x00000013,"c.nop"),
+            (0x1501,0xfe050513,"c.addi a0,-32"),
+            (0x3001,0x801ff0ef,"c.jal -2048"),
+            (0x47fd,0x01f00793,"c.li a5,31"),
+            (0x7101,0xe0010113,"c.addi16sp sp,-512"),
+            (0x7501,0xfffe0537,"c.lui a0,0xfffe0"),
+            (0x80fd,0x01f4d493,"c.srli s1,31"),
+            (0x8785,0x4017d793,"c.srai a5,1"),
+            (0x997d,0xfff57513,"c.andi a0,-1"),
+            (0x8c1d,0x40f40433,"c.sub s0,a5"),
+            (0x8c3d,0x00f44433,"c.xor s0,a5"),
+            (0x8c5d,0x00f46433,"c.or s0,a5"),
+            (0x8c7d,0x00f47433,"c.and s0,a5"),
+            (0xaffd,0x7fe0006f,"c.j 2046"),
+            (0xd101,0xf00500e3,"c.beqz a0,-256"),
+            (0xecfd,0x0e049f63,"c.bnez s1,254")
+        ]);
+    }
+
+    #[test]
+    fn quadrant_2(){
+        check(&[
+            (0x057e,0x01f51513,"c.slli a0,31"),
+            (0x357e,0x1f813507,"c.fldsp fa0,504(sp)"),
+            (0x50fe,0x0fc12083,"c.lwsp ra,252(sp)"),
+            (0x6592,0x00412587,"c.flwsp fa1,4(sp)"),
+            (0x8082,0x00008067,"c.jr ra"),
+            (0x852e,0x00b00533,"c.mv a0,a1"),
+            (0x9002,0x00100073,"c.ebreak"),
+            (0x9282,0x000280e7,"c.jalr t0"),
+            (0x952e,0x00b50533,"c.add a0,a1"),
+            (0xbfaa,0x1ea13c27,"c.fsdsp fa0,504(sp)"),
+            (0xdf86,0x0e112e23,"c.swsp ra,252(sp)"),
+            (0xe22e,0x00b12227,"c.fswsp fa1,4(sp)")
+        ]);
+    }
+
+    #[test]
+    fn reserved_encodings_are_illegal(){
+        for (inst,name) in [
+            (0x0000,"all zeros"),
+            (0x0004,"c.addi4spn with imm 0"),
+            (0x6501,"c.lui with imm 0"),
+            (0x6101,"c.addi16sp with imm 0"),
+            (0x8002,"c.jr with rs1 0"),
+            (0x4002,"c.lwsp with rd 0"),
+            (0x9081,"c.srli with shamt[5] set"),
+            (0x1082,"c.slli with shamt[5] set"),
+            (0x9c1d,"c.subw (RV64 only)")
+        ]{
+            assert_eq!(expand(inst),None,"{} ({:#06x})",name,inst);
+        }
+    }
+
+    #[test]
+    fn full_size_instructions_are_not_compressed(){
+        assert!(!is_compressed(0x00000013));
+        assert!(is_compressed(0x0001));
+    }
+}
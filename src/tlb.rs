@@ -0,0 +1,137 @@
+// A direct-mapped software TLB caching Sv32 leaf PTEs by virtual page and
+// ASID. Permissions are checked against the cached PTE on every access, so
+// changes to the privilege mode, SUM or MXR need no flush.
+
+use crate::param::PTE_G;
+
+const TLB_ENTRIES: usize = 256;
+
+#[derive(Clone,Copy)]
+pub struct TlbEntry{
+    /// Virtual page number of the 4 KiB page; a superpage is cached one
+    /// 4 KiB page at a time.
+    pub vpn: u32,
+    pub asid: u32,
+    /// The leaf PTE as read from memory.
+    pub pte: u32,
+    /// Physical address of the leaf PTE, for setting its A and D bits.
+    pub pte_addr: u64,
+    pub superpage: bool
+}
+
+impl TlbEntry{
+    fn is_global(&self) -> bool{
+        self.pte & PTE_G != 0
+    }
+    /// Whether the entry translates `vaddr`, in its 4 KiB page or anywhere
+    /// in its superpage.
+    fn covers(&self,vaddr:u32) -> bool{
+        let vpn = vaddr >> 12;
+        self.vpn == vpn || (self.superpage && self.vpn >> 10 == vpn >> 10)
+    }
+}
+
+pub struct Tlb{
+    entries: Vec<Option<TlbEntry>>,
+    pub hits: u64,
+    pub misses: u64
+}
+
+impl Tlb{
+    pub fn new() -> Self{
+        Self{entries:vec![None;TLB_ENTRIES],hits:0,misses:0}
+    }
+    fn index(vpn:u32) -> usize{
+        vpn as usize % TLB_ENTRIES
+    }
+    /// The entry for `vpn` visible to `asid`. The caller counts the hit or
+    /// miss, as it may still walk again for an entry it cannot use.
+    pub fn lookup(&self,vpn:u32,asid:u32) -> Option<TlbEntry>{
+        self.entries[Self::index(vpn)].filter(|e| e.vpn == vpn && (e.asid == asid || e.is_global()))
+    }
+    pub fn insert(&mut self,entry:TlbEntry){
+        self.entries[Self::index(entry.vpn)] = Some(entry);
+    }
+    /// Drop entries as `sfence.vma` does: those covering `vaddr`, or all if
+    /// none is given, and only non-global ones of `asid` if one is given.
+    pub fn flush(&mut self,vaddr:Option<u32>,asid:Option<u32>){
+        for slot in &mut self.entries{
+            let hit = slot.is_some_and(|e| {
+                vaddr.is_none_or(|vaddr| e.covers(vaddr)) && asid.is_none_or(|asid| e.asid == asid && !e.is_global())
+            });
+            if hit{
+                *slot = None;
+            }
+        }
+    }
+    pub fn flush_all(&mut self){
+        self.entries.fill(None);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const ASID: u32 = 1;
+    const OTHER_ASID: u32 = 2;
+
+    fn entry(vaddr:u32,asid:u32,global:bool,superpage:bool) -> TlbEntry{
+        let pte = if global {PTE_G} else {0};
+        TlbEntry{vpn:vaddr >> 12,asid,pte,pte_addr:0,superpage}
+    }
+    fn cached(tlb:&Tlb,vaddr:u32,asid:u32) -> bool{
+        tlb.lookup(vaddr >> 12,asid).is_some()
+    }
+
+    #[test]
+    fn global_entries_are_shared_by_every_asid(){
+        let mut tlb = Tlb::new();
+        tlb.insert(entry(0x1000,ASID,false,false));
+        tlb.insert(entry(0x2000,ASID,true,false));
+        assert!(cached(&tlb,0x1000,ASID));
+        assert!(!cached(&tlb,0x1000,OTHER_ASID));
+        assert!(cached(&tlb,0x2000,OTHER_ASID));
+    }
+
+    #[test]
+    fn flushing_an_address_drops_the_entries_covering_it(){
+        let mut tlb = Tlb::new();
+        tlb.insert(entry(0x1000,ASID,false,false));
+        tlb.insert(entry(0x2000,OTHER_ASID,true,false));
+        // a superpage entry for a 4 KiB page away from the flushed address
+        tlb.insert(entry(0x0040_5000,ASID,false,true));
+        tlb.flush(Some(0x1234),None);
+        assert!(!cached(&tlb,0x1000,ASID));
+        assert!(cached(&tlb,0x2000,OTHER_ASID));
+        tlb.flush(Some(0x0043_0000),None);
+        assert!(!cached(&tlb,0x0040_5000,ASID));
+        tlb.flush(Some(0x2000),None);
+        assert!(!cached(&tlb,0x2000,OTHER_ASID));
+    }
+
+    #[test]
+    fn flushing_an_asid_keeps_global_entries(){
+        let mut tlb = Tlb::new();
+        tlb.insert(entry(0x1000,ASID,false,false));
+        tlb.insert(entry(0x2000,ASID,true,false));
+        tlb.insert(entry(0x3000,OTHER_ASID,false,false));
+        tlb.insert(entry(0x0080_0000,ASID,false,true));
+        tlb.flush(Some(0x1000),Some(OTHER_ASID));
+        assert!(cached(&tlb,0x1000,ASID));
+        tlb.flush(None,Some(ASID));
+        assert!(!cached(&tlb,0x1000,ASID));
+        assert!(!cached(&tlb,0x0080_0000,ASID));
+        assert!(cached(&tlb,0x2000,ASID));
+        assert!(cached(&tlb,0x3000,OTHER_ASID));
+        // an address and an ASID together drop only what matches both
+        tlb.insert(entry(0x1000,ASID,false,false));
+        tlb.flush(Some(0x2000),Some(ASID));
+        assert!(cached(&tlb,0x2000,ASID));
+        tlb.flush(Some(0x1000),Some(ASID));
+        assert!(!cached(&tlb,0x1000,ASID));
+        tlb.flush(None,None);
+        assert!(!cached(&tlb,0x2000,ASID));
+        assert!(!cached(&tlb,0x3000,OTHER_ASID));
+    }
+}
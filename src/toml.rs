@@ -0,0 +1,271 @@
+// A parser for the subset of TOML used by machine description files:
+// comments, bare and quoted keys, [tables], [[arrays of tables]], and
+// string, integer, boolean and array values. Dotted keys, inline tables,
+// floats and dates are rejected.
+
+use std::collections::BTreeMap;
+
+pub type Table = BTreeMap<String,Value>;
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Value{
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table)
+}
+
+impl Value{
+    pub fn type_name(&self) -> &'static str{
+        match self{
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table"
+        }
+    }
+}
+
+struct Parser<'a>{
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize
+}
+
+impl Parser<'_>{
+    fn error(&self,msg:&str) -> String{
+        format!("line {}: {}",self.line,msg)
+    }
+    fn peek(&mut self) -> Option<char>{
+        self.chars.peek().copied()
+    }
+    fn next(&mut self) -> Option<char>{
+        let c = self.chars.next();
+        if c == Some('\n'){
+            self.line += 1;
+        }
+        c
+    }
+    fn expect(&mut self,want:char) -> Result<(),String>{
+        match self.next(){
+            Some(c) if c == want => Ok(()),
+            Some(c) => Err(self.error(&format!("expected '{}', found '{}'",want,c))),
+            None => Err(self.error(&format!("expected '{}' at end of file",want)))
+        }
+    }
+    /// Skip spaces and tabs, and a comment up to the end of the line.
+    fn skip_space(&mut self){
+        while let Some(c) = self.peek(){
+            match c{
+                ' ' | '\t' => {
+                    self.next();
+                }
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n'){
+                        self.next();
+                    }
+                }
+                _ => break
+            }
+        }
+    }
+    /// Skip whitespace, comments and newlines.
+    fn skip_blank(&mut self){
+        loop{
+            self.skip_space();
+            match self.peek(){
+                Some('\n' | '\r') => {
+                    self.next();
+                }
+                _ => break
+            }
+        }
+    }
+    /// Require the end of the line after a key/value pair or table header.
+    fn end_of_line(&mut self) -> Result<(),String>{
+        self.skip_space();
+        match self.peek(){
+            None | Some('\n' | '\r') => Ok(()),
+            Some(c) => Err(self.error(&format!("unexpected '{}' after value",c)))
+        }
+    }
+    fn key(&mut self) -> Result<String,String>{
+        match self.peek(){
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let mut key = String::new();
+                while let Some(c) = self.peek().filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-'){
+                    key.push(c);
+                    self.next();
+                }
+                if key.is_empty(){
+                    return Err(self.error("expected a key"));
+                }
+                if self.peek() == Some('.'){
+                    return Err(self.error("dotted keys are not supported"));
+                }
+                Ok(key)
+            }
+        }
+    }
+    fn basic_string(&mut self) -> Result<String,String>{
+        self.expect('"')?;
+        let mut s = String::new();
+        loop{
+            match self.next(){
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.next(){
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        _ => return Err(self.error("unsupported escape sequence"))
+                    };
+                    s.push(c);
+                }
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => s.push(c)
+            }
+        }
+    }
+    fn literal_string(&mut self) -> Result<String,String>{
+        self.expect('\'')?;
+        let mut s = String::new();
+        loop{
+            match self.next(){
+                Some('\'') => return Ok(s),
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => s.push(c)
+            }
+        }
+    }
+    fn integer(&mut self) -> Result<i64,String>{
+        let mut text = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_alphanumeric() || "+-_.".contains(*c)){
+            text.push(c);
+            self.next();
+        }
+        let (negative,digits) = match text.strip_prefix('-'){
+            Some(rest) => (true,rest),
+            None => (false,text.strip_prefix('+').unwrap_or(&text))
+        };
+        let digits = digits.replace('_',"");
+        let parsed = match digits.get(..2){
+            Some("0x") => i64::from_str_radix(&digits[2..],16),
+            Some("0o") => i64::from_str_radix(&digits[2..],8),
+            Some("0b") => i64::from_str_radix(&digits[2..],2),
+            _ => digits.parse()
+        };
+        let value = parsed.map_err(|_| self.error(&format!("invalid integer '{}'",text)))?;
+        Ok(if negative {-value} else {value})
+    }
+    fn array(&mut self) -> Result<Vec<Value>,String>{
+        self.expect('[')?;
+        let mut values = Vec::new();
+        loop{
+            self.skip_blank();
+            if self.peek() == Some(']'){
+                self.next();
+                return Ok(values);
+            }
+            values.push(self.value()?);
+            self.skip_blank();
+            match self.next(){
+                Some(',') => (),
+                Some(']') => return Ok(values),
+                _ => return Err(self.error("expected ',' or ']' in array"))
+            }
+        }
+    }
+    fn value(&mut self) -> Result<Value,String>{
+        match self.peek(){
+            Some('"') => Ok(Value::String(self.basic_string()?)),
+            Some('\'') => Ok(Value::String(self.literal_string()?)),
+            Some('[') => Ok(Value::Array(self.array()?)),
+            Some('t' | 'f') => {
+                let mut word = String::new();
+                while let Some(c) = self.peek().filter(|c| c.is_ascii_alphabetic()){
+                    word.push(c);
+                    self.next();
+                }
+                match word.as_str(){
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => Err(self.error(&format!("invalid value '{}'",word)))
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '+' || c == '-' => Ok(Value::Integer(self.integer()?)),
+            Some(c) => Err(self.error(&format!("unsupported value starting with '{}'",c))),
+            None => Err(self.error("expected a value"))
+        }
+    }
+}
+
+/// Parse a document into its root table.
+pub fn parse(text:&str) -> Result<Table,String>{
+    let mut p = Parser{chars:text.chars().peekable(),line:1};
+    let mut root = Table::new();
+    // the table that key/value pairs go to: none for the root, otherwise the
+    // name of a [table] or of the last element of an [[array]]
+    let mut current: Option<String> = None;
+    loop{
+        p.skip_blank();
+        match p.peek(){
+            None => return Ok(root),
+            Some('[') => {
+                p.next();
+                let is_array = p.peek() == Some('[');
+                if is_array{
+                    p.next();
+                }
+                p.skip_space();
+                let name = p.key()?;
+                p.skip_space();
+                p.expect(']')?;
+                if is_array{
+                    p.expect(']')?;
+                    match root.entry(name.clone()).or_insert(Value::Array(Vec::new())){
+                        Value::Array(elements) if elements.iter().all(|e| matches!(e,Value::Table(_))) => {
+                            elements.push(Value::Table(Table::new()));
+                        }
+                        _ => return Err(p.error(&format!("'{}' is not an array of tables",name)))
+                    }
+                } else{
+                    if root.contains_key(&name){
+                        return Err(p.error(&format!("'{}' is defined twice",name)));
+                    }
+                    root.insert(name.clone(),Value::Table(Table::new()));
+                }
+                p.end_of_line()?;
+                current = Some(name);
+            }
+            Some(_) => {
+                let key = p.key()?;
+                p.skip_space();
+                p.expect('=')?;
+                p.skip_space();
+                let value = p.value()?;
+                p.end_of_line()?;
+                let table = match &current{
+                    None => &mut root,
+                    Some(name) => match root.get_mut(name){
+                        Some(Value::Table(table)) => table,
+                        Some(Value::Array(elements)) => match elements.last_mut(){
+                            Some(Value::Table(table)) => table,
+                            _ => unreachable!()
+                        },
+                        _ => unreachable!()
+                    }
+                };
+                if table.contains_key(&key){
+                    return Err(p.error(&format!("'{}' is defined twice",key)));
+                }
+                table.insert(key,value);
+            }
+        }
+    }
+}
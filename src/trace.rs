@@ -0,0 +1,181 @@
+// Tracing of what the harts do: the instructions they run, the registers
+// and memory those change and the traps they take, written to a file or
+// stderr and filtered by pc and privilege mode.
+
+use std::fs::File;
+use std::io::{self,BufWriter,Write};
+use crate::disasm::disassemble;
+use crate::elf::SymbolTable;
+use crate::interrupt::MASK_INTERRUPT_BIT;
+use crate::rvc;
+
+pub const TRACE_INSTRUCTIONS: u32 = 1 << 0;
+pub const TRACE_REGISTERS: u32 = 1 << 1;
+pub const TRACE_MEMORY: u32 = 1 << 2;
+pub const TRACE_TRAPS: u32 = 1 << 3;
+/// Spike's `--log-commits` format.
+pub const TRACE_COMMITS: u32 = 1 << 4;
+
+/// Every privilege mode, as a set of `1 << mode`.
+pub const ALL_MODES: u32 = 0b1011;
+
+/// Parse a comma-separated list of trace outputs.
+pub fn parse_outputs(s:&str) -> Result<u32,String>{
+    s.split(',').try_fold(0,|outputs,name| Ok(outputs | match name{
+        "off" => 0,
+        "insns" => TRACE_INSTRUCTIONS,
+        "regs" => TRACE_REGISTERS,
+        "mem" => TRACE_MEMORY,
+        "traps" => TRACE_TRAPS,
+        "commits" => TRACE_COMMITS,
+        _ => return Err(format!("unknown trace output '{}'",name))
+    }))
+}
+
+/// Parse a set of privilege modes written as letters, like `su`.
+pub fn parse_modes(s:&str) -> Result<u32,String>{
+    s.chars().try_fold(0,|modes,c| Ok(modes | match c.to_ascii_lowercase(){
+        'u' => 1 << 0,
+        's' => 1 << 1,
+        'm' => 1 << 3,
+        _ => return Err(format!("unknown privilege mode '{}'",c))
+    }))
+}
+
+/// A register written by an instruction, with its new value.
+pub enum Written{
+    X(u32,u32),
+    F(u32,u64)
+}
+
+/// A load or store of `size` bits at a virtual address.
+pub struct Access{
+    pub addr: u32,
+    pub size: u32,
+    pub value: u64,
+    pub store: bool
+}
+
+pub struct Trap{
+    pub cause: u32,
+    pub tval: u32,
+    pub epc: u32,
+    /// The modes the trap was taken from and to.
+    pub from: u32,
+    pub to: u32,
+    pub handler: u32
+}
+
+/// What a hart did in one step.
+pub struct Record{
+    pub pc: u32,
+    pub mode: u32,
+    /// The instruction as fetched, if the hart fetched one.
+    pub inst: Option<u32>,
+    /// Whether the instruction completed without an exception.
+    pub retired: bool,
+    pub written: Option<Written>,
+    pub accesses: Vec<Access>,
+    pub traps: Vec<Trap>
+}
+
+impl Record{
+    pub fn new(pc:u32,mode:u32) -> Self{
+        Self{pc,mode,inst:None,retired:false,written:None,accesses:Vec::new(),traps:Vec::new()}
+    }
+}
+
+/// Whether the 32-bit instruction `inst` writes rd of the integer (`false`)
+/// or floating-point (`true`) register file, and which.
+pub fn destination(inst:u32) -> Option<(bool,u32)>{
+    let rd = (inst >> 7) & 0x1f;
+    let float = match inst & 0x7f{
+        0x37 | 0x17 | 0x6f | 0x67 | 0x03 | 0x13 | 0x33 | 0x2f => false,
+        0x73 if (inst >> 12) & 0x7 != 0 => false,
+        0x07 | 0x43 | 0x47 | 0x4b | 0x4f => true,
+        // compares, conversions to integers, fclass and fmv.x.w
+        0x53 => !matches!(inst >> 27,0x14 | 0x18 | 0x1c),
+        _ => return None
+    };
+    if !float && rd == 0 {None} else {Some((float,rd))}
+}
+
+fn mode_letter(mode:u32) -> char{
+    ['U','S','H','M'][mode as usize & 0b11]
+}
+
+pub struct Tracer{
+    outputs: u32,
+    /// Only instructions at `start..end` are traced.
+    pc_range: Option<(u32,u32)>,
+    /// Only instructions run in these modes, as a set of `1 << mode`, are traced.
+    modes: u32,
+    out: Box<dyn Write>
+}
+
+impl Tracer{
+    /// A tracer writing `outputs` to `file`, or to stderr without one.
+    pub fn new(outputs:u32,file:Option<&str>,pc_range:Option<(u32,u32)>,modes:u32) -> io::Result<Self>{
+        let out:Box<dyn Write> = match file{
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(io::stderr())
+        };
+        Ok(Self{outputs,pc_range,modes,out})
+    }
+    /// Write what hart `hart` did in a step.
+    pub fn write(&mut self,hart:usize,record:&Record,symbols:&SymbolTable) -> io::Result<()>{
+        if self.modes & (1 << record.mode) == 0 || self.pc_range.is_some_and(|(start,end)| record.pc < start || record.pc >= end){
+            return Ok(());
+        }
+        let prefix = format!("core {:3}:",hart);
+        let out = &mut self.out;
+        if let Some(inst) = record.inst{
+            let bits = if rvc::is_compressed(inst) {format!("0x{:04x}",inst)} else {format!("0x{:08x}",inst)};
+            if self.outputs & TRACE_INSTRUCTIONS != 0{
+                if let Some((name,0)) = symbols.lookup(record.pc){
+                    writeln!(out,"{} >>>>  {}",prefix,name)?;
+                }
+                let expanded = if rvc::is_compressed(inst) {rvc::expand(inst).unwrap_or(0)} else {inst};
+                writeln!(out,"{} {} 0x{:08x} ({}) {}",prefix,mode_letter(record.mode),record.pc,bits,disassemble(expanded))?;
+            }
+            if self.outputs & TRACE_COMMITS != 0 && record.retired{
+                write!(out,"{} {} 0x{:08x} ({})",prefix,record.mode,record.pc,bits)?;
+                match record.written{
+                    Some(Written::X(r,value)) => write!(out," x{:<2} 0x{:08x}",r,value)?,
+                    Some(Written::F(r,value)) => write!(out," f{:<2} 0x{:016x}",r,value)?,
+                    None => ()
+                }
+                for access in record.accesses.iter().filter(|a| !a.store){
+                    write!(out," mem 0x{:08x}",access.addr)?;
+                }
+                for access in record.accesses.iter().filter(|a| a.store){
+                    write!(out," mem 0x{:08x} 0x{:0width$x}",access.addr,access.value,width = access.size as usize / 4)?;
+                }
+                writeln!(out)?;
+            }
+        }
+        if self.outputs & TRACE_REGISTERS != 0 && record.retired{
+            match record.written{
+                Some(Written::X(r,value)) => writeln!(out,"{} {} 0x{:08x} x{:<2} 0x{:08x}",prefix,mode_letter(record.mode),record.pc,r,value)?,
+                Some(Written::F(r,value)) => writeln!(out,"{} {} 0x{:08x} f{:<2} 0x{:016x}",prefix,mode_letter(record.mode),record.pc,r,value)?,
+                None => ()
+            }
+        }
+        if self.outputs & TRACE_MEMORY != 0{
+            for access in &record.accesses{
+                let kind = if access.store {"store"} else {"load "};
+                writeln!(out,"{} {} 0x{:08x} {} 0x{:08x} 0x{:0width$x}",prefix,mode_letter(record.mode),record.pc,
+                    kind,access.addr,access.value,width = access.size as usize / 4)?;
+            }
+        }
+        if self.outputs & TRACE_TRAPS != 0{
+            for trap in &record.traps{
+                let code = trap.cause & !MASK_INTERRUPT_BIT;
+                let kind = if trap.cause & MASK_INTERRUPT_BIT != 0 {"interrupt"} else {"exception"};
+                writeln!(out,"{} {} 0x{:08x} {} {} tval 0x{:08x} -> {} 0x{:08x}",prefix,mode_letter(trap.from),trap.epc,
+                    kind,code,trap.tval,mode_letter(trap.to),trap.handler)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,90 +1,274 @@
-use std::sync::{
-    atomic::{AtomicBool,Ordering},
-    Arc,Condvar,Mutex
-};
+use std::collections::VecDeque;
+use std::sync::{Arc,Mutex};
 use std::io::prelude::*;
 use std::io;
 use std::thread;
-use crate::param::*;
+use crate::device::{Device,Wakeup};
 use crate::exceptions::Exception;
+use crate::fdt::Fdt;
 
-const UART_SIZE: u32 = 8;
+// register offsets; DLL and DLM replace RBR/THR and IER while LCR.DLAB is set
+const UART_RBR: u32 = 0;
+const UART_THR: u32 = 0;
+const UART_DLL: u32 = 0;
+const UART_IER: u32 = 1;
+const UART_DLM: u32 = 1;
+const UART_IIR: u32 = 2;
+const UART_FCR: u32 = 2;
+const UART_LCR: u32 = 3;
+const UART_MCR: u32 = 4;
 const UART_LSR: u32 = 5;
-const MASK_UART_LSR_RX : u8 = 1;
-const MASK_UART_LSR_TX : u8 = 0x20;
-const UART_IRQ : u32 = 10;
-const UART_RHR : u32 = 0;
-const UART_THR : u32 = 0;
-const UART_LCR : u32 = 3;
+const UART_MSR: u32 = 6;
+const UART_SCR: u32 = 7;
 
+const MASK_IER_RDI: u8 = 0x1;
+const MASK_IER_THRI: u8 = 0x2;
+const MASK_IIR_NO_INT: u8 = 0x1;
+const IIR_THRI: u8 = 0x2;
+const IIR_RDI: u8 = 0x4;
+const MASK_IIR_FIFO: u8 = 0xc0;
+const MASK_FCR_FIFO: u8 = 0x1;
+const MASK_FCR_CLEAR_RX: u8 = 0x2;
+const MASK_LCR_DLAB: u8 = 0x80;
+const MASK_MCR_LOOP: u8 = 0x10;
+const MASK_LSR_DR: u8 = 0x1;
+const MASK_LSR_THRE: u8 = 0x20;
+const MASK_LSR_TEMT: u8 = 0x40;
+// modem status: clear to send, data set ready and carrier detect are
+// asserted unless the loopback mode wires them to MCR
+const MSR_DEFAULT: u8 = 0xb0;
+/// Input clock advertised to the guest's driver, which derives the divisor
+/// latch values from it; the emulated UART ignores the baud rate anyway.
+const UART_CLOCK_FREQ: u32 = 3_686_400;
+
+/// A 16550-compatible UART. Transmitted bytes go straight to stdout, so the
+/// transmitter is always empty; received bytes come from stdin once
+/// `connect_stdin` has been called.
 pub struct UartController{
-    uart_regs:Arc<(Mutex<[u8;UART_SIZE as usize]>,Condvar)>,
-    interrupt:Arc<AtomicBool>
+    rx: Arc<Mutex<VecDeque<u8>>>,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    fifo: bool,
+    divisor: u16,
+    /// THR-empty interrupt, raised by each (instant) transmission and by
+    /// enabling it, cleared by reading IIR
+    thre_pending: bool
 }
 
 impl UartController{
     pub fn new() -> Self{
-        let mut arr = [0; UART_SIZE as usize];
-        // set send buffer empty
-        arr[UART_LSR as usize] |= MASK_UART_LSR_TX;
-        let uart_regs = Arc::new((Mutex::new(arr),Condvar::new()));
-        let interrupt = Arc::new(AtomicBool::new(false));
-
-        let read_uart = Arc::clone(&uart_regs);
-        let read_interrupt = Arc::clone(&interrupt);
-        let mut byte = [0];
+        Self{
+            rx: Arc::new(Mutex::new(VecDeque::new())),
+            ier: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            fifo: false,
+            divisor: 0,
+            thre_pending: false
+        }
+    }
+    /// Feed the host's stdin into the receiver from a background thread,
+    /// notifying `wakeup` of every byte.
+    pub fn connect_stdin(&mut self,wakeup:Arc<Wakeup>){
+        let rx = Arc::clone(&self.rx);
         thread::spawn(move ||{
-            loop{
-                match io::stdin().read(&mut byte){
-                    Ok(_) =>{
-                        let (uart_regs,cvar) = &*read_uart;
-                        let mut array = uart_regs.lock().unwrap();
-                        while (array[UART_LSR as usize] & MASK_UART_LSR_RX) == 1{
-                            array = cvar.wait(array).unwrap();
+            let mut byte = [0];
+            // stop at end of file or on a read error
+            while let Ok(1) = io::stdin().read(&mut byte){
+                rx.lock().unwrap().push_back(byte[0]);
+                wakeup.notify();
+            }
+        });
+    }
+    fn has_data(&self) -> bool{
+        !self.rx.lock().unwrap().is_empty()
+    }
+    /// The highest-priority pending interrupt, as an IIR identification.
+    fn interrupt_id(&self) -> Option<u8>{
+        if self.ier & MASK_IER_RDI != 0 && self.has_data(){
+            Some(IIR_RDI)
+        } else if self.ier & MASK_IER_THRI != 0 && self.thre_pending{
+            Some(IIR_THRI)
+        } else{
+            None
+        }
+    }
+    fn transmit(&mut self,byte:u8){
+        if self.mcr & MASK_MCR_LOOP != 0{
+            self.rx.lock().unwrap().push_back(byte);
+        } else{
+            let mut stdout = io::stdout();
+            stdout.write_all(&[byte]).and_then(|_| stdout.flush()).unwrap_or(());
+        }
+        self.thre_pending = true;
+    }
+}
+
+impl Device for UartController{
+    fn load(&mut self,offset:u32,_size:u32) -> Result<u32,Exception>{
+        let dlab = self.lcr & MASK_LCR_DLAB != 0;
+        let value = match offset{
+            UART_DLL if dlab => self.divisor as u8,
+            UART_DLM if dlab => (self.divisor >> 8) as u8,
+            UART_RBR => self.rx.lock().unwrap().pop_front().unwrap_or(0),
+            UART_IER => self.ier,
+            UART_IIR => {
+                let fifo = if self.fifo {MASK_IIR_FIFO} else {0};
+                match self.interrupt_id(){
+                    Some(id) => {
+                        if id == IIR_THRI{
+                            self.thre_pending = false;
                         }
-                        array[UART_RHR as usize] = byte[0];
-                        read_interrupt.store(true, Ordering::Release);
-                        array[UART_LSR as usize] |= MASK_UART_LSR_RX;
+                        id | fifo
                     }
-                    Err(e) => println!("{}",e)
-
+                    None => MASK_IIR_NO_INT | fifo
                 }
             }
-
-        });
-        Self{uart_regs,interrupt}
+            UART_LCR => self.lcr,
+            UART_MCR => self.mcr,
+            UART_LSR => MASK_LSR_THRE | MASK_LSR_TEMT | if self.has_data() {MASK_LSR_DR} else {0},
+            UART_MSR if self.mcr & MASK_MCR_LOOP != 0 => {
+                // DTR -> DSR, RTS -> CTS, OUT1 -> RI, OUT2 -> DCD
+                let m = self.mcr;
+                (m & 0x1) << 5 | (m & 0x2) << 3 | (m & 0x4) << 4 | (m & 0x8) << 4
+            }
+            UART_MSR => MSR_DEFAULT,
+            UART_SCR => self.scr,
+            _ => 0
+        };
+        Ok(value as u32)
     }
-    pub fn load(&self,addr:u32) -> Result<u32,Exception>{
-        let offset = addr - UART_BASE;
-        let (arr,cond) = &*self.uart_regs;
-        let mut array = arr.lock().unwrap();
+    fn store(&mut self,offset:u32,_size:u32,data:u32) -> Result<(),Exception>{
+        let dlab = self.lcr & MASK_LCR_DLAB != 0;
+        let data = data as u8;
         match offset{
-            UART_RHR => {
-                cond.notify_one();
-                array[UART_LSR as usize] &= !MASK_UART_LSR_RX;
-                Ok(array[UART_RHR as usize] as u32)
+            UART_DLL if dlab => self.divisor = (self.divisor & 0xff00) | data as u16,
+            UART_DLM if dlab => self.divisor = (self.divisor & 0x00ff) | (data as u16) << 8,
+            UART_THR => self.transmit(data),
+            UART_IER => {
+                // enabling the THR-empty interrupt reports the idle transmitter
+                if self.ier & MASK_IER_THRI == 0 && data & MASK_IER_THRI != 0{
+                    self.thre_pending = true;
+                }
+                self.ier = data & 0xf;
             }
-            _ => {
-                Ok(array[offset as usize] as u32)
+            UART_FCR => {
+                self.fifo = data & MASK_FCR_FIFO != 0;
+                if data & MASK_FCR_CLEAR_RX != 0{
+                    self.rx.lock().unwrap().clear();
+                }
             }
+            UART_LCR => self.lcr = data,
+            UART_MCR => self.mcr = data & 0x1f,
+            UART_SCR => self.scr = data,
+            // LSR and MSR are read-only
+            _ => ()
         }
+        Ok(())
+    }
+    fn reset(&mut self){
+        // input the host has already sent stays queued
+        *self = Self{rx:Arc::clone(&self.rx),..Self::new()};
+    }
+    fn is_interrupting(&self) -> bool{
+        self.interrupt_id().is_some()
+    }
+    fn fdt_name(&self) -> Option<(&'static str,&'static str)>{
+        Some(("serial","ns16550a"))
+    }
+    fn fdt_properties(&self,fdt:&mut Fdt){
+        fdt.property_u32("clock-frequency",UART_CLOCK_FREQ);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
 
+    fn read(uart:&mut UartController,offset:u32) -> u8{
+        uart.load(offset,8).unwrap() as u8
     }
-    pub fn store(&mut self,addr: u32,data:u32) {
-        let offset = addr - UART_BASE;
-        let (arr,cond) = &*self.uart_regs;
-        let mut array = arr.lock().unwrap();
-        match offset{
-            UART_THR => {
-                print!("{}",data as u8 as char);
-                io::stdout().flush().unwrap();
-            }
-            _ => {
-                array[offset as usize] = data as u8;
-            }
-        }
+
+    /// A UART in loopback mode, so that transmitted bytes are received.
+    fn looped() -> UartController{
+        let mut uart = UartController::new();
+        uart.store(UART_MCR,8,MASK_MCR_LOOP as u32).unwrap();
+        uart
+    }
+
+    #[test]
+    fn lsr_reports_received_data(){
+        let mut uart = looped();
+        assert_eq!(read(&mut uart,UART_LSR),MASK_LSR_THRE | MASK_LSR_TEMT);
+        uart.store(UART_THR,8,b'a' as u32).unwrap();
+        assert_eq!(read(&mut uart,UART_LSR),MASK_LSR_THRE | MASK_LSR_TEMT | MASK_LSR_DR);
+        assert_eq!(read(&mut uart,UART_RBR),b'a');
+        assert_eq!(read(&mut uart,UART_LSR) & MASK_LSR_DR,0);
+    }
+
+    #[test]
+    fn received_data_interrupts_only_when_enabled(){
+        let mut uart = looped();
+        uart.store(UART_THR,8,b'a' as u32).unwrap();
+        assert!(!uart.is_interrupting());
+        assert_eq!(read(&mut uart,UART_IIR),MASK_IIR_NO_INT);
+        uart.store(UART_IER,8,MASK_IER_RDI as u32).unwrap();
+        assert!(uart.is_interrupting());
+        assert_eq!(read(&mut uart,UART_IIR),IIR_RDI);
+        assert!(uart.is_interrupting(),"reading IIR does not clear RDI");
+        read(&mut uart,UART_RBR);
+        assert!(!uart.is_interrupting(),"draining the receiver does");
+        assert_eq!(read(&mut uart,UART_IIR),MASK_IIR_NO_INT);
     }
-    pub fn is_iterrupt(&self) -> bool{
-        self.interrupt.swap(false, Ordering::Acquire)
+
+    #[test]
+    fn thr_empty_interrupt_is_cleared_by_reading_iir(){
+        let mut uart = looped();
+        uart.store(UART_IER,8,MASK_IER_THRI as u32).unwrap();
+        assert!(uart.is_interrupting(),"enabling it reports the idle transmitter");
+        assert_eq!(read(&mut uart,UART_IIR),IIR_THRI);
+        assert!(!uart.is_interrupting());
+        uart.store(UART_THR,8,b'a' as u32).unwrap();
+        assert!(uart.is_interrupting(),"every transmission raises it again");
+        uart.store(UART_IER,8,0).unwrap();
+        assert!(!uart.is_interrupting());
+    }
+
+    #[test]
+    fn received_data_takes_priority_over_thr_empty(){
+        let mut uart = looped();
+        uart.store(UART_FCR,8,MASK_FCR_FIFO as u32).unwrap();
+        uart.store(UART_IER,8,(MASK_IER_RDI | MASK_IER_THRI) as u32).unwrap();
+        uart.store(UART_THR,8,b'a' as u32).unwrap();
+        assert_eq!(read(&mut uart,UART_IIR),IIR_RDI | MASK_IIR_FIFO);
+        read(&mut uart,UART_RBR);
+        assert_eq!(read(&mut uart,UART_IIR),IIR_THRI | MASK_IIR_FIFO);
+        assert_eq!(read(&mut uart,UART_IIR),MASK_IIR_NO_INT | MASK_IIR_FIFO);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn ier_is_behind_the_divisor_latch(){
+        let mut uart = UartController::new();
+        uart.store(UART_LCR,8,MASK_LCR_DLAB as u32).unwrap();
+        uart.store(UART_DLM,8,0xff).unwrap();
+        assert!(!uart.is_interrupting());
+        uart.store(UART_LCR,8,0).unwrap();
+        assert_eq!(read(&mut uart,UART_IER),0);
+        uart.store(UART_IER,8,0xff).unwrap();
+        assert_eq!(read(&mut uart,UART_IER),0xf);
+    }
+
+    #[test]
+    fn clearing_the_receive_fifo_drops_the_interrupt(){
+        let mut uart = looped();
+        uart.store(UART_IER,8,MASK_IER_RDI as u32).unwrap();
+        uart.store(UART_THR,8,b'a' as u32).unwrap();
+        assert!(uart.is_interrupting());
+        uart.store(UART_FCR,8,(MASK_FCR_FIFO | MASK_FCR_CLEAR_RX) as u32).unwrap();
+        assert!(!uart.is_interrupting());
+        assert_eq!(read(&mut uart,UART_LSR) & MASK_LSR_DR,0);
+    }
+}
@@ -0,0 +1,94 @@
+//! Conformance tests in the style of riscv-arch-test: every `<name>.elf`
+//! under `tests/arch/<suite>` is run with `--signature` and the words it
+//! leaves between `begin_signature` and `end_signature` must match
+//! `<name>.reference_output`. Setting `REMU_ARCH_TESTS` to a directory with
+//! the same layout also runs the tests found there.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Bounds a test that never halts.
+const MAX_INSTRUCTIONS: &str = "1000000";
+
+/// Run every test of `suite`, panicking with a line per failing test.
+fn run_suite(suite:&str){
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/arch").join(suite);
+    let mut tests = 0;
+    let mut failures = Vec::new();
+    run_dir(suite,&dir,&mut tests,&mut failures);
+    assert!(tests > 0,"no tests in {}",dir.display());
+    if let Some(external) = std::env::var_os("REMU_ARCH_TESTS"){
+        let dir = Path::new(&external).join(suite);
+        if dir.is_dir(){
+            run_dir(suite,&dir,&mut tests,&mut failures);
+        }
+    }
+    assert!(failures.is_empty(),"{} of {} tests failed:\n{}",failures.len(),tests,failures.join("\n"));
+}
+
+/// Run the tests in `dir`, counting them and recording what fails.
+fn run_dir(suite:&str,dir:&Path,tests:&mut usize,failures:&mut Vec<String>){
+    for entry in fs::read_dir(dir).unwrap(){
+        let elf = entry.unwrap().path();
+        if elf.extension().is_none_or(|ext| ext != "elf"){
+            continue;
+        }
+        *tests += 1;
+        let name = elf.file_stem().unwrap().to_string_lossy().into_owned();
+        let reference = fs::read_to_string(elf.with_extension("reference_output")).unwrap();
+        let signature = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}-{}.signature",suite,name));
+        let status = Command::new(env!("CARGO_BIN_EXE_remu"))
+            .args(["--no-stdin","--max-insns",MAX_INSTRUCTIONS,"--signature"])
+            .arg(&signature)
+            .arg(&elf)
+            .status()
+            .unwrap();
+        if !status.success(){
+            failures.push(format!("{}: remu failed with {}",name,status));
+            continue;
+        }
+        let words = fs::read_to_string(&signature).unwrap();
+        let (words,expected):(Vec<&str>,Vec<&str>) = (words.lines().collect(),reference.lines().map(str::trim).collect());
+        if let Some(i) = words.iter().zip(&expected).position(|(word,expected)| !word.eq_ignore_ascii_case(expected)){
+            failures.push(format!("{}: signature word {} is {} instead of {}",name,i,words[i],expected[i]));
+        } else if words.len() != expected.len(){
+            failures.push(format!("{}: signature has {} words instead of {}",name,words.len(),expected.len()));
+        }
+    }
+}
+
+#[test]
+fn rv32i(){
+    run_suite("I");
+}
+
+#[test]
+fn rv32m(){
+    run_suite("M");
+}
+
+#[test]
+fn rv32a(){
+    run_suite("A");
+}
+
+#[test]
+fn rv32f(){
+    run_suite("F");
+}
+
+#[test]
+fn rv32d(){
+    run_suite("D");
+}
+
+#[test]
+fn rv32c(){
+    run_suite("C");
+}
+
+#[test]
+fn privilege(){
+    run_suite("privilege");
+}
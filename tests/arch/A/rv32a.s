@@ -0,0 +1,236 @@
+# RV32A: atomic memory operations and load-reserved/store-conditional.
+#
+# Results are appended to the signature; the reference signature holds
+# the values the ISA manual defines for them.
+
+.include "model.inc"
+
+    .globl _start
+_start:
+    RVMODEL_BOOT
+    la a3, amo_word
+    li a1, 0x7fffffff
+    sw a1, 0(a3)
+    li a2, 0x1
+    amoswap.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x80000000
+    sw a1, 0(a3)
+    li a2, 0xffffffff
+    amoswap.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x5
+    sw a1, 0(a3)
+    li a2, 0xfffffffb
+    amoswap.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x7fffffff
+    sw a1, 0(a3)
+    li a2, 0x1
+    amoadd.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x80000000
+    sw a1, 0(a3)
+    li a2, 0xffffffff
+    amoadd.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x5
+    sw a1, 0(a3)
+    li a2, 0xfffffffb
+    amoadd.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x7fffffff
+    sw a1, 0(a3)
+    li a2, 0x1
+    amoxor.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x80000000
+    sw a1, 0(a3)
+    li a2, 0xffffffff
+    amoxor.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x5
+    sw a1, 0(a3)
+    li a2, 0xfffffffb
+    amoxor.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x7fffffff
+    sw a1, 0(a3)
+    li a2, 0x1
+    amoand.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x80000000
+    sw a1, 0(a3)
+    li a2, 0xffffffff
+    amoand.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x5
+    sw a1, 0(a3)
+    li a2, 0xfffffffb
+    amoand.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x7fffffff
+    sw a1, 0(a3)
+    li a2, 0x1
+    amoor.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x80000000
+    sw a1, 0(a3)
+    li a2, 0xffffffff
+    amoor.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x5
+    sw a1, 0(a3)
+    li a2, 0xfffffffb
+    amoor.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x7fffffff
+    sw a1, 0(a3)
+    li a2, 0x1
+    amomin.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x80000000
+    sw a1, 0(a3)
+    li a2, 0xffffffff
+    amomin.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x5
+    sw a1, 0(a3)
+    li a2, 0xfffffffb
+    amomin.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x7fffffff
+    sw a1, 0(a3)
+    li a2, 0x1
+    amomax.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x80000000
+    sw a1, 0(a3)
+    li a2, 0xffffffff
+    amomax.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x5
+    sw a1, 0(a3)
+    li a2, 0xfffffffb
+    amomax.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x7fffffff
+    sw a1, 0(a3)
+    li a2, 0x1
+    amominu.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x80000000
+    sw a1, 0(a3)
+    li a2, 0xffffffff
+    amominu.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x5
+    sw a1, 0(a3)
+    li a2, 0xfffffffb
+    amominu.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x7fffffff
+    sw a1, 0(a3)
+    li a2, 0x1
+    amomaxu.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x80000000
+    sw a1, 0(a3)
+    li a2, 0xffffffff
+    amomaxu.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x5
+    sw a1, 0(a3)
+    li a2, 0xfffffffb
+    amomaxu.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a1, 0x55
+    sw a1, 0(a3)
+    amoadd.w.aqrl a0, a1, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+
+    # a store conditional succeeds once per reservation
+    lr.w a0, (a3)
+    SIG a0
+    li a2, 0x1234
+    sc.w a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    li a2, 0x5678
+    sc.w a0, a2, (a3)
+    snez a0, a0
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    lr.w.aq a0, (a3)
+    li a2, 0x9abc
+    sc.w.rl a0, a2, (a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+
+    RVMODEL_HALT
+    .balign 4
+amo_word:
+    .word 0
+
+RVMODEL_DATA_BEGIN
+    .fill 63, 4, 0xdeadbeef
+RVMODEL_DATA_END
@@ -0,0 +1,120 @@
+# RV32C: compressed arithmetic, loads, stores, branches and jumps.
+#
+# Results are appended to the signature; the reference signature holds
+# the values the ISA manual defines for them.
+
+.include "model.inc"
+
+    .globl _start
+_start:
+    RVMODEL_BOOT
+
+    # immediates
+    c.li a0, -32
+    SIG a0
+    c.lui a0, 0xfffff
+    SIG a0
+    c.lui a0, 1
+    SIG a0
+    c.li a0, 0
+    c.addi a0, -1
+    SIG a0
+
+    # shifts and logic
+    c.li a0, 1
+    c.slli a0, 31
+    SIG a0
+    c.srli a0, 4
+    SIG a0
+    c.slli a0, 4
+    c.srai a0, 4
+    SIG a0
+    li a0, 0x12345678
+    c.andi a0, -16
+    SIG a0
+    li a0, 0x0ff0f0f0
+    li a1, 0x00ff00ff
+    c.mv a2, a0
+    c.add a2, a1
+    SIG a2
+    c.mv a2, a0
+    c.sub a2, a1
+    SIG a2
+    c.mv a2, a0
+    c.xor a2, a1
+    SIG a2
+    c.mv a2, a0
+    c.or a2, a1
+    SIG a2
+    c.mv a2, a0
+    c.and a2, a1
+    SIG a2
+
+    # stack pointer arithmetic
+    la sp, c_stack
+    mv s0, sp
+    c.addi16sp sp, -64
+    c.addi4spn a0, sp, 8
+    sub a0, a0, s0
+    SIG a0
+
+    # loads and stores
+    li a1, 0x11223344
+    c.sw a1, 4(s0)
+    c.lw a0, 4(s0)
+    SIG a0
+    li a1, 0x55667788
+    c.swsp a1, 60(sp)
+    lw a0, -4(s0)
+    SIG a0
+    c.lwsp a2, 68(sp)
+    SIG a2
+
+    # branches and jumps
+    c.li a0, 0
+    c.j 1f
+    c.li a0, 1
+1:
+    SIG a0
+    c.li a0, 0
+    c.li a1, 0
+    c.beqz a1, 1f
+    c.li a0, 1
+1:
+    SIG a0
+    c.li a0, 0
+    c.bnez a1, 1f
+    c.li a0, 1
+1:
+    SIG a0
+2:  c.jal 1f
+1:  la a1, 2b
+    sub a0, ra, a1
+    SIG a0
+    la a1, 1f
+2:  c.jalr a1
+1:  la a1, 2b
+    sub a0, ra, a1
+    SIG a0
+    la a1, 1f
+    c.li a0, 0
+    c.jr a1
+    c.li a0, 1
+1:
+    SIG a0
+
+    # a 32-bit instruction at a halfword boundary
+    c.nop
+    addi a0, zero, 0x123
+    SIG a0
+
+    RVMODEL_HALT
+
+    .balign 16
+    .fill 16, 4, 0
+c_stack:
+    .fill 4, 4, 0
+
+RVMODEL_DATA_BEGIN
+    .fill 24, 4, 0xdeadbeef
+RVMODEL_DATA_END
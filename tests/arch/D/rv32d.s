@@ -0,0 +1,244 @@
+# RV32D: double-precision arithmetic, conversions, comparisons and NaN-boxing.
+#
+# Results are appended to the signature; the reference signature holds
+# the values the ISA manual defines for them.
+
+.include "model.inc"
+
+    .globl _start
+_start:
+    RVMODEL_BOOT
+    la a3, d_data
+
+    # arithmetic
+    fld fa1, 0(a3)
+    fld fa2, 8(a3)
+    fsflags zero
+    fadd.d fa0, fa1, fa2
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+    frflags a0
+    SIG a0
+    fld fa1, 16(a3)
+    fld fa2, 24(a3)
+    fsflags zero
+    fdiv.d fa0, fa1, fa2
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+    frflags a0
+    SIG a0
+    fld fa1, 32(a3)
+    fsflags zero
+    fsqrt.d fa0, fa1
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+    frflags a0
+    SIG a0
+    fld fa1, 40(a3)
+    fsflags zero
+    fsqrt.d fa0, fa1
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+    frflags a0
+    SIG a0
+    fld fa1, 48(a3)
+    fld fa2, 48(a3)
+    fsflags zero
+    fdiv.d fa0, fa1, fa2
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+    frflags a0
+    SIG a0
+    fld fa1, 56(a3)
+    fld fa2, 56(a3)
+    fsflags zero
+    fmul.d fa0, fa1, fa2
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+    frflags a0
+    SIG a0
+    fld fa1, 64(a3)
+    fld fa2, 72(a3)
+    fld fa3, 80(a3)
+    fsflags zero
+    fmadd.d fa0, fa1, fa2, fa3
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+    frflags a0
+    SIG a0
+    fld fa1, 32(a3)
+    fld fa2, 24(a3)
+    fld fa3, 16(a3)
+    fsflags zero
+    fmsub.d fa0, fa1, fa2, fa3
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+    frflags a0
+    SIG a0
+
+    # minimum and maximum
+    fld fa1, 88(a3)
+    fld fa2, 32(a3)
+    fsflags zero
+    fmin.d fa0, fa1, fa2
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+    frflags a0
+    SIG a0
+    fld fa1, 96(a3)
+    fld fa2, 32(a3)
+    fsflags zero
+    fmax.d fa0, fa1, fa2
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+    frflags a0
+    SIG a0
+
+    # conversions
+    fld fa1, 104(a3)
+    fsflags zero
+    fcvt.s.d fa0, fa1
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    fld fa1, 56(a3)
+    fsflags zero
+    fcvt.s.d fa0, fa1
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    fld fa1, 112(a3)
+    fsflags zero
+    fcvt.d.s fa0, fa1
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+    frflags a0
+    SIG a0
+    fld fa1, 120(a3)
+    fsflags zero
+    fcvt.w.d a0, fa1, rne
+    SIG a0
+    frflags a0
+    SIG a0
+    fld fa1, 120(a3)
+    fsflags zero
+    fcvt.w.d a0, fa1, rmm
+    SIG a0
+    frflags a0
+    SIG a0
+    fld fa1, 128(a3)
+    fsflags zero
+    fcvt.wu.d a0, fa1, rtz
+    SIG a0
+    frflags a0
+    SIG a0
+    fld fa1, 136(a3)
+    fsflags zero
+    fcvt.wu.d a0, fa1, rtz
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, -1
+    fcvt.d.w fa0, a1
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+    li a1, -1
+    fcvt.d.wu fa0, a1
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+
+    # classification and comparison
+    fld fa1, 144(a3)
+    fsflags zero
+    fclass.d a0, fa1
+    SIG a0
+    frflags a0
+    SIG a0
+    fld fa1, 96(a3)
+    fsflags zero
+    fclass.d a0, fa1
+    SIG a0
+    frflags a0
+    SIG a0
+    fld fa1, 64(a3)
+    fld fa2, 64(a3)
+    fsflags zero
+    feq.d a0, fa1, fa2
+    SIG a0
+    frflags a0
+    SIG a0
+    fld fa1, 88(a3)
+    fld fa2, 16(a3)
+    fsflags zero
+    flt.d a0, fa1, fa2
+    SIG a0
+    frflags a0
+    SIG a0
+    fld fa1, 152(a3)
+    fld fa2, 48(a3)
+    fsflags zero
+    fle.d a0, fa1, fa2
+    SIG a0
+    frflags a0
+    SIG a0
+
+    # sign injection
+    fld fa1, 16(a3)
+    fld fa2, 16(a3)
+    fsflags zero
+    fsgnjn.d fa0, fa1, fa2
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+    frflags a0
+    SIG a0
+
+    # single-precision operations read only properly NaN-boxed values
+    fld fa1, 16(a3)
+    fsflags zero
+    fadd.s fa0, fa1, fa1
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    fld fa1, 160(a3)
+    fsflags zero
+    fadd.s fa0, fa1, fa1
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x3f800000
+    fmv.w.x fa0, a1
+    fsd fa0, 0(s11)
+    addi s11, s11, 8
+
+    RVMODEL_HALT
+    .balign 8
+d_data:
+    .quad 0x3ff8000000000000
+    .quad 0x4002000000000000
+    .quad 0x3ff0000000000000
+    .quad 0x4008000000000000
+    .quad 0x4000000000000000
+    .quad 0x3fd0000000000000
+    .quad 0x0
+    .quad 0x7e37e43c8800759c
+    .quad 0x3fb999999999999a
+    .quad 0x4024000000000000
+    .quad 0xbff0000000000000
+    .quad 0x7ff8000000000000
+    .quad 0x7ff0000000000001
+    .quad 0x3fd5555555555555
+    .quad 0xffffffff3dcccccd
+    .quad 0xc004000000000000
+    .quad 0x41efffffffe00000
+    .quad 0x41f0000000000000
+    .quad 0x800fffffffffffff
+    .quad 0x8000000000000000
+    .quad 0xffffffff3f800000
+
+RVMODEL_DATA_BEGIN
+    .fill 68, 4, 0xdeadbeef
+RVMODEL_DATA_END
@@ -0,0 +1,452 @@
+# RV32F: single-precision arithmetic, conversions, comparisons and flags.
+#
+# Results are appended to the signature; the reference signature holds
+# the values the ISA manual defines for them.
+
+.include "model.inc"
+
+    .globl _start
+_start:
+    RVMODEL_BOOT
+
+    # arithmetic
+    li a1, 0x3fc00000
+    fmv.w.x fa1, a1
+    li a1, 0x40100000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fadd.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x3f800000
+    fmv.w.x fa1, a1
+    li a1, 0x30800000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fsub.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x40400000
+    fmv.w.x fa1, a1
+    li a1, 0x3dcccccd
+    fmv.w.x fa2, a1
+    fsflags zero
+    fmul.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x3f800000
+    fmv.w.x fa1, a1
+    li a1, 0x40400000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fdiv.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x3f800000
+    fmv.w.x fa1, a1
+    li a1, 0x0
+    fmv.w.x fa2, a1
+    fsflags zero
+    fdiv.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x0
+    fmv.w.x fa1, a1
+    li a1, 0x0
+    fmv.w.x fa2, a1
+    fsflags zero
+    fdiv.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x40000000
+    fmv.w.x fa1, a1
+    fsflags zero
+    fsqrt.s fa0, fa1
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0xbf800000
+    fmv.w.x fa1, a1
+    fsflags zero
+    fsqrt.s fa0, fa1
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x7f000000
+    fmv.w.x fa1, a1
+    li a1, 0x40800000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fmul.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0xd800000
+    fmv.w.x fa1, a1
+    li a1, 0xd800000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fmul.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x40000000
+    fmv.w.x fa1, a1
+    li a1, 0x40400000
+    fmv.w.x fa2, a1
+    li a1, 0x3f800000
+    fmv.w.x fa3, a1
+    fsflags zero
+    fmadd.s fa0, fa1, fa2, fa3
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x40000000
+    fmv.w.x fa1, a1
+    li a1, 0x40400000
+    fmv.w.x fa2, a1
+    li a1, 0x3f800000
+    fmv.w.x fa3, a1
+    fsflags zero
+    fnmsub.s fa0, fa1, fa2, fa3
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+
+    # minimum and maximum
+    li a1, 0x80000000
+    fmv.w.x fa1, a1
+    li a1, 0x0
+    fmv.w.x fa2, a1
+    fsflags zero
+    fmin.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x80000000
+    fmv.w.x fa1, a1
+    li a1, 0x0
+    fmv.w.x fa2, a1
+    fsflags zero
+    fmax.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x7f800001
+    fmv.w.x fa1, a1
+    li a1, 0x3f800000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fmax.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x7fc00000
+    fmv.w.x fa1, a1
+    li a1, 0x7fc00000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fmin.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+
+    # conversions
+    li a1, 0x40600000
+    fmv.w.x fa1, a1
+    fsflags zero
+    fcvt.w.s a0, fa1, rne
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x40600000
+    fmv.w.x fa1, a1
+    fsflags zero
+    fcvt.w.s a0, fa1, rtz
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0xc0600000
+    fmv.w.x fa1, a1
+    fsflags zero
+    fcvt.w.s a0, fa1, rdn
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x501502f9
+    fmv.w.x fa1, a1
+    fsflags zero
+    fcvt.w.s a0, fa1, rtz
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x7fc00000
+    fmv.w.x fa1, a1
+    fsflags zero
+    fcvt.w.s a0, fa1, rtz
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0xbf800000
+    fmv.w.x fa1, a1
+    fsflags zero
+    fcvt.wu.s a0, fa1, rtz
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x4f32d05e
+    fmv.w.x fa1, a1
+    fsflags zero
+    fcvt.wu.s a0, fa1, rtz
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, -7
+    fsflags zero
+    fcvt.s.w fa0, a1
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x7fffffff
+    fsflags zero
+    fcvt.s.w fa0, a1
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, -1
+    fsflags zero
+    fcvt.s.wu fa0, a1
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+
+    # classification and comparison
+    li a1, 0xff800000
+    fmv.w.x fa1, a1
+    fsflags zero
+    fclass.s a0, fa1
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0xbf800000
+    fmv.w.x fa1, a1
+    fsflags zero
+    fclass.s a0, fa1
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x807fffff
+    fmv.w.x fa1, a1
+    fsflags zero
+    fclass.s a0, fa1
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x80000000
+    fmv.w.x fa1, a1
+    fsflags zero
+    fclass.s a0, fa1
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x0
+    fmv.w.x fa1, a1
+    fsflags zero
+    fclass.s a0, fa1
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x1
+    fmv.w.x fa1, a1
+    fsflags zero
+    fclass.s a0, fa1
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x3f800000
+    fmv.w.x fa1, a1
+    fsflags zero
+    fclass.s a0, fa1
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x7f800000
+    fmv.w.x fa1, a1
+    fsflags zero
+    fclass.s a0, fa1
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x7f800001
+    fmv.w.x fa1, a1
+    fsflags zero
+    fclass.s a0, fa1
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x7fc00000
+    fmv.w.x fa1, a1
+    fsflags zero
+    fclass.s a0, fa1
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x7fc00000
+    fmv.w.x fa1, a1
+    li a1, 0x7fc00000
+    fmv.w.x fa2, a1
+    fsflags zero
+    feq.s a0, fa1, fa2
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x7f800001
+    fmv.w.x fa1, a1
+    li a1, 0x3f800000
+    fmv.w.x fa2, a1
+    fsflags zero
+    feq.s a0, fa1, fa2
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x7fc00000
+    fmv.w.x fa1, a1
+    li a1, 0x3f800000
+    fmv.w.x fa2, a1
+    fsflags zero
+    flt.s a0, fa1, fa2
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x3f800000
+    fmv.w.x fa1, a1
+    li a1, 0x3f800000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fle.s a0, fa1, fa2
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x80000000
+    fmv.w.x fa1, a1
+    li a1, 0x0
+    fmv.w.x fa2, a1
+    fsflags zero
+    flt.s a0, fa1, fa2
+    SIG a0
+    frflags a0
+    SIG a0
+
+    # sign injection
+    li a1, 0x3f800000
+    fmv.w.x fa1, a1
+    li a1, 0xc0000000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fsgnj.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0x3f800000
+    fmv.w.x fa1, a1
+    li a1, 0xc0000000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fsgnjn.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    li a1, 0xbf800000
+    fmv.w.x fa1, a1
+    li a1, 0xc0000000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fsgnjx.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+
+    # dynamic rounding modes
+    fsrmi 2
+    li a1, 0x3f800000
+    fmv.w.x fa1, a1
+    li a1, 0x30800000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fadd.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    fsrmi 3
+    li a1, 0x3f800000
+    fmv.w.x fa1, a1
+    li a1, 0x30800000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fadd.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    fsrmi 1
+    li a1, 0xbf800000
+    fmv.w.x fa1, a1
+    li a1, 0x30800000
+    fmv.w.x fa2, a1
+    fsflags zero
+    fsub.s fa0, fa1, fa2
+    fmv.x.w a0, fa0
+    SIG a0
+    frflags a0
+    SIG a0
+    fsrmi 0
+    frrm a0
+    SIG a0
+
+    # loads and stores
+    la a3, f_data
+    flw fa0, 0(a3)
+    fsw fa0, 4(a3)
+    lw a0, 4(a3)
+    SIG a0
+    frcsr a0
+    SIG a0
+
+    RVMODEL_HALT
+    .balign 4
+f_data:
+    .word 0x40490fdb
+    .word 0
+
+RVMODEL_DATA_BEGIN
+    .fill 97, 4, 0xdeadbeef
+RVMODEL_DATA_END
@@ -0,0 +1,990 @@
+# RV32I: arithmetic, logic, shifts, loads, stores, branches and jumps.
+#
+# Results are appended to the signature; the reference signature holds
+# the values the ISA manual defines for them.
+
+.include "model.inc"
+
+    .globl _start
+_start:
+    RVMODEL_BOOT
+
+    # register-register
+    li a1, 0x0
+    li a2, 0x0
+    add a0, a1, a2
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    add a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x1
+    add a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    add a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0xfedcba98
+    add a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x21
+    add a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x1f
+    add a0, a1, a2
+    SIG a0
+    li a1, 0x5555aaaa
+    li a2, 0x4
+    add a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    sub a0, a1, a2
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    sub a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x1
+    sub a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    sub a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0xfedcba98
+    sub a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x21
+    sub a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x1f
+    sub a0, a1, a2
+    SIG a0
+    li a1, 0x5555aaaa
+    li a2, 0x4
+    sub a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    sll a0, a1, a2
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    sll a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x1
+    sll a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    sll a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0xfedcba98
+    sll a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x21
+    sll a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x1f
+    sll a0, a1, a2
+    SIG a0
+    li a1, 0x5555aaaa
+    li a2, 0x4
+    sll a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    slt a0, a1, a2
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    slt a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x1
+    slt a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    slt a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0xfedcba98
+    slt a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x21
+    slt a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x1f
+    slt a0, a1, a2
+    SIG a0
+    li a1, 0x5555aaaa
+    li a2, 0x4
+    slt a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    sltu a0, a1, a2
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    sltu a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x1
+    sltu a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    sltu a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0xfedcba98
+    sltu a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x21
+    sltu a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x1f
+    sltu a0, a1, a2
+    SIG a0
+    li a1, 0x5555aaaa
+    li a2, 0x4
+    sltu a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    xor a0, a1, a2
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    xor a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x1
+    xor a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    xor a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0xfedcba98
+    xor a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x21
+    xor a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x1f
+    xor a0, a1, a2
+    SIG a0
+    li a1, 0x5555aaaa
+    li a2, 0x4
+    xor a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    srl a0, a1, a2
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    srl a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x1
+    srl a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    srl a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0xfedcba98
+    srl a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x21
+    srl a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x1f
+    srl a0, a1, a2
+    SIG a0
+    li a1, 0x5555aaaa
+    li a2, 0x4
+    srl a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    sra a0, a1, a2
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    sra a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x1
+    sra a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    sra a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0xfedcba98
+    sra a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x21
+    sra a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x1f
+    sra a0, a1, a2
+    SIG a0
+    li a1, 0x5555aaaa
+    li a2, 0x4
+    sra a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    or a0, a1, a2
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    or a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x1
+    or a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    or a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0xfedcba98
+    or a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x21
+    or a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x1f
+    or a0, a1, a2
+    SIG a0
+    li a1, 0x5555aaaa
+    li a2, 0x4
+    or a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    and a0, a1, a2
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    and a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x1
+    and a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    and a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0xfedcba98
+    and a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x21
+    and a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x1f
+    and a0, a1, a2
+    SIG a0
+    li a1, 0x5555aaaa
+    li a2, 0x4
+    and a0, a1, a2
+    SIG a0
+
+    # register-immediate
+    li a1, 0x7fffffff
+    addi a0, a1, -1
+    SIG a0
+    li a1, 0x7fffffff
+    addi a0, a1, 2047
+    SIG a0
+    li a1, 0x7fffffff
+    addi a0, a1, -2048
+    SIG a0
+    li a1, 0x7fffffff
+    addi a0, a1, 1
+    SIG a0
+    li a1, 0x80000000
+    addi a0, a1, -1
+    SIG a0
+    li a1, 0x80000000
+    addi a0, a1, 2047
+    SIG a0
+    li a1, 0x80000000
+    addi a0, a1, -2048
+    SIG a0
+    li a1, 0x80000000
+    addi a0, a1, 1
+    SIG a0
+    li a1, 0x0
+    addi a0, a1, -1
+    SIG a0
+    li a1, 0x0
+    addi a0, a1, 2047
+    SIG a0
+    li a1, 0x0
+    addi a0, a1, -2048
+    SIG a0
+    li a1, 0x0
+    addi a0, a1, 1
+    SIG a0
+    li a1, 0xfffff800
+    addi a0, a1, -1
+    SIG a0
+    li a1, 0xfffff800
+    addi a0, a1, 2047
+    SIG a0
+    li a1, 0xfffff800
+    addi a0, a1, -2048
+    SIG a0
+    li a1, 0xfffff800
+    addi a0, a1, 1
+    SIG a0
+    li a1, 0x7fffffff
+    slti a0, a1, -1
+    SIG a0
+    li a1, 0x7fffffff
+    slti a0, a1, 2047
+    SIG a0
+    li a1, 0x7fffffff
+    slti a0, a1, -2048
+    SIG a0
+    li a1, 0x7fffffff
+    slti a0, a1, 1
+    SIG a0
+    li a1, 0x80000000
+    slti a0, a1, -1
+    SIG a0
+    li a1, 0x80000000
+    slti a0, a1, 2047
+    SIG a0
+    li a1, 0x80000000
+    slti a0, a1, -2048
+    SIG a0
+    li a1, 0x80000000
+    slti a0, a1, 1
+    SIG a0
+    li a1, 0x0
+    slti a0, a1, -1
+    SIG a0
+    li a1, 0x0
+    slti a0, a1, 2047
+    SIG a0
+    li a1, 0x0
+    slti a0, a1, -2048
+    SIG a0
+    li a1, 0x0
+    slti a0, a1, 1
+    SIG a0
+    li a1, 0xfffff800
+    slti a0, a1, -1
+    SIG a0
+    li a1, 0xfffff800
+    slti a0, a1, 2047
+    SIG a0
+    li a1, 0xfffff800
+    slti a0, a1, -2048
+    SIG a0
+    li a1, 0xfffff800
+    slti a0, a1, 1
+    SIG a0
+    li a1, 0x7fffffff
+    sltiu a0, a1, -1
+    SIG a0
+    li a1, 0x7fffffff
+    sltiu a0, a1, 2047
+    SIG a0
+    li a1, 0x7fffffff
+    sltiu a0, a1, -2048
+    SIG a0
+    li a1, 0x7fffffff
+    sltiu a0, a1, 1
+    SIG a0
+    li a1, 0x80000000
+    sltiu a0, a1, -1
+    SIG a0
+    li a1, 0x80000000
+    sltiu a0, a1, 2047
+    SIG a0
+    li a1, 0x80000000
+    sltiu a0, a1, -2048
+    SIG a0
+    li a1, 0x80000000
+    sltiu a0, a1, 1
+    SIG a0
+    li a1, 0x0
+    sltiu a0, a1, -1
+    SIG a0
+    li a1, 0x0
+    sltiu a0, a1, 2047
+    SIG a0
+    li a1, 0x0
+    sltiu a0, a1, -2048
+    SIG a0
+    li a1, 0x0
+    sltiu a0, a1, 1
+    SIG a0
+    li a1, 0xfffff800
+    sltiu a0, a1, -1
+    SIG a0
+    li a1, 0xfffff800
+    sltiu a0, a1, 2047
+    SIG a0
+    li a1, 0xfffff800
+    sltiu a0, a1, -2048
+    SIG a0
+    li a1, 0xfffff800
+    sltiu a0, a1, 1
+    SIG a0
+    li a1, 0x7fffffff
+    xori a0, a1, -1
+    SIG a0
+    li a1, 0x7fffffff
+    xori a0, a1, 2047
+    SIG a0
+    li a1, 0x7fffffff
+    xori a0, a1, -2048
+    SIG a0
+    li a1, 0x7fffffff
+    xori a0, a1, 1
+    SIG a0
+    li a1, 0x80000000
+    xori a0, a1, -1
+    SIG a0
+    li a1, 0x80000000
+    xori a0, a1, 2047
+    SIG a0
+    li a1, 0x80000000
+    xori a0, a1, -2048
+    SIG a0
+    li a1, 0x80000000
+    xori a0, a1, 1
+    SIG a0
+    li a1, 0x0
+    xori a0, a1, -1
+    SIG a0
+    li a1, 0x0
+    xori a0, a1, 2047
+    SIG a0
+    li a1, 0x0
+    xori a0, a1, -2048
+    SIG a0
+    li a1, 0x0
+    xori a0, a1, 1
+    SIG a0
+    li a1, 0xfffff800
+    xori a0, a1, -1
+    SIG a0
+    li a1, 0xfffff800
+    xori a0, a1, 2047
+    SIG a0
+    li a1, 0xfffff800
+    xori a0, a1, -2048
+    SIG a0
+    li a1, 0xfffff800
+    xori a0, a1, 1
+    SIG a0
+    li a1, 0x7fffffff
+    ori a0, a1, -1
+    SIG a0
+    li a1, 0x7fffffff
+    ori a0, a1, 2047
+    SIG a0
+    li a1, 0x7fffffff
+    ori a0, a1, -2048
+    SIG a0
+    li a1, 0x7fffffff
+    ori a0, a1, 1
+    SIG a0
+    li a1, 0x80000000
+    ori a0, a1, -1
+    SIG a0
+    li a1, 0x80000000
+    ori a0, a1, 2047
+    SIG a0
+    li a1, 0x80000000
+    ori a0, a1, -2048
+    SIG a0
+    li a1, 0x80000000
+    ori a0, a1, 1
+    SIG a0
+    li a1, 0x0
+    ori a0, a1, -1
+    SIG a0
+    li a1, 0x0
+    ori a0, a1, 2047
+    SIG a0
+    li a1, 0x0
+    ori a0, a1, -2048
+    SIG a0
+    li a1, 0x0
+    ori a0, a1, 1
+    SIG a0
+    li a1, 0xfffff800
+    ori a0, a1, -1
+    SIG a0
+    li a1, 0xfffff800
+    ori a0, a1, 2047
+    SIG a0
+    li a1, 0xfffff800
+    ori a0, a1, -2048
+    SIG a0
+    li a1, 0xfffff800
+    ori a0, a1, 1
+    SIG a0
+    li a1, 0x7fffffff
+    andi a0, a1, -1
+    SIG a0
+    li a1, 0x7fffffff
+    andi a0, a1, 2047
+    SIG a0
+    li a1, 0x7fffffff
+    andi a0, a1, -2048
+    SIG a0
+    li a1, 0x7fffffff
+    andi a0, a1, 1
+    SIG a0
+    li a1, 0x80000000
+    andi a0, a1, -1
+    SIG a0
+    li a1, 0x80000000
+    andi a0, a1, 2047
+    SIG a0
+    li a1, 0x80000000
+    andi a0, a1, -2048
+    SIG a0
+    li a1, 0x80000000
+    andi a0, a1, 1
+    SIG a0
+    li a1, 0x0
+    andi a0, a1, -1
+    SIG a0
+    li a1, 0x0
+    andi a0, a1, 2047
+    SIG a0
+    li a1, 0x0
+    andi a0, a1, -2048
+    SIG a0
+    li a1, 0x0
+    andi a0, a1, 1
+    SIG a0
+    li a1, 0xfffff800
+    andi a0, a1, -1
+    SIG a0
+    li a1, 0xfffff800
+    andi a0, a1, 2047
+    SIG a0
+    li a1, 0xfffff800
+    andi a0, a1, -2048
+    SIG a0
+    li a1, 0xfffff800
+    andi a0, a1, 1
+    SIG a0
+    li a1, 0x80000001
+    slli a0, a1, 0
+    SIG a0
+    li a1, 0x80000001
+    slli a0, a1, 1
+    SIG a0
+    li a1, 0x80000001
+    slli a0, a1, 16
+    SIG a0
+    li a1, 0x80000001
+    slli a0, a1, 31
+    SIG a0
+    li a1, 0x7ff0000f
+    slli a0, a1, 0
+    SIG a0
+    li a1, 0x7ff0000f
+    slli a0, a1, 1
+    SIG a0
+    li a1, 0x7ff0000f
+    slli a0, a1, 16
+    SIG a0
+    li a1, 0x7ff0000f
+    slli a0, a1, 31
+    SIG a0
+    li a1, 0x80000001
+    srli a0, a1, 0
+    SIG a0
+    li a1, 0x80000001
+    srli a0, a1, 1
+    SIG a0
+    li a1, 0x80000001
+    srli a0, a1, 16
+    SIG a0
+    li a1, 0x80000001
+    srli a0, a1, 31
+    SIG a0
+    li a1, 0x7ff0000f
+    srli a0, a1, 0
+    SIG a0
+    li a1, 0x7ff0000f
+    srli a0, a1, 1
+    SIG a0
+    li a1, 0x7ff0000f
+    srli a0, a1, 16
+    SIG a0
+    li a1, 0x7ff0000f
+    srli a0, a1, 31
+    SIG a0
+    li a1, 0x80000001
+    srai a0, a1, 0
+    SIG a0
+    li a1, 0x80000001
+    srai a0, a1, 1
+    SIG a0
+    li a1, 0x80000001
+    srai a0, a1, 16
+    SIG a0
+    li a1, 0x80000001
+    srai a0, a1, 31
+    SIG a0
+    li a1, 0x7ff0000f
+    srai a0, a1, 0
+    SIG a0
+    li a1, 0x7ff0000f
+    srai a0, a1, 1
+    SIG a0
+    li a1, 0x7ff0000f
+    srai a0, a1, 16
+    SIG a0
+    li a1, 0x7ff0000f
+    srai a0, a1, 31
+    SIG a0
+
+    # upper immediates
+    lui a0, 0xfffff
+    SIG a0
+    lui a0, 0x80000
+    SIG a0
+1:  auipc a0, 0x12345
+    la a1, 1b
+    sub a0, a0, a1
+    SIG a0
+    addi x0, x0, 5
+    SIG x0
+
+    # loads
+    la a3, load_data
+    lb a0, 0(a3)
+    SIG a0
+    lbu a0, 0(a3)
+    SIG a0
+    lb a0, 1(a3)
+    SIG a0
+    lbu a0, 1(a3)
+    SIG a0
+    lb a0, 2(a3)
+    SIG a0
+    lbu a0, 2(a3)
+    SIG a0
+    lb a0, 3(a3)
+    SIG a0
+    lbu a0, 3(a3)
+    SIG a0
+    lh a0, 0(a3)
+    SIG a0
+    lhu a0, 0(a3)
+    SIG a0
+    lh a0, 2(a3)
+    SIG a0
+    lhu a0, 2(a3)
+    SIG a0
+    lw a0, 0(a3)
+    SIG a0
+    lw a0, -4(a3)
+    SIG a0
+
+    # stores of bytes and halves into the signature
+    li a0, 0x11
+    sb a0, 1(s11)
+    addi s11, s11, 4
+    li a0, 0x12345678
+    sb a0, 3(s11)
+    addi s11, s11, 4
+    li a0, 0x2233
+    sh a0, 2(s11)
+    addi s11, s11, 4
+    li a0, 0x12345678
+    sh a0, 0(s11)
+    addi s11, s11, 4
+
+    # branches: 0 if taken, 1 if not
+    li a1, 0x1
+    li a2, 0x1
+    li a0, 0
+    beq a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0x2
+    li a0, 0
+    beq a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x1
+    li a0, 0
+    beq a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0xffffffff
+    li a0, 0
+    beq a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x7fffffff
+    li a0, 0
+    beq a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    li a0, 0
+    bne a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0x2
+    li a0, 0
+    bne a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x1
+    li a0, 0
+    bne a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0xffffffff
+    li a0, 0
+    bne a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x7fffffff
+    li a0, 0
+    bne a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    li a0, 0
+    blt a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0x2
+    li a0, 0
+    blt a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x1
+    li a0, 0
+    blt a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0xffffffff
+    li a0, 0
+    blt a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x7fffffff
+    li a0, 0
+    blt a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    li a0, 0
+    bge a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0x2
+    li a0, 0
+    bge a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x1
+    li a0, 0
+    bge a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0xffffffff
+    li a0, 0
+    bge a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x7fffffff
+    li a0, 0
+    bge a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    li a0, 0
+    bltu a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0x2
+    li a0, 0
+    bltu a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x1
+    li a0, 0
+    bltu a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0xffffffff
+    li a0, 0
+    bltu a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x7fffffff
+    li a0, 0
+    bltu a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0x1
+    li a0, 0
+    bgeu a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0x2
+    li a0, 0
+    bgeu a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0x1
+    li a0, 0
+    bgeu a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x1
+    li a2, 0xffffffff
+    li a0, 0
+    bgeu a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x7fffffff
+    li a0, 0
+    bgeu a1, a2, 1f
+    li a0, 1
+1:
+    SIG a0
+
+    # jumps: the link register holds the address after the jump
+    jal a0, 1f
+2:  li a0, 0
+1:  la a1, 2b
+    sub a0, a0, a1
+    SIG a0
+    la a1, 3f
+2:  jalr a0, 1(a1)
+    j fail
+3:  la a1, 2b
+    sub a0, a0, a1
+    SIG a0
+    j 1f
+fail:
+    li a0, -1
+    SIG a0
+1:
+
+    RVMODEL_HALT
+    .word 0x01020304
+load_data:
+    .word 0x8081f2f3
+
+RVMODEL_DATA_BEGIN
+    .fill 254, 4, 0xdeadbeef
+RVMODEL_DATA_END
@@ -0,0 +1,304 @@
+# RV32M: multiplication and division, including division by zero and overflow.
+#
+# Results are appended to the signature; the reference signature holds
+# the values the ISA manual defines for them.
+
+.include "model.inc"
+
+    .globl _start
+_start:
+    RVMODEL_BOOT
+    li a1, 0x0
+    li a2, 0x0
+    mul a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0x0
+    mul a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    mul a0, a1, a2
+    SIG a0
+    li a1, 0xfffffff9
+    li a2, 0x2
+    mul a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0xfffffffe
+    mul a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0xffffffff
+    mul a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0x9abcdef0
+    mul a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x80000000
+    mul a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x7fffffff
+    mul a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    mulh a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0x0
+    mulh a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    mulh a0, a1, a2
+    SIG a0
+    li a1, 0xfffffff9
+    li a2, 0x2
+    mulh a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0xfffffffe
+    mulh a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0xffffffff
+    mulh a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0x9abcdef0
+    mulh a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x80000000
+    mulh a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x7fffffff
+    mulh a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    mulhsu a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0x0
+    mulhsu a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    mulhsu a0, a1, a2
+    SIG a0
+    li a1, 0xfffffff9
+    li a2, 0x2
+    mulhsu a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0xfffffffe
+    mulhsu a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0xffffffff
+    mulhsu a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0x9abcdef0
+    mulhsu a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x80000000
+    mulhsu a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x7fffffff
+    mulhsu a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    mulhu a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0x0
+    mulhu a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    mulhu a0, a1, a2
+    SIG a0
+    li a1, 0xfffffff9
+    li a2, 0x2
+    mulhu a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0xfffffffe
+    mulhu a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0xffffffff
+    mulhu a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0x9abcdef0
+    mulhu a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x80000000
+    mulhu a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x7fffffff
+    mulhu a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    div a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0x0
+    div a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    div a0, a1, a2
+    SIG a0
+    li a1, 0xfffffff9
+    li a2, 0x2
+    div a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0xfffffffe
+    div a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0xffffffff
+    div a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0x9abcdef0
+    div a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x80000000
+    div a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x7fffffff
+    div a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    divu a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0x0
+    divu a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    divu a0, a1, a2
+    SIG a0
+    li a1, 0xfffffff9
+    li a2, 0x2
+    divu a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0xfffffffe
+    divu a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0xffffffff
+    divu a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0x9abcdef0
+    divu a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x80000000
+    divu a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x7fffffff
+    divu a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    rem a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0x0
+    rem a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    rem a0, a1, a2
+    SIG a0
+    li a1, 0xfffffff9
+    li a2, 0x2
+    rem a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0xfffffffe
+    rem a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0xffffffff
+    rem a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0x9abcdef0
+    rem a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x80000000
+    rem a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x7fffffff
+    rem a0, a1, a2
+    SIG a0
+    li a1, 0x0
+    li a2, 0x0
+    remu a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0x0
+    remu a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0xffffffff
+    remu a0, a1, a2
+    SIG a0
+    li a1, 0xfffffff9
+    li a2, 0x2
+    remu a0, a1, a2
+    SIG a0
+    li a1, 0x7
+    li a2, 0xfffffffe
+    remu a0, a1, a2
+    SIG a0
+    li a1, 0xffffffff
+    li a2, 0xffffffff
+    remu a0, a1, a2
+    SIG a0
+    li a1, 0x12345678
+    li a2, 0x9abcdef0
+    remu a0, a1, a2
+    SIG a0
+    li a1, 0x80000000
+    li a2, 0x80000000
+    remu a0, a1, a2
+    SIG a0
+    li a1, 0x7fffffff
+    li a2, 0x7fffffff
+    remu a0, a1, a2
+    SIG a0
+
+    RVMODEL_HALT
+
+RVMODEL_DATA_BEGIN
+    .fill 72, 4, 0xdeadbeef
+RVMODEL_DATA_END
@@ -0,0 +1,22 @@
+#!/bin/sh
+# Rebuild the test ELFs from their sources with llvm-mc. Each directory is
+# a suite assembled for the extensions it tests; the reference signatures
+# next to the sources are maintained by hand.
+set -e
+cd "$(dirname "$0")"
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+for src in */*.s; do
+    case "$(dirname "$src")" in
+        C) mattr=+c ;;
+        M) mattr=+m ;;
+        A | privilege) mattr=+a ;;
+        F) mattr=+f ;;
+        D) mattr=+f,+d ;;
+        *) mattr= ;;
+    esac
+    llvm-mc -triple=riscv32 -mattr="${mattr:+$mattr,}-relax" -I . -filetype=obj -o "$tmp/test.o" "$src"
+    llvm-objcopy -O binary -j .text "$tmp/test.o" "$tmp/test.bin"
+    symbols=$(llvm-nm "$tmp/test.o" | awk '$3 ~ /^(tohost|fromhost|begin_signature|end_signature)$/ {print $3 "=0x" $1}')
+    python3 mkelf.py "${src%.s}.elf" "$tmp/test.bin" 0x80000000 $symbols
+done
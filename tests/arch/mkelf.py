@@ -0,0 +1,36 @@
+#!/usr/bin/env python3
+# Wrap the flat code of a test in an ELF32 executable with one loadable
+# segment and a symbol table, so that no RISC-V linker is needed.
+#
+# usage: mkelf.py <out.elf> <code.bin> <base> <name>=<offset>...
+
+import struct
+import sys
+
+out, code, base = sys.argv[1], open(sys.argv[2], 'rb').read(), int(sys.argv[3], 0)
+symbols = [(name, base + int(offset, 0)) for name, offset in (arg.split('=') for arg in sys.argv[4:])]
+
+strtab, symtab = b'\0', b'\0' * 16
+for name, value in symbols:
+    symtab += struct.pack('<IIIBBH', len(strtab), value, 0, 0x10, 0, 1)
+    strtab += name.encode() + b'\0'
+shstrtab = b'\0.text\0.symtab\0.strtab\0.shstrtab\0'
+
+code_off = 52 + 32
+sym_off = code_off + len(code)
+str_off = sym_off + len(symtab)
+shstr_off = str_off + len(strtab)
+sh_off = (shstr_off + len(shstrtab) + 3) & ~3
+
+header = b'\x7fELF\1\1\1' + b'\0' * 9
+header += struct.pack('<HHIIIIIHHHHHH', 2, 243, 1, base, 52, sh_off, 0, 52, 32, 1, 40, 5, 4)
+segment = struct.pack('<IIIIIIII', 1, code_off, base, base, len(code), len(code), 7, 4)
+sections = b'\0' * 40
+sections += struct.pack('<IIIIIIIIII', 1, 1, 6, base, code_off, len(code), 0, 0, 4, 0)
+sections += struct.pack('<IIIIIIIIII', 7, 2, 0, 0, sym_off, len(symtab), 3, 1, 4, 16)
+sections += struct.pack('<IIIIIIIIII', 15, 3, 0, 0, str_off, len(strtab), 0, 0, 1, 0)
+sections += struct.pack('<IIIIIIIIII', 23, 3, 0, 0, shstr_off, len(shstrtab), 0, 0, 1, 0)
+
+image = header + segment + code + symtab + strtab + shstrtab
+image += b'\0' * (sh_off - len(image)) + sections
+open(out, 'wb').write(image)
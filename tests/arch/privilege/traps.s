@@ -0,0 +1,173 @@
+# Privileged architecture: synchronous traps, xRET, delegation and CSR
+# write rules.
+#
+# Every trap appends its cause, its epc relative to a5 and its tval
+# relative to a4 to the signature. The M-mode handler then skips the
+# trapping instruction, or continues in M-mode at a6 if it is set.
+#
+# Results are appended to the signature; the reference signature holds
+# the values the ISA manual defines for them. Where the manual leaves
+# tval to the implementation, remu's choice is recorded: the faulting
+# instruction for illegal instructions and the pc for breakpoints.
+
+.include "model.inc"
+
+    .globl _start
+_start:
+    RVMODEL_BOOT
+    la t0, mtrap
+    csrw mtvec, t0
+    li a6, 0
+
+    # exceptions in M-mode
+    li a4, 0
+    la a5, 1f
+1:  ecall
+    la a4, 1f
+    la a5, 1f
+1:  ebreak
+    li a4, 0xf1151073
+    la a5, 1f
+1:  csrw mvendorid, a0
+    li a4, 0
+    la a5, 1f
+1:  lw a0, 0(zero)
+    la a4, amo_word + 2
+    la a5, 1f
+1:  amoadd.w a0, a0, (a4)
+    la a5, 1f
+1:  lr.w a0, (a4)
+
+    # U-mode, with every address accessible
+    li t0, -1
+    csrw pmpaddr0, t0
+    li t0, 0x1f
+    csrw pmpcfg0, t0
+    li t0, 0x1800
+    csrc mstatus, t0
+    la t0, user
+    csrw mepc, t0
+    mret
+user:
+    li a4, 0
+    la a5, 1f
+1:  ecall
+    li a4, 0x30002573
+    la a5, 1f
+1:  csrr a0, mstatus
+    li a4, 0
+    la a6, back
+    la a5, 1f
+1:  ecall
+back:
+    # mstatus.MPP is U after a trap from U-mode
+    csrr t1, mstatus
+    srli t1, t1, 11
+    andi t1, t1, 3
+    SIG t1
+
+    # delegate U-mode ecalls to S-mode
+    li t0, 0x100
+    csrw medeleg, t0
+    csrr t1, medeleg
+    SIG t1
+    la t0, strap
+    csrw stvec, t0
+    li t0, 0x1800
+    csrc mstatus, t0
+    la t0, user2
+    csrw mepc, t0
+    mret
+user2:
+    la a5, 1f
+1:  ecall
+back2:
+    # mstatus.MPP is S after a trap from S-mode
+    csrr t1, mstatus
+    srli t1, t1, 11
+    andi t1, t1, 3
+    SIG t1
+    csrw medeleg, zero
+
+    # mret sets MIE from MPIE, sets MPIE and leaves MPP at U
+    li t0, 0x1888
+    csrc mstatus, t0
+    li t0, 0x1880
+    csrs mstatus, t0
+    la t0, 1f
+    csrw mepc, t0
+    mret
+1:  csrr t1, mstatus
+    csrci mstatus, 8
+    li t0, 0x1888
+    and t1, t1, t0
+    SIG t1
+
+    # WARL fields and CSR instruction semantics
+    li t0, 0x80000003
+    csrw mepc, t0
+    csrr t1, mepc
+    SIG t1
+    li t0, 0x80000002
+    csrrw t2, mtvec, t0
+    csrr t1, mtvec
+    csrw mtvec, t2
+    SIG t1
+    li t0, 0x55
+    csrw mscratch, t0
+    li t0, 0xaa
+    csrrw t1, mscratch, t0
+    SIG t1
+    csrrsi t1, mscratch, 5
+    SIG t1
+    csrrci t1, mscratch, 0xf
+    SIG t1
+    csrr t1, mscratch
+    SIG t1
+
+    RVMODEL_HALT
+
+    .balign 4
+mtrap:
+    csrr t1, mcause
+    SIG t1
+    csrr t1, mepc
+    sub t1, t1, a5
+    SIG t1
+    csrr t1, mtval
+    sub t1, t1, a4
+    SIG t1
+    bnez a6, 1f
+    csrr t1, mepc
+    addi t1, t1, 4
+    csrw mepc, t1
+    mret
+1:  mv t1, a6
+    li a6, 0
+    jr t1
+
+    .balign 4
+strap:
+    csrr t1, scause
+    SIG t1
+    csrr t1, sepc
+    sub t1, t1, a5
+    SIG t1
+    # sstatus.SPP is U after a trap from U-mode
+    csrr t1, sstatus
+    srli t1, t1, 8
+    andi t1, t1, 1
+    SIG t1
+    li a4, 0
+    la a6, back2
+    la a5, 1f
+1:  ecall
+
+    .balign 4
+amo_word:
+    .word 0
+    .word 0
+
+RVMODEL_DATA_BEGIN
+    .fill 43, 4, 0xdeadbeef
+RVMODEL_DATA_END
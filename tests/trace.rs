@@ -0,0 +1,53 @@
+//! The trace written with `--trace-file`, checked on the conformance programs.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Run `test` under `tests/arch` with the trace options `args` and return the trace.
+fn trace(test:&str,name:&str,args:&[&str]) -> String{
+    let file = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.trace",name));
+    let status = Command::new(env!("CARGO_BIN_EXE_remu"))
+        .args(["--no-stdin","--max-insns","100000","--trace-file"])
+        .arg(&file)
+        .args(args)
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/arch").join(test))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fs::read_to_string(&file).unwrap()
+}
+
+#[test]
+fn commit_log_matches_spike(){
+    let log = trace("C/rv32c.elf","commits",&["--trace","commits"]);
+    let lines:Vec<&str> = log.lines().take(5).collect();
+    assert_eq!(lines,[
+        "core   0: 3 0x80000000 (0x00000d97) x27 0x80000000",
+        "core   0: 3 0x80000004 (0x1d0d8d93) x27 0x800001d0",
+        "core   0: 3 0x80000008 (0x5501) x10 0xffffffe0",
+        "core   0: 3 0x8000000a (0x00ada023) mem 0x800001d0 0xffffffe0",
+        "core   0: 3 0x8000000e (0x0d91) x27 0x800001d4"
+    ]);
+}
+
+#[test]
+fn instructions_are_disassembled(){
+    let log = trace("A/rv32a.elf","insns",&["--trace","insns","--trace-pc","0x80000014:0x80000024"]);
+    let lines:Vec<&str> = log.lines().collect();
+    assert_eq!(&lines[..4],[
+        "core   0: M 0x80000014 (0xfff58593) addi a1, a1, -1",
+        "core   0: M 0x80000018 (0x00b6a023) sw a1, 0(a3)",
+        "core   0: M 0x8000001c (0x00100613) addi a2, zero, 1",
+        "core   0: M 0x80000020 (0x08c6a52f) amoswap.w a0, a2, (a3)"
+    ]);
+}
+
+#[test]
+fn modes_filter_the_trace(){
+    let log = trace("privilege/traps.elf","modes",&["--trace","insns,traps","--trace-mode","u"]);
+    assert!(!log.is_empty());
+    assert!(log.lines().all(|line| line.starts_with("core   0: U ")));
+    assert!(log.contains("exception 8 tval 0x00000000 -> M"));
+    assert!(log.contains("exception 8 tval 0x00000000 -> S"));
+}